@@ -12,6 +12,7 @@ android {
     defaultConfig {
         minSdk = 21
         testInstrumentationRunner = "androidx.test.runner.AndroidJUnitRunner"
+        consumerProguardFiles("consumer-rules.pro")
     }
 
     buildTypes {
@@ -47,6 +48,12 @@ cargo {
     libname = "liveview_native_core"
     // In case you need to run the unit tests, install the respective toolchain and add the target below.
     targets = listOf("arm", "arm64", "x86", "x86_64")
+    // The natives of NativeChecks, which only the unit tests declare. Release builds leave them out.
+    if (project.hasProperty("nativeChecks")) {
+        features {
+            defaultAnd(arrayOf("native-checks"))
+        }
+    }
 }
 
 // Running cargo command before build
@@ -0,0 +1,45 @@
+# Classes and members the native library looks up by name, see
+# jni_bindings/src/cache.rs. Keep in sync with it.
+
+# Natives are registered by name, see jni_bindings/src/natives.rs. Classes declaring them are
+# kept even if the app never uses them, since registration fails if any is missing.
+-keepclasseswithmembers,includedescriptorclasses class org.phoenixframework.liveview.lib.** {
+    native <methods>;
+}
+
+-keep class org.phoenixframework.liveview.lib.Document
+
+-keep class org.phoenixframework.liveview.lib.Attribute {
+    private long nativeObject;
+}
+
+-keep class org.phoenixframework.liveview.lib.Document$Companion$Handler {
+    private byte ffiFailurePolicy();
+    private void ffiOnHandle(long, byte, int, int, int, int, org.phoenixframework.liveview.lib.ChangeDetails, int);
+    private void ffiOnComponents(long, int[], int[]);
+}
+
+-keep class org.phoenixframework.liveview.lib.Document$Companion$BatchHandler {
+    private void ffiOnBatch(long, int[], org.phoenixframework.liveview.lib.ChangeDetails[]);
+}
+
+-keep class org.phoenixframework.liveview.lib.ChangeDetails {
+    <init>(org.phoenixframework.liveview.lib.AttributeChange[], java.lang.String, java.lang.String);
+}
+
+-keep class org.phoenixframework.liveview.lib.AttributeChange {
+    <init>(byte, java.lang.String, java.lang.String, java.lang.String, java.lang.String);
+}
+
+-keep class org.phoenixframework.liveview.lib.ChannelEvent {
+    private static org.phoenixframework.liveview.lib.ChannelEvent ffiNew(byte, java.lang.String, java.lang.String, java.lang.String, java.lang.String, java.lang.String, java.lang.String, java.lang.String, java.lang.String, java.lang.String);
+}
+
+-keep class org.phoenixframework.liveview.lib.JoinParams {
+    <init>(java.lang.String, java.lang.String, boolean, java.lang.String, java.lang.String);
+}
+
+# Thrown from native code
+-keep class org.phoenixframework.liveview.lib.*Exception {
+    <init>(...);
+}
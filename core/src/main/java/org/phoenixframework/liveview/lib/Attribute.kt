@@ -3,20 +3,36 @@ package org.phoenixframework.liveview.lib
 class Attribute internal constructor(private var nativeObject: Long) {
 
     val name: String
-        get() = get_name(nativeObject)
+        get() = nameSymbol.let {
+            if (it == Symbols.NOT_INTERNED) get_name_string(nativeObject) else Symbols.name(it)
+        }
 
     /** The namespace of an attribute */
     val namespace: String
+        get() = namespaceSymbol.let {
+            if (it == Symbols.NOT_INTERNED) get_namespace_string(nativeObject) else Symbols.name(it)
+        }
+
+    /** The interned ID of [name], or [Symbols.NOT_INTERNED], see [Symbols] */
+    val nameSymbol: Int
+        get() = get_name(nativeObject)
+
+    /** The interned ID of [namespace], or [Symbols.NOT_INTERNED], see [Symbols] */
+    val namespaceSymbol: Int
         get() = get_namespace(nativeObject)
 
     val value: String
         get() = get_value(nativeObject)
 
-    private external fun get_name(pointer: Long): String
+    private external fun get_name(pointer: Long): Int
+
+    private external fun get_name_string(pointer: Long): String
 
     private external fun get_value(pointer: Long): String
 
-    private external fun get_namespace(pointer: Long): String
+    private external fun get_namespace(pointer: Long): Int
+
+    private external fun get_namespace_string(pointer: Long): String
 
     override fun toString(): String =
         "Attribute {\n" +
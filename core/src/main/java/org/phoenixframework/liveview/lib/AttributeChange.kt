@@ -0,0 +1,20 @@
+package org.phoenixframework.liveview.lib
+
+/** An attribute that a merge added, removed, or gave a new value */
+class AttributeChange internal constructor(
+    kind: Byte,
+    val namespace: String?,
+    val name: String,
+    /** The value before the merge, or null if the attribute was added or had no value */
+    val oldValue: String?,
+    /** The value after the merge, or null if the attribute was removed or has no value */
+    val newValue: String?
+) {
+    enum class Kind {
+        Added,
+        Removed,
+        Modified
+    }
+
+    val kind: Kind = Kind.values()[kind.toInt()]
+}
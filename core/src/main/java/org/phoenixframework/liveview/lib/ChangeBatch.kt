@@ -0,0 +1,49 @@
+package org.phoenixframework.liveview.lib
+
+/**
+ * Every change made by a single merge, in the order the patches were applied.
+ *
+ * Changes are packed into one `IntArray` of `[changeType, nodeRef, parent, index,
+ * previousSibling, oldIndex]` records, with -1 for anything absent, so that a merge crosses JNI once no
+ * matter how many changes it makes.
+ */
+class ChangeBatch internal constructor(
+    private val records: IntArray,
+    private val details: Array<ChangeDetails?>
+) {
+    /** The number of changes */
+    val size: Int
+        get() = records.size / RECORD_LEN
+
+    fun changeType(index: Int): Document.Companion.ChangeType =
+        Document.Companion.ChangeType.values()[records[index * RECORD_LEN]]
+
+    fun nodeRef(index: Int): NodeRef = NodeRef(records[index * RECORD_LEN + 1])
+
+    /** The parent of the node, or null for a `Change` */
+    fun parent(index: Int): NodeRef? =
+        records[index * RECORD_LEN + 2].let { if (it < 0) null else NodeRef(it) }
+
+    /**
+     * For an `Add`, `Replace` or `Move`, the position of the node among the children of its
+     * parent in the merged document, or -1
+     */
+    fun childIndex(index: Int): Int = records[index * RECORD_LEN + 3]
+
+    /** For an `Add`, `Replace` or `Move`, the child just before the node in the merged document */
+    fun previousSibling(index: Int): NodeRef? =
+        records[index * RECORD_LEN + 4].let { if (it < 0) null else NodeRef(it) }
+
+    /**
+     * For a `Move`, the position of the node among the children of its parent before the
+     * merge, or -1
+     */
+    fun oldIndex(index: Int): Int = records[index * RECORD_LEN + 5]
+
+    /** For a `Change`, what changed about the node */
+    fun details(index: Int): ChangeDetails? = details[index]
+
+    private companion object {
+        const val RECORD_LEN = 6
+    }
+}
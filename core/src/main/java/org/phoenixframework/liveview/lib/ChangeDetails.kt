@@ -0,0 +1,22 @@
+package org.phoenixframework.liveview.lib
+
+/**
+ * What a `Change` made by a merge changed about a node: its attributes if it is an element,
+ * or its text if it is a leaf
+ */
+class ChangeDetails internal constructor(
+    val attributes: Array<AttributeChange>,
+    /** The text of a leaf before the merge, or null for an element */
+    val oldText: String?,
+    /** The text of a leaf after the merge, or null for an element */
+    val newText: String?
+) {
+    val added: List<AttributeChange>
+        get() = attributes.filter { it.kind == AttributeChange.Kind.Added }
+
+    val removed: List<AttributeChange>
+        get() = attributes.filter { it.kind == AttributeChange.Kind.Removed }
+
+    val modified: List<AttributeChange>
+        get() = attributes.filter { it.kind == AttributeChange.Kind.Modified }
+}
@@ -0,0 +1,119 @@
+package org.phoenixframework.liveview.lib
+
+/**
+ * A Phoenix channel message, as decoded by [Document.handleChannelMessage]
+ *
+ * @property joinRef the ref of the join the message belongs to, if any
+ * @property ref the ref of the push this message replies to, if any
+ */
+sealed class ChannelEvent(val joinRef: String?, val ref: String?, val topic: String) {
+
+    /** A join reply, whose rendering was merged into the document */
+    class Rendered internal constructor(joinRef: String?, ref: String?, topic: String) :
+        ChannelEvent(joinRef, ref, topic)
+
+    /** A `diff` push, which was merged into the document */
+    class Diff internal constructor(joinRef: String?, ref: String?, topic: String) :
+        ChannelEvent(joinRef, ref, topic)
+
+    /**
+     * Any other reply, e.g. to an event push, or to a join that failed. A `diff` in the response
+     * of an `ok` reply was merged into the document before this is returned.
+     *
+     * @property status `ok` or `error`
+     * @property response the JSON of the response, without the merged `diff`
+     */
+    class Reply internal constructor(
+        joinRef: String?,
+        ref: String?,
+        topic: String,
+        val status: String,
+        val response: String
+    ) : ChannelEvent(joinRef, ref, topic)
+
+    /** The channel crashed on the server, `phx_error` */
+    class Error internal constructor(joinRef: String?, ref: String?, topic: String) :
+        ChannelEvent(joinRef, ref, topic)
+
+    /** The channel was closed by the server, `phx_close` */
+    class Close internal constructor(joinRef: String?, ref: String?, topic: String) :
+        ChannelEvent(joinRef, ref, topic)
+
+    /** A `redirect` to a URL outside of the LiveView */
+    class Redirect internal constructor(
+        joinRef: String?,
+        ref: String?,
+        topic: String,
+        val to: String,
+        val flash: String?
+    ) : ChannelEvent(joinRef, ref, topic)
+
+    /**
+     * A `live_redirect` to another LiveView
+     *
+     * @property kind `push` or `replace`, if given
+     */
+    class LiveRedirect internal constructor(
+        joinRef: String?,
+        ref: String?,
+        topic: String,
+        val to: String,
+        val kind: String?,
+        val flash: String?
+    ) : ChannelEvent(joinRef, ref, topic)
+
+    /**
+     * A `live_patch` of the current LiveView
+     *
+     * @property kind `push` or `replace`, if given
+     */
+    class LivePatch internal constructor(
+        joinRef: String?,
+        ref: String?,
+        topic: String,
+        val to: String,
+        val kind: String?
+    ) : ChannelEvent(joinRef, ref, topic)
+
+    /**
+     * An event the bindings do not interpret, e.g. from `push_event`
+     *
+     * @property payload the JSON of the payload
+     */
+    class Other internal constructor(
+        joinRef: String?,
+        ref: String?,
+        topic: String,
+        val event: String,
+        val payload: String
+    ) : ChannelEvent(joinRef, ref, topic)
+
+    private companion object {
+        /** Called from `jni_bindings/src/channel.rs`, with a `type` matching its `Event` */
+        @JvmStatic
+        private fun ffiNew(
+            type: Byte,
+            joinRef: String?,
+            ref: String?,
+            topic: String,
+            event: String,
+            status: String?,
+            to: String?,
+            kind: String?,
+            flash: String?,
+            payload: String?
+        ): ChannelEvent =
+            when (type.toInt()) {
+                0 -> Rendered(joinRef, ref, topic)
+                1 -> Diff(joinRef, ref, topic)
+                2 -> Reply(joinRef, ref, topic, status!!, payload!!)
+                3 -> Error(joinRef, ref, topic)
+                4 -> Close(joinRef, ref, topic)
+                5 -> Redirect(joinRef, ref, topic, to!!, flash)
+                6 -> LiveRedirect(joinRef, ref, topic, to!!, kind, flash)
+                7 -> LivePatch(joinRef, ref, topic, to!!, kind)
+                8 -> Other(joinRef, ref, topic, event, payload!!)
+                else -> throw IllegalStateException("Unknown channel event type $type")
+            }
+    }
+}
@@ -0,0 +1,84 @@
+package org.phoenixframework.liveview.lib
+
+/**
+ * Base class of the failures reported by the native LiveView core
+ *
+ * @property kind the variant of the underlying core error, e.g. `Syntax` or `TemplateNotFound`
+ */
+sealed class CoreException(val kind: String, message: String) : RuntimeException(message)
+
+/**
+ * Thrown when markup can not be parsed into a `Document`.
+ *
+ * The parser does not report where it failed, so the location below is a guess: the first
+ * structural problem found by rescanning the source, such as an unclosed tag or a mismatched
+ * end tag. The message says so, along with what was found there.
+ *
+ * @property line the 1-based line of the likely failure, or 0 if none was found
+ * @property column the 1-based column of the likely failure, or 0 if none was found
+ * @property offset the 0-based byte offset of the likely failure, or -1 if none was found
+ * @property excerpt the source line at [line], followed by a line with a `^` marker under
+ * [column], or an empty string if none was found
+ */
+class ParseException(
+    kind: String,
+    message: String,
+    val line: Int,
+    val column: Int,
+    val offset: Int,
+    val excerpt: String
+) : CoreException(kind, message)
+
+/**
+ * Thrown when a fragment diff is not valid JSON, or does not have the shape of a diff
+ *
+ * @property jsonPath the path to the offending value, e.g. `0.d[3]`, or `.` for the whole diff
+ * @property line the 1-based line of the offending JSON, or 0 if unknown, as for valid JSON that
+ * does not have the shape of a diff
+ * @property column the 1-based column of the offending JSON, or 0 if unknown
+ */
+class FragmentDecodeException(
+    kind: String,
+    message: String,
+    val jsonPath: String,
+    val line: Int,
+    val column: Int
+) : CoreException(kind, message)
+
+/** Thrown when a decoded fragment diff can not be merged into the document's fragment template */
+class FragmentMergeException(kind: String, message: String) : CoreException(kind, message)
+
+/** Thrown when the merged fragment template can not be rendered to markup */
+class RenderException(kind: String, message: String) : CoreException(kind, message)
+
+/**
+ * Thrown when a Phoenix channel message is not valid JSON, or not a
+ * `[join_ref, ref, topic, event, payload]` array
+ */
+class ChannelDecodeException(kind: String, message: String) : CoreException(kind, message)
+
+/**
+ * Thrown when the payload of an event push can not be built, e.g. because no element is
+ * bound to the event, or `phx-target` matches nothing
+ */
+class EventException(kind: String, message: String) : CoreException(kind, message)
+
+/**
+ * Thrown when a binding attribute is not valid JSON, or holds `JS` commands that are unknown
+ * or malformed. [kind] is `UnknownCommand` for a command this library does not know.
+ */
+class JsCommandException(kind: String, message: String) : CoreException(kind, message)
+
+/**
+ * Thrown when the dead render of a LiveView page lacks a parameter needed to join its channel:
+ * the LiveView container, its `data-phx-session`, `data-phx-static` or `id`, or the CSRF token
+ */
+class DeadRenderException(kind: String, message: String) : CoreException(kind, message)
+
+/**
+ * Thrown when a patch can not be applied to the document during a merge
+ *
+ * @property patchIndex the position of the failing patch in the merge
+ */
+class PatchApplyException(kind: String, message: String, val patchIndex: Int) :
+    CoreException(kind, message)
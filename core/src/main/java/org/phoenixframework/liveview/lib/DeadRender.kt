@@ -0,0 +1,44 @@
+package org.phoenixframework.liveview.lib
+
+/**
+ * The page served over HTTP before the client joins the LiveView channel, parsed together with
+ * the parameters of the join.
+ */
+class DeadRender private constructor(
+    /** The initial document, to render until the join reply arrives */
+    val document: Document,
+    val joinParams: JoinParams
+) {
+    companion object {
+        /**
+         * Parses the body of the HTTP response for a LiveView page
+         *
+         * @throws ParseException if the body is malformed
+         * @throws DeadRenderException if a join parameter is missing from it
+         */
+        @Throws(ParseException::class, DeadRenderException::class)
+        fun parse(body: String): DeadRender {
+            val document = Document.parse(body)
+            return DeadRender(document, document.joinParams())
+        }
+    }
+}
+
+/**
+ * What the client needs to join the LiveView channel, found in the dead render
+ *
+ * @property session the signed `data-phx-session` of the LiveView container
+ * @property staticToken the signed `data-phx-static` of the LiveView container
+ * @property main whether the container is marked `data-phx-main`, rather than being the first
+ * LiveView found
+ * @property containerId the DOM id of the LiveView container, which names the channel topic
+ * `lv:<containerId>`
+ * @property csrfToken the token to send as `_csrf_token` when connecting the socket
+ */
+data class JoinParams internal constructor(
+    val session: String,
+    val staticToken: String,
+    val main: Boolean,
+    val containerId: String,
+    val csrfToken: String
+)
@@ -1,5 +1,6 @@
 package org.phoenixframework.liveview.lib
 import android.util.Log;
+import java.nio.ByteBuffer
 
 
 class Document {
@@ -11,29 +12,67 @@ class Document {
         asBorrow = false
     }
 
-    internal constructor(pointer: Long, borrowed: Boolean = true ) {
-        nativeObject = pointer
+    internal constructor(handle: Long, borrowed: Boolean = true ) {
+        nativeObject = handle
         asBorrow = borrowed
     }
 
+    /** The native handle, for the `NativeChecks` object of the tests */
+    internal val handle: Long
+        get() = nativeObject
+
     companion object {
         init {
-            System.loadLibrary("liveview_native_core")
+            NativeLibrary.load()
         }
 
         /**
          * Parses a `Document` from a string
          *
-         * @throws Exception if the document is malformed
+         * @throws ParseException if the document is malformed
          */
-        @Throws
-        fun parse(string: String): Document {
-            val result = JavaResult(do_parse(string))
-            return result.document ?: throw Exception(result.error)
-        }
+        @Throws(ParseException::class)
+        fun parse(string: String): Document = Document(do_parse(string), false)
+
+        /**
+         * Parses a `Document` from `length` bytes of UTF-8 at `offset`, without decoding them to
+         * a `String` first
+         *
+         * @throws ParseException if the document is malformed or not valid UTF-8
+         */
+        @Throws(ParseException::class)
+        fun parse(bytes: ByteArray, offset: Int = 0, length: Int = bytes.size - offset): Document =
+            Document(do_parse_bytes(bytes, offset, length), false)
+
+        /**
+         * Parses a `Document` from the remaining UTF-8 bytes of `buffer`, leaving its position
+         * unchanged. A direct buffer is read in place.
+         *
+         * @throws ParseException if the document is malformed or not valid UTF-8
+         */
+        @Throws(ParseException::class)
+        fun parse(buffer: ByteBuffer): Document =
+            if (buffer.isDirect) {
+                Document(do_parse_buffer(buffer, buffer.position(), buffer.remaining()), false)
+            } else {
+                buffer.withArray { bytes, offset, length -> parse(bytes, offset, length) }
+            }
+
+        /** Calls `f` with the remaining bytes of a heap `ByteBuffer`, copying them if read-only */
+        private inline fun <T> ByteBuffer.withArray(f: (ByteArray, Int, Int) -> T): T =
+            if (hasArray()) {
+                f(array(), arrayOffset() + position(), remaining())
+            } else {
+                val bytes = ByteArray(remaining()).also { duplicate().get(it) }
+                f(bytes, 0, bytes.size)
+            }
 
         private external fun do_parse(text: String): Long
 
+        private external fun do_parse_bytes(text: ByteArray, offset: Int, length: Int): Long
+
+        private external fun do_parse_buffer(text: ByteBuffer, offset: Int, length: Int): Long
+
         /** Output logs from the Rust side into android's logcat */
         private external fun initialize_log()
 
@@ -41,27 +80,169 @@ class Document {
             Change,
             Add,
             Remove,
-            Replace
+            Replace,
+            /**
+             * A keyed node was moved among the children of its parent, see
+             * [Document.setKeyAttribute]
+             */
+            Move
+        }
+
+        /** What a merge does when its [Handler] throws */
+        enum class FailurePolicy {
+            /** Keep the merged document, and skip the remaining notifications */
+            SkipRemaining,
+            /** Restore the document to its state before the merge */
+            Rollback
         }
 
-        open class Handler {
-            private fun ffiOnHandle(context: Long, changeType: Byte, nodeRef: Int, parent: Int) {
-                onHandle(
-                    Document(context, true),
-                    ChangeType.values()[changeType.toInt()],
-                    NodeRef(nodeRef),
-                    if (parent == 0) null else NodeRef(parent))
+        /**
+         * Receives the changes made by a merge.
+         *
+         * An exception thrown from [onHandle] stops the merge and is rethrown,
+         * unchanged, to the caller of `merge`/`mergeFragmentJson`.
+         *
+         * A handler must not merge into the document it is notified about: such a
+         * merge throws an `IllegalStateException`. Merge once the handler has returned.
+         * Until then, a merge into the same document from another thread throws too.
+         */
+        open class Handler(val failurePolicy: FailurePolicy = FailurePolicy.SkipRemaining) {
+            private fun ffiFailurePolicy(): Byte = failurePolicy.ordinal.toByte()
+
+            private fun ffiOnHandle(
+                context: Long,
+                changeType: Byte,
+                nodeRef: Int,
+                parent: Int,
+                index: Int,
+                previousSibling: Int,
+                details: ChangeDetails?,
+                oldIndex: Int
+            ) {
+                val type = ChangeType.values()[changeType.toInt()]
+                val previous = if (previousSibling < 0) null else NodeRef(previousSibling)
+                if (type == ChangeType.Move) {
+                    onMove(
+                        Document(context, true),
+                        NodeRef(nodeRef),
+                        NodeRef(parent),
+                        oldIndex,
+                        index,
+                        previous)
+                } else {
+                    onHandle(
+                        Document(context, true),
+                        type,
+                        NodeRef(nodeRef),
+                        if (parent < 0) null else NodeRef(parent),
+                        index,
+                        previous,
+                        details)
+                }
+            }
+
+            private fun ffiOnComponents(context: Long, rendered: IntArray, destroyed: IntArray) {
+                onComponents(Document(context, true), rendered, destroyed)
             }
 
+            /**
+             * Called after the changes of a fragment diff that touched live components.
+             *
+             * `rendered` are the cids of the components the diff re-rendered, and `destroyed`
+             * those no longer rendered anywhere. As the LiveView client does, push
+             * `cids_will_destroy` and then `cids_destroyed` for them, and call
+             * [Document.pruneComponents] with the cids in the server's reply.
+             */
+            open fun onComponents(context: Document, rendered: IntArray, destroyed: IntArray) {}
+
+            /**
+             * Called for a keyed node that moved from `oldIndex` to `newIndex` among the
+             * children of `parent`. `nodeRef` is the node that holds the key now, which may
+             * have replaced the one that held it before, see [Document.setKeyAttribute].
+             *
+             * Calls [onHandle] with [ChangeType.Move] and `newIndex` by default.
+             */
+            open fun onMove(
+                context: Document,
+                nodeRef: NodeRef,
+                parent: NodeRef,
+                oldIndex: Int,
+                newIndex: Int,
+                previousSibling: NodeRef?
+            ) = onHandle(context, ChangeType.Move, nodeRef, parent, newIndex, previousSibling, null)
+
+            /**
+             * Called once per change, after every patch has been applied, so
+             * `context` is always the fully merged document.
+             *
+             * `parent` is null for a `Change`. The root, whose ref is 0, is reported as the
+             * parent of its children like any other node.
+             */
             open fun onHandle(
                 context: Document,
                 changeType: ChangeType,
                 nodeRef: NodeRef,
                 parent: NodeRef?
             ) {}
+
+            /**
+             * Like the overload above, with the position of an added or replacing node:
+             * `index` is its position among the children of `parent`, and
+             * `previousSibling` the child just before it, both in the merged document.
+             * `index` is -1 and `previousSibling` null for other changes, or if the node
+             * was detached again by a later patch.
+             *
+             * Calls the overload above by default.
+             */
+            open fun onHandle(
+                context: Document,
+                changeType: ChangeType,
+                nodeRef: NodeRef,
+                parent: NodeRef?,
+                index: Int,
+                previousSibling: NodeRef?
+            ) = onHandle(context, changeType, nodeRef, parent)
+
+            /**
+             * Like the overload above, with what a `Change` changed about the node in
+             * `details`, which is null for other changes. Unless the handler may roll the
+             * merge back, or a key attribute is set, details are only collected for nodes
+             * the merge pairs up by position, and may be null for a `Change` of another node.
+             *
+             * Calls the overload above by default.
+             */
+            open fun onHandle(
+                context: Document,
+                changeType: ChangeType,
+                nodeRef: NodeRef,
+                parent: NodeRef?,
+                index: Int,
+                previousSibling: NodeRef?,
+                details: ChangeDetails?
+            ) = onHandle(context, changeType, nodeRef, parent, index, previousSibling)
+        }
+
+        /**
+         * A [Handler] that receives every change made by a merge in a single call to
+         * [onChanges], instead of one call to [onHandle] per change.
+         */
+        open class BatchHandler(
+            failurePolicy: FailurePolicy = FailurePolicy.SkipRemaining
+        ) : Handler(failurePolicy) {
+            private fun ffiOnBatch(
+                context: Long,
+                records: IntArray,
+                details: Array<ChangeDetails?>
+            ) {
+                onChanges(Document(context, true), ChangeBatch(records, details))
+            }
+
+            /** Called once per merge, after every patch has been applied */
+            open fun onChanges(context: Document, changes: ChangeBatch) {}
         }
     }
 
+    /** @throws InvalidNodeRefException if `nodeRef` is not attached to this document */
     fun getNodeString(nodeRef: NodeRef): String = node_to_string(nativeObject, nodeRef.ref)
 
     /**
@@ -72,32 +253,68 @@ class Document {
     val rootNodeRef
         get() = run { NodeRef(root(nativeObject)) }
 
-    /** Returns the data associated with the given `NodeRef` */
+    /**
+     * Returns the data associated with the given `NodeRef`
+     *
+     * @throws InvalidNodeRefException if `nodeRef` is not attached to this document
+     */
     fun getNode(nodeRef: NodeRef): Node {
-        val nodePtr = get_node(nativeObject, nodeRef.ref)
+        val nodeHandle = get_node(nativeObject, nodeRef.ref)
         // construct node
-        return when (val nodeType = get_node_type(nodePtr)) {
-            0.toByte() -> {
-                Node.Root
-            }
-            1.toByte() -> {
-                val elementPtr = get_node_element(nodePtr)
-                Node.Element(elementPtr)
+        try {
+            return when (val nodeType = get_node_type(nodeHandle)) {
+                0.toByte() -> {
+                    Node.Root
+                }
+                1.toByte() -> {
+                    val elementHandle = get_node_element(nodeHandle)
+                    Node.Element(elementHandle)
+                }
+                2.toByte() -> {
+                    Node.Leaf(get_node_leaf_string(nativeObject, nodeRef.ref))
+                }
+                else -> throw Exception("Unknown node type ${nodeType}")
             }
-            2.toByte() -> {
-                Node.Leaf(get_node_leaf_string(nativeObject, nodeRef.ref))
-            }
-            else -> throw Exception("Unknown node type ${nodeType}")
+        } finally {
+            drop_node(nodeHandle)
         }
     }
 
-    /** Returns the children of `node` as a string */
+    /**
+     * Returns the children of `node` as a string
+     *
+     * @throws InvalidNodeRefException if `nodeRef` is not attached to this document
+     */
     fun getChildren(nodeRef: NodeRef) = get_children(nativeObject, nodeRef.ref).map { NodeRef(it) }
 
-    /** Returns the parent of `node`, if it has one */
+    /**
+     * Returns the parent of `node`, if it has one
+     *
+     * @throws InvalidNodeRefException if `nodeRef` is not attached to this document
+     */
     fun getParent(nodeRef: NodeRef) =
         get_parent(nativeObject, nodeRef.ref).let { if (it < 0) null else NodeRef(it) }
 
+    /**
+     * Copies `nodeRef` and its whole subtree out of the document in a single native call, instead
+     * of one call per node and attribute with [getChildren] and [getNode].
+     *
+     * @throws InvalidNodeRefException if `nodeRef` is not attached to this document
+     */
+    fun snapshot(nodeRef: NodeRef = rootNodeRef): SnapshotNode =
+        SnapshotNode.decode(snapshot(nativeObject, nodeRef.ref))
+
+    /**
+     * Diffs `other` against this document and applies the patches.
+     *
+     * Either every patch is applied, or the document is left unchanged. Only a handler with
+     * [FailurePolicy.Rollback], or a key attribute, has the document copied before patching:
+     * without either, a patch that can not be applied leaves the document unusable, and any
+     * later call on it throws an `IllegalStateException`.
+     *
+     * @throws PatchApplyException if a patch can not be applied
+     */
+    @Throws(PatchApplyException::class)
     fun merge(other: Document, handler: Handler) {
         merge(nativeObject, other.nativeObject, handler)
     }
@@ -106,18 +323,185 @@ class Document {
      * Deserializes the json, renders it, parses it and then diffs it against
      * the current document.
      *
-     * @throws Exception if the this is not deserializable, not renderable or
-     * if the rendering is unparsable.
+     * A diff that only changes dynamic text or attribute values skips the
+     * render, parse and diff, and updates the affected nodes directly.
+     *
+     * The merge is all-or-nothing: if any step fails, the document and its
+     * fragment template are left exactly as they were before the call.
+     *
+     * @throws FragmentDecodeException if the json is not a valid fragment diff
+     * @throws FragmentMergeException if the diff can not be merged into the fragment template
+     * @throws RenderException if the merged fragment template can not be rendered
+     * @throws ParseException if the rendering is unparsable
+     * @throws PatchApplyException if the resulting patches can not be applied
      */
-    @Throws
+    @Throws(CoreException::class)
     fun mergeFragmentJson(other_json: String, handler: Handler) {
         merge_fragment_json(nativeObject, other_json, handler)
     }
 
+    /**
+     * Like [mergeFragmentJson], with the json as `length` bytes of UTF-8 at `offset`, decoded
+     * without a `String` in between
+     */
+    @Throws(CoreException::class)
+    fun mergeFragmentJson(
+        otherJson: ByteArray,
+        handler: Handler,
+        offset: Int = 0,
+        length: Int = otherJson.size - offset
+    ) {
+        merge_fragment_json_bytes(nativeObject, otherJson, offset, length, handler)
+    }
+
+    /**
+     * Like [mergeFragmentJson], with the json as the remaining UTF-8 bytes of `otherJson`. The
+     * position of the buffer is left unchanged, and a direct buffer is read in place.
+     */
+    @Throws(CoreException::class)
+    fun mergeFragmentJson(otherJson: ByteBuffer, handler: Handler) {
+        if (otherJson.isDirect) {
+            merge_fragment_json_buffer(
+                nativeObject, otherJson, otherJson.position(), otherJson.remaining(), handler)
+        } else {
+            otherJson.withArray { bytes, offset, length ->
+                mergeFragmentJson(bytes, handler, offset, length)
+            }
+        }
+    }
+
+    /**
+     * Decodes a Phoenix channel message, `[join_ref, ref, topic, event, payload]`, and merges
+     * what it carries: the rendering of a join reply replaces the fragment template, while a
+     * `diff` push or a reply carrying a diff is merged like [mergeFragmentJson].
+     *
+     * @return the decoded message, once what it carries is merged. A reply carrying a diff is
+     * still returned as a [ChannelEvent.Reply], with the rest of its response
+     * @throws ChannelDecodeException if the message is not a channel message
+     * @throws CoreException if what the message carries can not be merged, see [mergeFragmentJson]
+     */
+    @Throws(CoreException::class)
+    fun handleChannelMessage(message: String, handler: Handler): ChannelEvent =
+        handle_channel_message(nativeObject, message, handler)
+
+    /**
+     * Like [handleChannelMessage], with the message as `length` bytes of UTF-8 at `offset`
+     */
+    @Throws(CoreException::class)
+    fun handleChannelMessage(
+        message: ByteArray,
+        handler: Handler,
+        offset: Int = 0,
+        length: Int = message.size - offset
+    ): ChannelEvent = handle_channel_message_bytes(nativeObject, message, offset, length, handler)
+
+    /**
+     * Returns the cid of the live component that rendered `nodeRef`, or null if it was rendered
+     * by the LiveView itself
+     *
+     * @throws InvalidNodeRefException if `nodeRef` is not attached to this document
+     * @throws IllegalStateException if the document does not match the rendered LiveView around
+     * `nodeRef`, so that the component can not be told
+     */
+    fun componentOf(nodeRef: NodeRef): Int? =
+        component_of(nativeObject, nodeRef.ref).let { if (it < 0) null else it }
+
+    /**
+     * Drops the state of live components the server acknowledged as destroyed, see
+     * [Companion.Handler.onComponents]
+     */
+    fun pruneComponents(cids: IntArray) {
+        prune_components(nativeObject, cids)
+    }
+
+    /**
+     * Builds the payloads of the `event` pushes the LiveView client sends when `binding`,
+     * e.g. `click` for `phx-click`, is triggered on `nodeRef`, or on a node it contains.
+     *
+     * Returns one JSON payload per target: a single one, unless `phx-target` is a selector
+     * matching elements of several live components. `submit` and `change` send the fields of
+     * the enclosing `LiveForm` URL-encoded in `value`.
+     *
+     * @throws EventException if no element at or above `nodeRef` is bound to the event, or
+     * `phx-target` is a selector that is not supported, matches nothing, or matches an element
+     * whose live component can not be told
+     * @throws InvalidNodeRefException if `nodeRef` is not attached to this document
+     */
+    fun eventPayloads(nodeRef: NodeRef, binding: String): List<String> =
+        event_payloads(nativeObject, nodeRef.ref, binding).asList()
+
+    /**
+     * The parameters of the LiveView channel join, when this is the dead render of a
+     * LiveView page, see [DeadRender.parse]
+     *
+     * @throws DeadRenderException if any of them is missing
+     */
+    fun joinParams(): JoinParams = join_params(nativeObject)
+
+    /**
+     * Enables move detection, with `name` as the attribute identifying keyed siblings, e.g.
+     * `id`. When a merge removes a keyed element and adds an element with the same tag and key
+     * under the same parent, the pair is reported as a single [Companion.ChangeType.Move] of the
+     * added element, which may be a new node: the merge rebuilds reordered children rather than
+     * moving them. An element with the same key but another tag, or a key added back under
+     * another parent, is still reported as a `Remove` and an `Add`.
+     *
+     * Move detection is disabled by default, and again with `null`.
+     */
+    fun setKeyAttribute(name: String?) {
+        set_key_attribute(nativeObject, name)
+    }
+
+    private external fun set_key_attribute(doc: Long, name: String?)
+
+    private external fun component_of(doc: Long, nodeRef: Int): Int
+
+    private external fun prune_components(doc: Long, cids: IntArray)
+
+    private external fun join_params(doc: Long): JoinParams
+
+    private external fun event_payloads(
+        doc: Long,
+        nodeRef: Int,
+        binding: String
+    ): Array<String>
+
+    private external fun handle_channel_message(
+        doc: Long,
+        message: String,
+        handler: Handler
+    ): ChannelEvent
+
+    private external fun handle_channel_message_bytes(
+        doc: Long,
+        message: ByteArray,
+        offset: Int,
+        length: Int,
+        handler: Handler
+    ): ChannelEvent
+
     private external fun merge(doc: Long, other: Long, handler: Handler)
 
     private external fun merge_fragment_json(doc: Long, other_json: String, handler: Handler)
 
+    private external fun merge_fragment_json_bytes(
+        doc: Long,
+        other_json: ByteArray,
+        offset: Int,
+        length: Int,
+        handler: Handler
+    )
+
+    private external fun merge_fragment_json_buffer(
+        doc: Long,
+        other_json: ByteBuffer,
+        offset: Int,
+        length: Int,
+        handler: Handler
+    )
+
+    private external fun snapshot(doc: Long, nodeRef: Int): ByteArray
+
     private external fun get_parent(doc: Long, nodeRef: Int): Int
 
     private external fun get_children(doc: Long, nodeRef: Int): IntArray
@@ -130,6 +514,8 @@ class Document {
 
     private external fun get_node(doc: Long, node: Int): Long
 
+    private external fun drop_node(node: Long)
+
     private external fun node_to_string(doc: Long, node: Int): String
 
     private external fun root(doc: Long): Int
@@ -0,0 +1,12 @@
+package org.phoenixframework.liveview.lib
+
+/**
+ * Thrown when a `NodeRef` does not refer to a node attached to the document, e.g. because it
+ * is out of range or the node was removed by a merge
+ */
+class InvalidNodeRefException(val ref: Int, message: String) : IllegalArgumentException(message) {
+
+    /** The offending `NodeRef` */
+    val nodeRef: NodeRef
+        get() = NodeRef(ref)
+}
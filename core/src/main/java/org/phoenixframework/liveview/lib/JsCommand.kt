@@ -0,0 +1,176 @@
+package org.phoenixframework.liveview.lib
+
+import java.nio.ByteBuffer
+
+/**
+ * A `Phoenix.LiveView.JS` command, as the server encodes it into binding attributes such as
+ * `phx-click`. Decode an attribute value with [JsCommand.decode].
+ */
+sealed class JsCommand {
+    /** Pushes `event` to the LiveView, or to the live component `target` resolves to */
+    data class Push(
+        val event: String,
+        /** A cid or a selector, see `phx-target` */
+        val target: String?,
+        /** A selector for the elements to mark as loading while the event is sent */
+        val loading: String?,
+        val pageLoading: Boolean,
+        /** JSON of the values to send along with the event */
+        val value: String?
+    ) : JsCommand()
+
+    data class Navigate(val href: String, val replace: Boolean) : JsCommand()
+
+    data class Patch(val href: String, val replace: Boolean) : JsCommand()
+
+    data class Show(
+        val to: Target,
+        val transition: TransitionClasses,
+        val time: Int,
+        val display: String?,
+        val blocking: Boolean
+    ) : JsCommand()
+
+    data class Hide(
+        val to: Target,
+        val transition: TransitionClasses,
+        val time: Int,
+        val blocking: Boolean
+    ) : JsCommand()
+
+    data class Toggle(
+        val to: Target,
+        val ins: TransitionClasses,
+        val outs: TransitionClasses,
+        val time: Int,
+        val display: String?,
+        val blocking: Boolean
+    ) : JsCommand()
+
+    data class AddClass(
+        val to: Target,
+        val names: List<String>,
+        val transition: TransitionClasses,
+        val time: Int,
+        val blocking: Boolean
+    ) : JsCommand()
+
+    data class RemoveClass(
+        val to: Target,
+        val names: List<String>,
+        val transition: TransitionClasses,
+        val time: Int,
+        val blocking: Boolean
+    ) : JsCommand()
+
+    data class SetAttr(val to: Target, val name: String, val value: String) : JsCommand()
+
+    data class RemoveAttr(val to: Target, val name: String) : JsCommand()
+
+    data class Dispatch(
+        val to: Target,
+        val event: String,
+        /** JSON of the event detail */
+        val detail: String?,
+        val bubbles: Boolean
+    ) : JsCommand()
+
+    /** Runs the commands in the attribute `attr` of the targeted elements */
+    data class Exec(val to: Target, val attr: String) : JsCommand()
+
+    data class Focus(val to: Target) : JsCommand()
+
+    data class Transition(
+        val to: Target,
+        val transition: TransitionClasses,
+        val time: Int,
+        val blocking: Boolean
+    ) : JsCommand()
+
+    /** Classes applied while transitioning, from `start` to `end` */
+    data class TransitionClasses(
+        val classes: List<String>,
+        val start: List<String>,
+        val end: List<String>
+    )
+
+    /** The elements a command applies to */
+    sealed class Target {
+        /** The element whose binding runs the command */
+        object Element : Target()
+
+        /** Every element matching `selector` */
+        data class Selector(val selector: String) : Target()
+
+        /** The closest ancestor matching `selector` */
+        data class Closest(val selector: String) : Target()
+
+        /** The descendants of the element matching `selector` */
+        data class Inner(val selector: String) : Target()
+    }
+
+    companion object {
+        init {
+            NativeLibrary.load()
+        }
+
+        /**
+         * Decodes the commands encoded in the value of a binding attribute, e.g.
+         * `[["push",{"event":"inc"}],["toggle",{"to":"#menu"}]]`
+         *
+         * @throws JsCommandException if `value` is not valid JSON, or holds a command that is
+         * unknown or malformed
+         */
+        fun decode(value: String): List<JsCommand> =
+            Decoder(ByteBuffer.wrap(do_decode(value))).commands()
+
+        private external fun do_decode(value: String): ByteArray
+    }
+
+    /** Reads the commands encoded by `jni_bindings/src/js.rs` */
+    private class Decoder(val buffer: ByteBuffer) {
+        fun commands(): List<JsCommand> = List(buffer.int) { command() }
+
+        private fun command(): JsCommand = when (val kind = buffer.get().toInt()) {
+            0 -> Push(string(), optionalString(), optionalString(), bool(), optionalString())
+            1 -> Navigate(string(), bool())
+            2 -> Patch(string(), bool())
+            3 -> Show(target(), transition(), buffer.int, optionalString(), bool())
+            4 -> Hide(target(), transition(), buffer.int, bool())
+            5 -> Toggle(target(), transition(), transition(), buffer.int, optionalString(), bool())
+            6 -> AddClass(target(), strings(), transition(), buffer.int, bool())
+            7 -> RemoveClass(target(), strings(), transition(), buffer.int, bool())
+            8 -> SetAttr(target(), string(), string())
+            9 -> RemoveAttr(target(), string())
+            10 -> Dispatch(target(), string(), optionalString(), bool())
+            11 -> Exec(target(), string())
+            12 -> Focus(target())
+            13 -> Transition(target(), transition(), buffer.int, bool())
+            else -> throw IllegalStateException("Unknown JS command kind $kind")
+        }
+
+        private fun target(): Target = when (val kind = buffer.get().toInt()) {
+            0 -> Target.Element
+            1 -> Target.Selector(string())
+            2 -> Target.Closest(string())
+            3 -> Target.Inner(string())
+            else -> throw IllegalStateException("Unknown JS command target $kind")
+        }
+
+        private fun transition() = TransitionClasses(strings(), strings(), strings())
+
+        private fun strings(): List<String> = List(buffer.int) { string() }
+
+        private fun bool(): Boolean = buffer.get() != 0.toByte()
+
+        private fun string(): String = bytes(buffer.int)
+
+        private fun optionalString(): String? = buffer.int.let { if (it < 0) null else bytes(it) }
+
+        private fun bytes(length: Int): String {
+            val string = String(buffer.array(), buffer.position(), length, Charsets.UTF_8)
+            buffer.position(buffer.position() + length)
+            return string
+        }
+    }
+}
@@ -0,0 +1,16 @@
+package org.phoenixframework.liveview.lib
+
+/**
+ * Loads the native library, once per process.
+ *
+ * Every class or object declaring native methods calls [load] when it is initialized, so that
+ * its natives are registered whichever of them is used first.
+ */
+internal object NativeLibrary {
+    init {
+        System.loadLibrary("liveview_native_core")
+    }
+
+    /** Does nothing itself: initializing this object is what loads the library */
+    fun load() {}
+}
@@ -0,0 +1,13 @@
+package org.phoenixframework.liveview.lib
+
+/**
+ * Thrown when the native library panics. The panic is caught before it can unwind into the JVM,
+ * so the document that was being accessed may be left in an unspecified state.
+ */
+class NativePanicException(message: String, val nativeBacktrace: String) :
+    RuntimeException(message) {
+
+    override fun toString(): String =
+        if (nativeBacktrace.isEmpty()) super.toString()
+        else "${super.toString()}\nNative backtrace:\n$nativeBacktrace"
+}
@@ -14,9 +14,21 @@ sealed class Node {
         private val nativeObject: Long = pointer
 
         val namespace: String
-            get() = get_namespace(nativeObject)
+            get() = namespaceSymbol.let {
+                if (it == Symbols.NOT_INTERNED) get_namespace_string(nativeObject) else Symbols.name(it)
+            }
 
         val tag: String
+            get() = tagSymbol.let {
+                if (it == Symbols.NOT_INTERNED) get_tag_string(nativeObject) else Symbols.name(it)
+            }
+
+        /** The interned ID of [namespace], or [Symbols.NOT_INTERNED], see [Symbols] */
+        val namespaceSymbol: Int
+            get() = get_namespace(nativeObject)
+
+        /** The interned ID of [tag], or [Symbols.NOT_INTERNED], see [Symbols] */
+        val tagSymbol: Int
             get() = get_tag(nativeObject)
 
         val attributes: Array<Attribute>
@@ -24,9 +36,13 @@ sealed class Node {
 
         private external fun get_attributes(element: Long): Array<Attribute>
 
-        private external fun get_tag(element: Long): String
+        private external fun get_tag(element: Long): Int
+
+        private external fun get_tag_string(element: Long): String
+
+        private external fun get_namespace(element: Long): Int
 
-        private external fun get_namespace(element: Long): String
+        private external fun get_namespace_string(element: Long): String
 
         private external fun drop(pointer: Long)
 
@@ -0,0 +1,81 @@
+package org.phoenixframework.liveview.lib
+
+import java.nio.ByteBuffer
+
+/**
+ * A copy of a node and its whole subtree, taken by [Document.snapshot] in a single native call.
+ *
+ * A snapshot does not change when the document is merged into, and reading it makes no further
+ * native calls.
+ */
+sealed class SnapshotNode {
+    abstract val nodeRef: NodeRef
+    abstract val children: List<SnapshotNode>
+
+    /** The root of the document */
+    class Root internal constructor(
+        override val nodeRef: NodeRef,
+        override val children: List<SnapshotNode>
+    ) : SnapshotNode()
+
+    /** An element, with its attributes */
+    class Element internal constructor(
+        override val nodeRef: NodeRef,
+        val namespace: String?,
+        val tag: String,
+        val attributes: List<SnapshotAttribute>,
+        override val children: List<SnapshotNode>
+    ) : SnapshotNode()
+
+    /** A text node, which never has children */
+    class Leaf internal constructor(override val nodeRef: NodeRef, val value: String) :
+        SnapshotNode() {
+        override val children: List<SnapshotNode>
+            get() = emptyList()
+    }
+
+    internal companion object {
+        /** Rebuilds the tree encoded by `jni_bindings/src/snapshot.rs` */
+        fun decode(bytes: ByteArray): SnapshotNode {
+            val buffer = ByteBuffer.wrap(bytes)
+            val strings = Array(buffer.int) {
+                val length = buffer.int
+                val string = String(bytes, buffer.position(), length, Charsets.UTF_8)
+                buffer.position(buffer.position() + length)
+                string
+            }
+            val nodeCount = buffer.int
+            check(nodeCount > 0) { "empty snapshot" }
+            return Decoder(buffer, strings).node()
+        }
+    }
+
+    private class Decoder(val buffer: ByteBuffer, val strings: Array<String>) {
+        fun node(): SnapshotNode {
+            val nodeRef = NodeRef(buffer.int)
+            return when (val type = buffer.get()) {
+                0.toByte() -> Root(nodeRef, children())
+                1.toByte() -> {
+                    val namespace = optionalString()
+                    val tag = string()
+                    val attributes = List(buffer.int) {
+                        SnapshotAttribute(optionalString(), string(), optionalString())
+                    }
+                    Element(nodeRef, namespace, tag, attributes, children())
+                }
+                // Always followed by a child count of 0
+                2.toByte() -> Leaf(nodeRef, string()).also { buffer.int }
+                else -> throw IllegalStateException("Unknown node type $type")
+            }
+        }
+
+        private fun children(): List<SnapshotNode> = List(buffer.int) { node() }
+
+        private fun string(): String = strings[buffer.int]
+
+        private fun optionalString(): String? = buffer.int.let { if (it < 0) null else strings[it] }
+    }
+}
+
+/** An attribute of a [SnapshotNode.Element] */
+data class SnapshotAttribute(val namespace: String?, val name: String, val value: String?)
@@ -0,0 +1,37 @@
+package org.phoenixframework.liveview.lib
+
+/**
+ * Resolves the interned tag and attribute names returned by [Node.Element.tagSymbol],
+ * [Attribute.nameSymbol] and their namespace counterparts.
+ *
+ * An ID always stands for the same name, so names are fetched from the native side once and
+ * cached for the lifetime of the process. Their number is bounded: once the native side has
+ * interned as many as it holds, new names get [NOT_INTERNED] instead, and the string properties
+ * of [Node.Element] and [Attribute] fetch them as strings.
+ */
+object Symbols {
+    init {
+        NativeLibrary.load()
+    }
+
+    /** The ID of a name that was not interned, and that [name] can not resolve */
+    const val NOT_INTERNED = -1
+
+    @Volatile private var names: Array<String> = emptyArray()
+
+    /** The name interned as `symbol` */
+    fun name(symbol: Int): String {
+        val names = names
+        if (symbol < names.size) {
+            return names[symbol]
+        }
+        return synchronized(this) {
+            if (symbol >= this.names.size) {
+                this.names += resolve(this.names.size)
+            }
+            this.names[symbol]
+        }
+    }
+
+    private external fun resolve(from: Int): Array<String>
+}
@@ -0,0 +1,40 @@
+package org.phoenixframework.liveview.lib
+
+/**
+ * Natives that exercise the failure paths of the native library, for tests
+ *
+ * They are only registered if the native library was built with `-PnativeChecks`, see
+ * jni_bindings/src/checks.rs.
+ */
+internal object NativeChecks {
+    init {
+        NativeLibrary.load()
+    }
+
+    /** Panics in native code with `message`, which must surface as a [NativePanicException] */
+    external fun panic(message: String)
+
+    /**
+     * Whether the fragment diff `json` would be merged into `doc` in place, without rendering its
+     * fragment template
+     */
+    fun mergesInPlace(doc: Document, json: String): Boolean = merges_in_place(doc.handle, json)
+
+    private external fun merges_in_place(doc: Long, json: String): Boolean
+
+    /**
+     * Looks up `[class, method, signature]` triples like the native library does when it is
+     * loaded, with an empty method to look up the class alone
+     *
+     * @throws UnsatisfiedLinkError listing every class and method that is missing
+     */
+    external fun resolve(lookups: Array<String>)
+
+    /**
+     * Checks `[class, method, signature]` triples like the native library does before registering
+     * its natives, without registering anything
+     *
+     * @throws UnsatisfiedLinkError listing every native that is missing or misspelled
+     */
+    external fun checkNatives(natives: Array<String>)
+}
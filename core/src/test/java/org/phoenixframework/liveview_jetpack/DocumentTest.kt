@@ -2,7 +2,31 @@
 
 import org.junit.Test
 import org.junit.Assert.*
+import java.nio.ByteBuffer
+import kotlin.concurrent.thread
+import org.phoenixframework.liveview.lib.AttributeChange
+import org.phoenixframework.liveview.lib.ChangeBatch
+import org.phoenixframework.liveview.lib.ChangeDetails
+import org.phoenixframework.liveview.lib.ChannelDecodeException
+import org.phoenixframework.liveview.lib.ChannelEvent
+import org.phoenixframework.liveview.lib.CoreException
+import org.phoenixframework.liveview.lib.DeadRender
+import org.phoenixframework.liveview.lib.DeadRenderException
 import org.phoenixframework.liveview.lib.Document
+import org.phoenixframework.liveview.lib.EventException
+import org.phoenixframework.liveview.lib.FragmentDecodeException
+import org.phoenixframework.liveview.lib.InvalidNodeRefException
+import org.phoenixframework.liveview.lib.JoinParams
+import org.phoenixframework.liveview.lib.JsCommand
+import org.phoenixframework.liveview.lib.JsCommandException
+import org.phoenixframework.liveview.lib.NativeChecks
+import org.phoenixframework.liveview.lib.NativePanicException
+import org.phoenixframework.liveview.lib.Node
+import org.phoenixframework.liveview.lib.NodeRef
+import org.phoenixframework.liveview.lib.ParseException
+import org.phoenixframework.liveview.lib.RenderException
+import org.phoenixframework.liveview.lib.SnapshotNode
+import org.phoenixframework.liveview.lib.Symbols
 
 class DocumentTest {
     @Test
@@ -10,6 +34,79 @@ class DocumentTest {
         Document()
     }
 
+    @Test
+    fun stale_handle_throws_illegal_state() {
+        val stale = Document(0x7fffffff00000001L)
+
+        assertThrows(IllegalStateException::class.java) {
+            stale.toString()
+        }
+    }
+
+    @Test
+    fun invalid_node_ref_throws() {
+        val doc = Document.parse("<Text>Hello</Text>")
+
+        val negative = assertThrows(InvalidNodeRefException::class.java) {
+            doc.getNode(NodeRef(-1))
+        }
+        assertEquals(-1, negative.ref)
+
+        val outOfRange = assertThrows(InvalidNodeRefException::class.java) {
+            doc.getChildren(NodeRef(4096))
+        }
+        assertEquals(4096, outOfRange.ref)
+    }
+
+    @Test
+    fun native_panics_throw_native_panic_exception() {
+        val err = assertThrows(NativePanicException::class.java) {
+            NativeChecks.panic("on purpose")
+        }
+        assertTrue(err.message!!.contains("on purpose"))
+        assertTrue(err.message!!.contains("lib.rs"))
+
+        // The panic is contained, so the library keeps working
+        assertTrue(Document.parse("<Text>A</Text>").toString().contains("A"))
+    }
+
+    @Test
+    fun missing_classes_and_methods_fail_to_resolve() {
+        val handler = "org/phoenixframework/liveview/lib/Document\$Companion\$Handler"
+        val err = assertThrows(UnsatisfiedLinkError::class.java) {
+            NativeChecks.resolve(arrayOf(
+                "org/phoenixframework/liveview/lib/Document", "", "",
+                "org/phoenixframework/liveview/lib/Missing", "", "",
+                handler, "ffiOnHandel", "(JBIIIILorg/phoenixframework/liveview/lib/ChangeDetails;I)V",
+            ))
+        }
+        assertTrue(err.message!!.contains("class org/phoenixframework/liveview/lib/Missing"))
+        assertTrue(err.message!!.contains("method $handler.ffiOnHandel"))
+
+        // The failed lookups left nothing pending
+        NativeChecks.resolve(arrayOf(handler, "ffiFailurePolicy", "()B"))
+        assertTrue(Document.parse("<VStack></VStack>").toString().contains("VStack"))
+    }
+
+    @Test
+    fun missing_natives_fail_with_the_full_list() {
+        val document = "org/phoenixframework/liveview/lib/Document"
+        val handler = "Lorg/phoenixframework/liveview/lib/Document\$Companion\$Handler;"
+        val err = assertThrows(UnsatisfiedLinkError::class.java) {
+            NativeChecks.checkNatives(arrayOf(
+                document, "merge", "(JJ${handler})V",
+                document, "merge", "(JJ)V",
+                "org/phoenixframework/liveview/lib/Missing", "merge", "(JJ)V",
+            ))
+        }
+        assertTrue(err.message!!.contains("native method $document.merge(JJ)V"))
+        assertTrue(err.message!!.contains("class org/phoenixframework/liveview/lib/Missing"))
+        assertFalse(err.message!!.contains("merge(JJ$handler)V"))
+
+        // Nothing was registered or left pending
+        assertTrue(Document.parse("<VStack></VStack>").toString().contains("VStack"))
+    }
+
     @Test
     fun it_morphs_live_form() {
         var doc = Document.parse("""
@@ -38,6 +135,272 @@ class DocumentTest {
         doc.merge(to,  Document.Companion.Handler());
     }
 
+    @Test
+    fun handler_exception_rolls_back_merge() {
+        val doc = Document.parse("<VStack><Text>Before</Text></VStack>")
+        val to = Document.parse("<VStack><Text>After</Text></VStack>")
+        val before = doc.toString()
+
+        val handler = object : Document.Companion.Handler(Document.Companion.FailurePolicy.Rollback) {
+            override fun onHandle(
+                context: Document,
+                changeType: Document.Companion.ChangeType,
+                nodeRef: NodeRef,
+                parent: NodeRef?
+            ) {
+                throw UnsupportedOperationException("from handler")
+            }
+        }
+
+        val thrown = assertThrows(UnsupportedOperationException::class.java) {
+            doc.merge(to, handler)
+        }
+        assertEquals("from handler", thrown.message)
+        assertEquals(before, doc.toString())
+    }
+
+    @Test
+    fun merging_from_a_handler_is_rejected() {
+        val doc = Document.parse("<VStack><Text>Before</Text></VStack>")
+        val to = Document.parse("<VStack><Text>After</Text></VStack>")
+        val nested = Document.parse("<VStack><Text>Nested</Text></VStack>")
+        val before = doc.toString()
+
+        val handler = object : Document.Companion.Handler(Document.Companion.FailurePolicy.Rollback) {
+            override fun onHandle(
+                context: Document,
+                changeType: Document.Companion.ChangeType,
+                nodeRef: NodeRef,
+                parent: NodeRef?
+            ) {
+                context.merge(nested, Document.Companion.Handler())
+            }
+        }
+
+        assertThrows(IllegalStateException::class.java) {
+            doc.merge(to, handler)
+        }
+        assertEquals(before, doc.toString())
+
+        // Once the outer merge is over, the document can be merged into again
+        doc.merge(nested, Document.Companion.Handler())
+        assertTrue(doc.toString().contains("Nested"))
+    }
+
+    @Test
+    fun merging_from_another_thread_during_a_handler_is_rejected() {
+        val doc = Document.parse("<VStack><Text>Before</Text></VStack>")
+        val to = Document.parse("<VStack><Text>After</Text></VStack>")
+        val other = Document.parse("<VStack><Text>Other</Text></VStack>")
+
+        var error: Throwable? = null
+        val handler = object : Document.Companion.Handler() {
+            override fun onHandle(
+                context: Document,
+                changeType: Document.Companion.ChangeType,
+                nodeRef: NodeRef,
+                parent: NodeRef?
+            ) {
+                thread {
+                    try {
+                        doc.merge(other, Document.Companion.Handler())
+                    } catch (e: Throwable) {
+                        error = e
+                    }
+                }.join()
+            }
+        }
+
+        doc.merge(to, handler)
+        assertTrue(error is IllegalStateException)
+        assertTrue(error!!.message!!.contains("another thread"))
+        assertTrue(doc.toString().contains("After"))
+
+        // Once the handler has returned, other threads can merge again
+        thread { doc.merge(other, Document.Companion.Handler()) }.join()
+        assertTrue(doc.toString().contains("Other"))
+    }
+
+    @Test
+    fun reads_from_other_threads_wait_for_merges() {
+        val doc = Document.parse("<VStack><Text>0</Text></VStack>")
+        val versions = (1..100).map { Document.parse("<VStack><Text>$it</Text></VStack>") }
+
+        var error: Throwable? = null
+        val merging = thread {
+            try {
+                for (version in versions) {
+                    doc.merge(version, Document.Companion.Handler())
+                }
+            } catch (e: Throwable) {
+                error = e
+            }
+        }
+        while (merging.isAlive) {
+            assertTrue(doc.toString().contains("<Text>"))
+        }
+        merging.join()
+        assertNull(error)
+        assertTrue(doc.toString().contains("100"))
+    }
+
+    @Test
+    fun batch_handler_receives_all_changes_at_once() {
+        val doc = Document.parse("<VStack><Text>Before</Text></VStack>")
+        val to = Document.parse("<VStack><Text>After</Text><Text>Added</Text></VStack>")
+
+        var calls = 0
+        var size = 0
+        doc.merge(to, object : Document.Companion.BatchHandler() {
+            override fun onChanges(context: Document, changes: ChangeBatch) {
+                calls += 1
+                size = changes.size
+            }
+        })
+
+        assertEquals(1, calls)
+        assertTrue(size > 0)
+    }
+
+    @Test
+    fun added_nodes_report_their_position() {
+        val doc = Document.parse("<VStack><Text>A</Text></VStack>")
+        val to = Document.parse("<VStack><Text>A</Text><Text>B</Text><Text>C</Text></VStack>")
+
+        var added = 0
+        doc.merge(to, object : Document.Companion.BatchHandler() {
+            override fun onChanges(context: Document, changes: ChangeBatch) {
+                for (i in 0 until changes.size) {
+                    if (changes.changeType(i) != Document.Companion.ChangeType.Add) continue
+                    val siblings = context.getChildren(changes.parent(i)!!)
+                    val index = changes.childIndex(i)
+                    if (index < 0) continue
+                    added += 1
+                    assertEquals(changes.nodeRef(i).ref, siblings[index].ref)
+                    assertEquals(
+                        if (index == 0) null else siblings[index - 1].ref,
+                        changes.previousSibling(i)?.ref)
+                }
+            }
+        })
+
+        assertTrue(added > 0)
+    }
+
+    /** The type and node of each change `doc.merge(to)` reports */
+    private fun mergeChanges(doc: Document, to: Document): List<Pair<Document.Companion.ChangeType, Int>> {
+        val reported = mutableListOf<Pair<Document.Companion.ChangeType, Int>>()
+        doc.merge(to, object : Document.Companion.BatchHandler() {
+            override fun onChanges(context: Document, changes: ChangeBatch) {
+                for (i in 0 until changes.size) {
+                    reported.add(changes.changeType(i) to changes.nodeRef(i).ref)
+                }
+            }
+        })
+        return reported
+    }
+
+    @Test
+    fun reordered_keyed_nodes_are_moved() {
+        val from = """<VStack><Text id="a">A</Text><Text id="b">B</Text><Text id="c">C</Text></VStack>"""
+        val to = """<VStack><Text id="c">C</Text><Text id="a">A</Text><Text id="b">B</Text></VStack>"""
+
+        // Move detection is opt-in
+        val unkeyed = Document.parse(from)
+        assertTrue(mergeChanges(unkeyed, Document.parse(to)).none { it.first == Document.Companion.ChangeType.Move })
+
+        val doc = Document.parse(from)
+        doc.setKeyAttribute("id")
+        val changes = mergeChanges(doc, Document.parse(to))
+        assertEquals(Document.parse(to).toString(), doc.toString())
+
+        // Moves are matched by key, whether or not the merge kept the node
+        val moved = changes.filter { it.first == Document.Companion.ChangeType.Move }.map { it.second }
+        assertTrue(moved.isNotEmpty())
+        val children = doc.getChildren(doc.getChildren(doc.rootNodeRef).single()).map { it.ref }
+        assertTrue(children.containsAll(moved))
+    }
+
+    @Test
+    fun same_key_replacements_are_not_moves() {
+        val doc = Document.parse("""<VStack><Text id="a">A</Text></VStack>""")
+        doc.setKeyAttribute("id")
+        val old = doc.getChildren(doc.getChildren(doc.rootNodeRef).single()).single()
+
+        val changes = mergeChanges(doc, Document.parse("""<VStack><Button id="a">A</Button></VStack>"""))
+        val new = doc.getChildren(doc.getChildren(doc.rootNodeRef).single()).single()
+
+        assertNotEquals(old.ref, new.ref)
+        assertTrue(changes.none { it.first == Document.Companion.ChangeType.Move })
+        assertTrue(changes.any { it.second == new.ref && it.first != Document.Companion.ChangeType.Change })
+    }
+
+    @Test
+    fun keyed_nodes_moved_to_another_parent_are_not_moves() {
+        val doc = Document.parse("""<VStack><VStack id="p1"><Text id="a">A</Text></VStack><VStack id="p2"></VStack></VStack>""")
+        doc.setKeyAttribute("id")
+
+        val changes = mergeChanges(
+            doc, Document.parse("""<VStack><VStack id="p1"></VStack><VStack id="p2"><Text id="a">A</Text></VStack></VStack>"""))
+
+        assertTrue(changes.none { it.first == Document.Companion.ChangeType.Move })
+        assertTrue(changes.any { it.first == Document.Companion.ChangeType.Add })
+    }
+
+    @Test
+    fun change_reports_modified_attributes() {
+        val doc = Document.parse("<VStack><Text color=\"red\">A</Text></VStack>")
+        val to = Document.parse("<VStack><Text color=\"blue\">A</Text></VStack>")
+
+        val modified = mutableListOf<AttributeChange>()
+        doc.merge(to, object : Document.Companion.Handler() {
+            override fun onHandle(
+                context: Document,
+                changeType: Document.Companion.ChangeType,
+                nodeRef: NodeRef,
+                parent: NodeRef?,
+                index: Int,
+                previousSibling: NodeRef?,
+                details: ChangeDetails?
+            ) {
+                details?.let { modified.addAll(it.modified) }
+            }
+        })
+
+        val color = modified.single { it.name == "color" }
+        assertEquals("red", color.oldValue)
+        assertEquals("blue", color.newValue)
+    }
+
+    @Test
+    fun snapshot_copies_the_subtree() {
+        val doc = Document.parse("<VStack><Text color=\"red\">A</Text><Text color=\"red\">B</Text></VStack>")
+
+        val root = doc.snapshot()
+        assertEquals(doc.rootNodeRef.ref, root.nodeRef.ref)
+        val stack = root.children.single() as SnapshotNode.Element
+        assertEquals("VStack", stack.tag)
+        assertEquals(doc.getChildren(stack.nodeRef).map { it.ref }, stack.children.map { it.nodeRef.ref })
+
+        val texts = stack.children.map { it as SnapshotNode.Element }
+        assertEquals(listOf("red", "red"), texts.map { it.attributes.single().value })
+        assertEquals(listOf("A", "B"), texts.map { (it.children.single() as SnapshotNode.Leaf).value })
+        assertEquals(doc.snapshot(texts[1].nodeRef).children.single().nodeRef.ref, texts[1].children.single().nodeRef.ref)
+    }
+
+    @Test
+    fun same_names_share_a_symbol() {
+        val doc = Document.parse("<VStack><Text color=\"red\">A</Text><Text color=\"blue\">B</Text></VStack>")
+        val stack = doc.getChildren(doc.rootNodeRef).single()
+        val texts = doc.getChildren(stack).map { doc.getNode(it) as Node.Element }
+
+        assertEquals(texts[0].tagSymbol, texts[1].tagSymbol)
+        assertNotEquals(texts[0].tagSymbol, (doc.getNode(stack) as Node.Element).tagSymbol)
+        assertEquals("Text", Symbols.name(texts[1].tagSymbol))
+        assertEquals(texts[0].attributes.single().nameSymbol, texts[1].attributes.single().nameSymbol)
+        assertEquals("color", texts[1].attributes.single().name)
+    }
+
     @Test
     fun merge_json_into_document_exception() {
         var doc = Document.parse("""
@@ -68,6 +431,59 @@ class DocumentTest {
 
     }
 
+    @Test
+    fun merge_json_reports_decode_position() {
+        var doc = Document.parse("<VStack></VStack>")
+
+        val err = assertThrows(FragmentDecodeException::class.java) {
+            doc.mergeFragmentJson("{\"0\": [}", Document.Companion.Handler())
+        }
+        assertEquals("Syntax", err.kind)
+        assertEquals(1, err.line)
+    }
+
+    @Test
+    fun parse_and_merge_from_bytes() {
+        val markup = "<VStack><Text>A</Text></VStack>".toByteArray()
+        val direct = ByteBuffer.allocateDirect(markup.size + 2).put(byteArrayOf(0, 0)).put(markup)
+        direct.position(2)
+
+        assertEquals(Document.parse(markup).toString(), Document.parse(direct).toString())
+        assertEquals(2, direct.position())
+
+        val doc = Document.parse(markup)
+        val json = "{\"0\": [}".toByteArray()
+        val err = assertThrows(FragmentDecodeException::class.java) {
+            doc.mergeFragmentJson(ByteBuffer.wrap(json), Document.Companion.Handler())
+        }
+        assertEquals(1, err.line)
+
+        assertThrows(ParseException::class.java) {
+            Document.parse(byteArrayOf(0xC3.toByte(), 0x28))
+        }
+    }
+
+    @Test
+    fun parse_failure_throws_parse_exception() {
+        val err = assertThrows(ParseException::class.java) {
+            Document.parse("<VStack>\n  <Text></VStack>")
+        }
+        assertEquals(2, err.line)
+        assertEquals(9, err.column)
+        assertEquals(17, err.offset)
+        assertEquals("  <Text></VStack>\n        ^", err.excerpt)
+    }
+
+    @Test
+    fun parse_failure_location_skips_text_that_looks_like_a_tag() {
+        val err = assertThrows(ParseException::class.java) {
+            Document.parse("<VStack><Text>a < b</Text></Stack>")
+        }
+        assertEquals(1, err.line)
+        assertEquals(27, err.column)
+        assertTrue(err.message!!.contains("suggests"))
+    }
+
     @Test
     fun merge_json_into_document() {
         var doc = Document.parse("""
@@ -104,4 +520,322 @@ class DocumentTest {
         doc.mergeFragmentJson(valid_json,  Document.Companion.Handler())
 
     }
+
+    @Test
+    fun failed_fragment_merges_leave_the_document_unchanged() {
+        val doc = Document()
+        val handler = Document.Companion.Handler()
+        doc.mergeFragmentJson("""{"0": "A", "s": ["<VStack><Text>", "</Text></VStack>"]}""", handler)
+        val before = doc.toString()
+
+        // The fragment template merges, but its rendering is not valid markup
+        assertThrows(ParseException::class.java) {
+            doc.mergeFragmentJson("""{"0": "B", "s": ["<VStack><Text>", "</Text></Stack>"]}""", handler)
+        }
+        assertEquals(before, doc.toString())
+
+        // The rendering refers to a component that does not exist
+        assertThrows(RenderException::class.java) {
+            doc.mergeFragmentJson("""{"0": 5}""", handler)
+        }
+        assertEquals(before, doc.toString())
+
+        // Rolled back after merging in place, when the handler throws
+        val throwing = object : Document.Companion.Handler(Document.Companion.FailurePolicy.Rollback) {
+            override fun onHandle(
+                context: Document,
+                changeType: Document.Companion.ChangeType,
+                nodeRef: NodeRef,
+                parent: NodeRef?
+            ) {
+                throw UnsupportedOperationException("from handler")
+            }
+        }
+        assertThrows(UnsupportedOperationException::class.java) {
+            doc.mergeFragmentJson("""{"0": "C"}""", throwing)
+        }
+        assertEquals(before, doc.toString())
+
+        // The fragment template was restored along with the document
+        doc.mergeFragmentJson("""{"0": "D"}""", handler)
+        assertEquals(before.replace("A", "D"), doc.toString())
+    }
+
+    @Test
+    fun empty_dynamics_are_merged_in_place() {
+        val doc = Document()
+        val handler = Document.Companion.Handler()
+        // The second dynamic is a conditional that renders nothing
+        doc.mergeFragmentJson("""{"0": "A", "1": "", "s": ["<VStack><Text>", "</Text>", "</VStack>"]}""", handler)
+
+        assertTrue(NativeChecks.mergesInPlace(doc, """{"0": "B"}"""))
+        doc.mergeFragmentJson("""{"0": "B"}""", handler)
+        assertTrue(doc.toString().contains("B"))
+
+        // Rendering something in place of the empty dynamic needs a full merge
+        assertFalse(NativeChecks.mergesInPlace(doc, """{"1": "<Text>C</Text>"}"""))
+    }
+
+    @Test
+    fun in_place_merges_decode_entities_like_the_parser() {
+        val statics = """["<VStack><Text>", "</Text></VStack>"]"""
+        val doc = Document()
+        doc.mergeFragmentJson("""{"0": "A", "s": $statics}""", Document.Companion.Handler())
+
+        for (escaped in listOf("&amp;&lt;&gt;&quot;&#39;", "&#x41;&#66;", "caf&#233;", "&nbsp;x")) {
+            val diff = """{"0": "$escaped"}"""
+            assertTrue(NativeChecks.mergesInPlace(doc, diff))
+            doc.mergeFragmentJson(diff, Document.Companion.Handler())
+
+            val rendered = Document()
+            rendered.mergeFragmentJson("""{"0": "$escaped", "s": $statics}""", Document.Companion.Handler())
+            assertEquals(rendered.toString(), doc.toString())
+        }
+
+        // References that tokenizers replace are left to the parser
+        assertFalse(NativeChecks.mergesInPlace(doc, """{"0": "&#0;"}"""))
+        assertFalse(NativeChecks.mergesInPlace(doc, """{"0": "&#x80;"}"""))
+    }
+
+    @Test
+    fun channel_messages_are_routed() {
+        val doc = Document()
+        val handler = Document.Companion.Handler()
+
+        val join = """["4", "4", "lv:phx-1", "phx_reply", {"status": "ok", "response": {"rendered": {"0": "A", "s": ["<Text>", "</Text>"]}}}]"""
+        assertTrue(doc.handleChannelMessage(join, handler) is ChannelEvent.Rendered)
+        assertTrue(doc.toString().contains("A"))
+
+        val diff = """["4", null, "lv:phx-1", "diff", {"0": "B"}]"""
+        val event = doc.handleChannelMessage(diff.toByteArray(), handler)
+        assertTrue(event is ChannelEvent.Diff)
+        assertEquals("4", event.joinRef)
+        assertNull(event.ref)
+        assertTrue(doc.toString().contains("B"))
+
+        val reply = """["4", "5", "lv:phx-1", "phx_reply", {"status": "ok", "response": {"diff": {"0": "C"}, "reply": {"saved": true}}}]"""
+        val replied = doc.handleChannelMessage(reply, handler) as ChannelEvent.Reply
+        assertEquals("5", replied.ref)
+        assertEquals("ok", replied.status)
+        assertEquals("""{"reply":{"saved":true}}""", replied.response)
+        assertTrue(doc.toString().contains("C"))
+
+        val redirect = """["4", null, "lv:phx-1", "live_redirect", {"to": "/next", "kind": "push"}]"""
+        val live = doc.handleChannelMessage(redirect, handler) as ChannelEvent.LiveRedirect
+        assertEquals("/next", live.to)
+        assertEquals("push", live.kind)
+
+        assertThrows(ChannelDecodeException::class.java) {
+            doc.handleChannelMessage("""{"event": "diff"}""", handler)
+        }
+    }
+
+    @Test
+    fun components_are_tracked_by_cid() {
+        val doc = Document()
+        val renderedCids = mutableListOf<Int>()
+        val destroyedCids = mutableListOf<Int>()
+        val handler = object : Document.Companion.Handler() {
+            override fun onComponents(context: Document, rendered: IntArray, destroyed: IntArray) {
+                renderedCids.addAll(rendered.toList())
+                destroyedCids.addAll(destroyed.toList())
+            }
+        }
+
+        doc.mergeFragmentJson("""
+            {"0": 1, "c": {"1": {"0": "x", "s": ["<Text>", "</Text>"]}}, "s": ["<VStack>", "</VStack>"]}
+        """, handler)
+        assertEquals(listOf(1), renderedCids)
+        val stack = doc.getChildren(doc.rootNodeRef).single()
+        val text = doc.getChildren(stack).single()
+        assertEquals(1, doc.componentOf(text))
+        assertEquals(1, doc.componentOf(doc.getChildren(text).single()))
+        assertNull(doc.componentOf(stack))
+
+        doc.mergeFragmentJson("""{"0": ""}""", handler)
+        assertEquals(listOf(1), destroyedCids)
+        doc.pruneComponents(intArrayOf(1))
+    }
+
+    @Test
+    fun components_sharing_statics_in_a_cycle_fail_cleanly() {
+        val doc = Document()
+        doc.mergeFragmentJson("""
+            {"0": 1, "c": {"1": {"0": "x", "s": ["<Text>", "</Text>"]}}, "s": ["<VStack>", "</VStack>"]}
+        """, Document.Companion.Handler())
+        val before = doc.toString()
+
+        assertThrows(CoreException::class.java) {
+            doc.mergeFragmentJson("""
+                {"0": 2, "c": {"2": {"0": "y", "s": 3}, "3": {"0": "z", "s": 2}}}
+            """, Document.Companion.Handler())
+        }
+        assertEquals(before, doc.toString())
+    }
+
+    @Test
+    fun selector_targets_find_components_with_blank_dynamics() {
+        val doc = Document()
+        doc.mergeFragmentJson("""
+            {
+                "0": 1,
+                "1": "<Button phx-click=\"go\" phx-target=\"#item\">Go</Button>",
+                "c": {"1": {"0": "", "1": "x", "s": ["<VStack id=\"item\">", "<Text>", "</Text></VStack>"]}},
+                "s": ["<VStack>", "", "</VStack>"]
+            }
+        """, Document.Companion.Handler())
+        val (item, button) = doc.getChildren(doc.getChildren(doc.rootNodeRef).single())
+
+        assertEquals(1, doc.componentOf(doc.getChildren(item).single()))
+        val payload = doc.eventPayloads(button, "click").single()
+        assertTrue(payload.contains("\"cid\":1"))
+    }
+
+    @Test
+    fun streamed_items_are_kept_across_diffs() {
+        val doc = Document()
+        val changes = mutableListOf<Document.Companion.ChangeType>()
+        val handler = object : Document.Companion.Handler() {
+            override fun onHandle(
+                context: Document,
+                changeType: Document.Companion.ChangeType,
+                nodeRef: NodeRef,
+                parent: NodeRef?
+            ) {
+                changes.add(changeType)
+            }
+        }
+        fun ids(): List<String?> {
+            val stack = doc.getChildren(doc.rootNodeRef).single()
+            return doc.getChildren(stack).map { child ->
+                (doc.getNode(child) as Node.Element).attributes.single { it.name == "id" }.value
+            }
+        }
+
+        doc.mergeFragmentJson("""
+            {"0": {"d": [["a", "A"], ["b", "B"]], "s": ["<Text id=\"", "\">", "</Text>"],
+                   "stream": ["items", [["a", -1, null], ["b", -1, null]], [], false]},
+             "s": ["<VStack id=\"items\" phx-update=\"stream\">", "</VStack>"]}
+        """, handler)
+        assertEquals(listOf("a", "b"), ids())
+
+        changes.clear()
+        doc.mergeFragmentJson("""
+            {"0": {"d": [["c", "C"]], "stream": ["items", [["c", 0, null]], ["a"], false]}}
+        """, handler)
+        assertEquals(listOf("c", "b"), ids())
+        assertEquals(
+            listOf(Document.Companion.ChangeType.Remove, Document.Companion.ChangeType.Add),
+            changes
+        )
+
+        doc.mergeFragmentJson("""
+            {"0": {"d": [["d", "D"]], "stream": ["items", [["d", -1, -2]], [], false]}}
+        """, handler)
+        assertEquals(listOf("b", "d"), ids())
+    }
+
+    @Test
+    fun phx_update_keeps_children() {
+        val doc = Document.parse("""
+            <VStack>
+              <Text id="ignored" phx-update="ignore" color="red" data-count="1">A</Text>
+              <VStack id="appended" phx-update="append"><Text id="a">A</Text></VStack>
+            </VStack>
+        """)
+        val to = Document.parse("""
+            <VStack>
+              <Text id="ignored" phx-update="ignore" color="blue" data-count="2">B</Text>
+              <VStack id="appended" phx-update="append"><Text id="b">B</Text></VStack>
+            </VStack>
+        """)
+        doc.merge(to, Document.Companion.Handler())
+
+        val (ignored, appended) = doc.getChildren(doc.getChildren(doc.rootNodeRef).single())
+        val attributes = (doc.getNode(ignored) as Node.Element).attributes.associate { it.name to it.value }
+        assertEquals("red", attributes["color"])
+        assertEquals("2", attributes["data-count"])
+        assertEquals("A", (doc.getNode(doc.getChildren(ignored).single()) as Node.Leaf).value)
+        val ids = doc.getChildren(appended).map { child ->
+            (doc.getNode(child) as Node.Element).attributes.single { it.name == "id" }.value
+        }
+        assertEquals(listOf("a", "b"), ids)
+    }
+
+    @Test
+    fun event_payloads_match_the_js_client() {
+        val doc = Document.parse("""
+            <VStack>
+              <Button id="like" phx-click="like" phx-value-id="7" phx-target="3">Like</Button>
+              <LiveForm id="user" phx-submit="save" phx-change="validate">
+                <TextField name="user[email]" value="a b@c.d" />
+                <Toggle name="user[admin]" type="checkbox" />
+                <LiveSubmitButton name="action" value="save"><Text>Save</Text></LiveSubmitButton>
+              </LiveForm>
+            </VStack>
+        """)
+        val (like, form) = doc.getChildren(doc.getChildren(doc.rootNodeRef).single())
+        val (email, _, submit) = doc.getChildren(form)
+
+        assertEquals(
+            listOf("""{"cid":3,"event":"like","type":"click","value":{"id":"7"}}"""),
+            doc.eventPayloads(doc.getChildren(like).single(), "click")
+        )
+        assertEquals(
+            listOf("""{"event":"validate","type":"form","uploads":{},"value":"user%5Bemail%5D=a+b%40c.d&_target=user%5Bemail%5D"}"""),
+            doc.eventPayloads(email, "change")
+        )
+        assertEquals(
+            listOf("""{"event":"save","meta":{},"type":"form","value":"user%5Bemail%5D=a+b%40c.d&action=save"}"""),
+            doc.eventPayloads(doc.getChildren(submit).single(), "submit")
+        )
+        assertThrows(EventException::class.java) {
+            doc.eventPayloads(email, "blur")
+        }
+    }
+
+    @Test
+    fun js_commands_are_decoded() {
+        val commands = JsCommand.decode("""
+            [["push", {"event": "inc", "value": {"by": 2}}],
+             ["toggle", {"to": {"closest": ".menu"}, "ins": [["fade-in"], [], []], "time": 150}],
+             ["set_attr", {"to": "#title", "attr": ["aria-expanded", "true"]}]]
+        """)
+        assertEquals(JsCommand.Push("inc", null, null, false, """{"by":2}"""), commands[0])
+        val toggle = commands[1] as JsCommand.Toggle
+        assertEquals(JsCommand.Target.Closest(".menu"), toggle.to)
+        assertEquals(listOf("fade-in"), toggle.ins.classes)
+        assertEquals(150, toggle.time)
+        assertEquals(
+            JsCommand.SetAttr(JsCommand.Target.Selector("#title"), "aria-expanded", "true"),
+            commands[2]
+        )
+
+        val unknown = assertThrows(JsCommandException::class.java) {
+            JsCommand.decode("""[["teleport", {}]]""")
+        }
+        assertEquals("UnknownCommand", unknown.kind)
+    }
+
+    @Test
+    fun dead_render_has_join_params() {
+        val render = DeadRender.parse("""
+            <html>
+              <head><meta name="csrf-token" content="csrf123" /></head>
+              <body>
+                <div id="phx-F1" data-phx-main data-phx-session="SFMyNTY" data-phx-static="c3RhdGlj">
+                  <Text>Hello</Text>
+                </div>
+              </body>
+            </html>
+        """)
+        assertEquals(JoinParams("SFMyNTY", "c3RhdGlj", true, "phx-F1", "csrf123"), render.joinParams)
+        assertTrue(render.document.toString().contains("Hello"))
+
+        val missing = assertThrows(DeadRenderException::class.java) {
+            DeadRender.parse("""<div id="phx-F1" data-phx-session="SFMyNTY"></div>""")
+        }
+        assertTrue(missing.message!!.contains("data-phx-static"))
+        assertTrue(missing.message!!.contains("CSRF token"))
+    }
 }
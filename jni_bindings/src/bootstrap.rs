@@ -0,0 +1,167 @@
+//! The parameters of the LiveView channel join, found in the dead render: the
+//! page served over HTTP before the client connects.
+//!
+//! The LiveView container carries the signed `data-phx-session` and
+//! `data-phx-static` tokens, and its DOM id is the channel topic. The CSRF
+//! token is in a `<meta name="csrf-token" content="..">` element, or in the
+//! `<csrf-token value="..">` element of native layouts.
+
+use jni::{
+    objects::{JObject, JValue},
+    JNIEnv,
+};
+use liveview_native_core::{dom, dom::Document};
+
+use crate::{cache, error::Error};
+
+const PHX_MAIN: &str = "data-phx-main";
+const PHX_SESSION: &str = "data-phx-session";
+const PHX_STATIC: &str = "data-phx-static";
+const CSRF_TOKEN: &str = "csrf-token";
+
+/// Mirrors `JoinParams`
+pub(crate) struct JoinParams {
+    session: String,
+    static_token: String,
+    /// Whether the container is marked as the main LiveView, rather than being
+    /// the first LiveView found
+    main: bool,
+    container_id: String,
+    csrf_token: String,
+}
+
+impl JoinParams {
+    /// Finds the join parameters in `doc`, or fails listing every one that is
+    /// missing
+    pub(crate) fn extract(doc: &Document) -> Result<Self, Error> {
+        let mut main = None;
+        let mut first = None;
+        let mut csrf_token = None;
+        let mut stack = vec![doc.root()];
+        while let Some(node) = stack.pop() {
+            if let dom::Node::Element(elem) = doc.get(node) {
+                if main.is_none() && has(elem, PHX_MAIN) {
+                    main = Some(node);
+                }
+                if first.is_none() && has(elem, PHX_SESSION) {
+                    first = Some(node);
+                }
+                if csrf_token.is_none() {
+                    csrf_token = csrf_token_of(elem);
+                }
+            }
+            // Pushed in reverse, so that they are popped in document order
+            stack.extend(doc.children(node).iter().rev().copied());
+        }
+
+        let container = main.or(first);
+        let attribute = |name: &str| -> Option<String> {
+            let dom::Node::Element(elem) = doc.get(container?) else {
+                return None;
+            };
+            value_of(elem, name)
+                .filter(|value| !value.is_empty())
+                .map(str::to_owned)
+        };
+        let session = attribute(PHX_SESSION);
+        let static_token = attribute(PHX_STATIC);
+        let container_id = attribute("id");
+
+        let mut missing = vec![];
+        if container.is_none() {
+            missing.push(format!(
+                "a LiveView container, an element with `{PHX_MAIN}` or `{PHX_SESSION}`"
+            ));
+        } else {
+            if session.is_none() {
+                missing.push(format!("`{PHX_SESSION}` on the LiveView container"));
+            }
+            if static_token.is_none() {
+                missing.push(format!("`{PHX_STATIC}` on the LiveView container"));
+            }
+            if container_id.is_none() {
+                missing.push("the `id` of the LiveView container".to_owned());
+            }
+        }
+        if csrf_token.is_none() {
+            missing.push(format!(
+                "a CSRF token, in `<meta name=\"{CSRF_TOKEN}\" content=\"..\">` or \
+                 `<{CSRF_TOKEN} value=\"..\">`"
+            ));
+        }
+
+        match (session, static_token, container_id, csrf_token) {
+            (Some(session), Some(static_token), Some(container_id), Some(csrf_token)) => Ok(Self {
+                session,
+                static_token,
+                main: main.is_some(),
+                container_id,
+                csrf_token,
+            }),
+            _ => Err(Error::DeadRender {
+                kind: "MissingJoinParams".into(),
+                message: format!(
+                    "the dead render has no {}; is it a LiveView page?",
+                    missing.join(", no ")
+                ),
+            }),
+        }
+    }
+
+    /// Builds the equivalent `JoinParams` Kotlin object
+    pub(crate) fn to_java<'local>(
+        &self,
+        env: &mut JNIEnv<'local>,
+    ) -> Result<JObject<'local>, Error> {
+        let session = env.new_string(&self.session)?;
+        let static_token = env.new_string(&self.static_token)?;
+        let container_id = env.new_string(&self.container_id)?;
+        let csrf_token = env.new_string(&self.csrf_token)?;
+
+        let cache = cache::get()?;
+        // SAFETY: the arguments match `cache::JOIN_PARAMS_CONSTRUCTOR`
+        let params = unsafe {
+            env.new_object_unchecked(
+                cache::class(&cache.join_params),
+                cache.join_params_new,
+                &[
+                    JValue::Object(&session).as_jni(),
+                    JValue::Object(&static_token).as_jni(),
+                    JValue::Bool(self.main.into()).as_jni(),
+                    JValue::Object(&container_id).as_jni(),
+                    JValue::Object(&csrf_token).as_jni(),
+                ],
+            )
+        }?;
+        for string in [session, static_token, container_id, csrf_token] {
+            env.delete_local_ref(string)?;
+        }
+        Ok(params)
+    }
+}
+
+/// The CSRF token carried by `elem`, if it is a CSRF token element
+fn csrf_token_of(elem: &dom::Element) -> Option<String> {
+    let tag = elem.name.name.as_str();
+    let token = if tag.eq_ignore_ascii_case("meta") && value_of(elem, "name") == Some(CSRF_TOKEN) {
+        value_of(elem, "content")
+    } else if tag == CSRF_TOKEN {
+        value_of(elem, "value")
+    } else {
+        None
+    };
+    token.filter(|token| !token.is_empty()).map(str::to_owned)
+}
+
+fn has(elem: &dom::Element, name: &str) -> bool {
+    elem.attributes()
+        .iter()
+        .any(|attr| attr.name.namespace.is_none() && attr.name.name.as_str() == name)
+}
+
+fn value_of<'a>(elem: &'a dom::Element, name: &str) -> Option<&'a str> {
+    elem.attributes()
+        .iter()
+        .find(|attr| attr.name.namespace.is_none() && attr.name.name.as_str() == name)
+        .and_then(|attr| attr.value.as_str())
+}
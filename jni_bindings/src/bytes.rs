@@ -0,0 +1,74 @@
+//! UTF-8 input handed over as a `byte[]` or a direct `ByteBuffer`.
+//!
+//! Payloads arrive from the socket as raw bytes, so reading them in place
+//! skips building a Java `String` only to convert it back from modified UTF-8.
+//!
+//! The bytes are only lent to a decoding step, which is given no `JNIEnv`: the
+//! elements of an array are held until it returns, and Kotlin must not run in
+//! the meantime. Whatever calls back into Kotlin, like notifying a handler, is
+//! done with the decoded value, once the elements are released.
+
+use jni::{
+    objects::{JByteArray, JByteBuffer, ReleaseMode},
+    sys::jint,
+    JNIEnv,
+};
+
+use crate::error::Error;
+
+/// Calls `f` with `length` bytes of `array`, starting at `offset`, and
+/// releases the elements of the array before returning what `f` decoded
+pub(crate) fn with_array<R>(
+    env: &mut JNIEnv,
+    array: &JByteArray,
+    offset: jint,
+    length: jint,
+    f: impl FnOnce(&[u8]) -> Result<R, Error>,
+) -> Result<R, Error> {
+    let len = env.get_array_length(array)?;
+    let range = range(offset, length, len as usize)?;
+    // SAFETY: no other reference to the elements exists, and they are released
+    // without being copied back, since they are only read
+    let elements = unsafe { env.get_array_elements(array, ReleaseMode::NoCopyBack) }?;
+    // SAFETY: `jbyte` and `u8` have the same layout
+    let bytes =
+        unsafe { std::slice::from_raw_parts(elements.as_ptr() as *const u8, elements.len()) };
+    f(&bytes[range])
+}
+
+/// Calls `f` with `length` bytes of the direct `buffer`, starting at `offset`
+pub(crate) fn with_buffer<R>(
+    env: &mut JNIEnv,
+    buffer: &JByteBuffer,
+    offset: jint,
+    length: jint,
+    f: impl FnOnce(&[u8]) -> Result<R, Error>,
+) -> Result<R, Error> {
+    let address = env.get_direct_buffer_address(buffer)?;
+    let capacity = env.get_direct_buffer_capacity(buffer)?;
+    let range = range(offset, length, capacity)?;
+    // SAFETY: the buffer is direct, so its memory does not move, and the Kotlin
+    // caller keeps it reachable until the call returns
+    let bytes = unsafe { std::slice::from_raw_parts(address as *const u8, capacity) };
+    f(&bytes[range])
+}
+
+fn range(offset: jint, length: jint, capacity: usize) -> Result<std::ops::Range<usize>, Error> {
+    let start = usize::try_from(offset).ok();
+    let end = start.zip(usize::try_from(length).ok()).map(|(s, l)| s + l);
+    match (start, end) {
+        (Some(start), Some(end)) if end <= capacity => Ok(start..end),
+        _ => Err(Error::IllegalState(format!(
+            "{length} bytes at offset {offset} are out of bounds of {capacity}"
+        ))),
+    }
+}
+
+/// `bytes` as a string, or a parse error if they are not valid UTF-8
+pub(crate) fn utf8(bytes: &[u8]) -> Result<&str, Error> {
+    std::str::from_utf8(bytes).map_err(|err| Error::Parse {
+        kind: "Utf8Error".into(),
+        message: format!("failed to parse document: {err}"),
+        location: None,
+    })
+}
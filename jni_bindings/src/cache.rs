@@ -0,0 +1,362 @@
+//! Classes, methods and fields of the Kotlin side, resolved once in
+//! `JNI_OnLoad`.
+//!
+//! Looking these up by name on every call is slow, and `FindClass` only sees
+//! app classes from threads attached by the app. Everything the bindings use is
+//! resolved up front, so that a class renamed or stripped by R8 fails loudly at
+//! load time rather than on first use. Keep `core/consumer-rules.pro` in sync
+//! with this file.
+
+use std::sync::OnceLock;
+
+use jni::{
+    objects::{GlobalRef, JClass, JFieldID, JMethodID, JStaticMethodID},
+    JNIEnv,
+};
+
+use crate::error::Error;
+
+pub(crate) const DOCUMENT_CLASS: &str = "org/phoenixframework/liveview/lib/Document";
+pub(crate) const ATTRIBUTE_CLASS: &str = "org/phoenixframework/liveview/lib/Attribute";
+pub(crate) const HANDLER_CLASS: &str =
+    "org/phoenixframework/liveview/lib/Document$Companion$Handler";
+pub(crate) const BATCH_HANDLER_CLASS: &str =
+    "org/phoenixframework/liveview/lib/Document$Companion$BatchHandler";
+pub(crate) const CHANGE_DETAILS_CLASS: &str = "org/phoenixframework/liveview/lib/ChangeDetails";
+pub(crate) const ATTRIBUTE_CHANGE_CLASS: &str = "org/phoenixframework/liveview/lib/AttributeChange";
+pub(crate) const CHANNEL_EVENT_CLASS: &str = "org/phoenixframework/liveview/lib/ChannelEvent";
+pub(crate) const JOIN_PARAMS_CLASS: &str = "org/phoenixframework/liveview/lib/JoinParams";
+
+pub(crate) const NULL_POINTER_EXCEPTION: &str = "java/lang/NullPointerException";
+pub(crate) const ILLEGAL_STATE_EXCEPTION: &str = "java/lang/IllegalStateException";
+pub(crate) const RUNTIME_EXCEPTION: &str = "java/lang/RuntimeException";
+pub(crate) const UNSATISFIED_LINK_ERROR: &str = "java/lang/UnsatisfiedLinkError";
+pub(crate) const INVALID_NODE_REF_EXCEPTION: &str =
+    "org/phoenixframework/liveview/lib/InvalidNodeRefException";
+pub(crate) const NATIVE_PANIC_EXCEPTION: &str =
+    "org/phoenixframework/liveview/lib/NativePanicException";
+pub(crate) const PARSE_EXCEPTION: &str = "org/phoenixframework/liveview/lib/ParseException";
+pub(crate) const FRAGMENT_DECODE_EXCEPTION: &str =
+    "org/phoenixframework/liveview/lib/FragmentDecodeException";
+pub(crate) const FRAGMENT_MERGE_EXCEPTION: &str =
+    "org/phoenixframework/liveview/lib/FragmentMergeException";
+pub(crate) const RENDER_EXCEPTION: &str = "org/phoenixframework/liveview/lib/RenderException";
+pub(crate) const CHANNEL_DECODE_EXCEPTION: &str =
+    "org/phoenixframework/liveview/lib/ChannelDecodeException";
+pub(crate) const JS_COMMAND_EXCEPTION: &str =
+    "org/phoenixframework/liveview/lib/JsCommandException";
+pub(crate) const EVENT_EXCEPTION: &str = "org/phoenixframework/liveview/lib/EventException";
+pub(crate) const DEAD_RENDER_EXCEPTION: &str =
+    "org/phoenixframework/liveview/lib/DeadRenderException";
+pub(crate) const PATCH_APPLY_EXCEPTION: &str =
+    "org/phoenixframework/liveview/lib/PatchApplyException";
+
+pub(crate) const ON_HANDLE_SIGNATURE: &str =
+    "(JBIIIILorg/phoenixframework/liveview/lib/ChangeDetails;I)V";
+pub(crate) const ON_COMPONENTS_SIGNATURE: &str = "(J[I[I)V";
+pub(crate) const ON_BATCH_SIGNATURE: &str =
+    "(J[I[Lorg/phoenixframework/liveview/lib/ChangeDetails;)V";
+pub(crate) const CHANGE_DETAILS_CONSTRUCTOR: &str =
+    "([Lorg/phoenixframework/liveview/lib/AttributeChange;Ljava/lang/String;Ljava/lang/String;)V";
+pub(crate) const ATTRIBUTE_CHANGE_CONSTRUCTOR: &str =
+    "(BLjava/lang/String;Ljava/lang/String;Ljava/lang/String;Ljava/lang/String;)V";
+/// `(type, joinRef, ref, topic, event, status, to, kind, flash, payload)`
+pub(crate) const CHANNEL_EVENT_FACTORY: &str = "(B\
+    Ljava/lang/String;Ljava/lang/String;Ljava/lang/String;Ljava/lang/String;Ljava/lang/String;\
+    Ljava/lang/String;Ljava/lang/String;Ljava/lang/String;Ljava/lang/String;\
+    )Lorg/phoenixframework/liveview/lib/ChannelEvent;";
+/// `(session, staticToken, main, containerId, csrfToken)`
+pub(crate) const JOIN_PARAMS_CONSTRUCTOR: &str =
+    "(Ljava/lang/String;Ljava/lang/String;ZLjava/lang/String;Ljava/lang/String;)V";
+
+const STRING: &str = "Ljava/lang/String;";
+
+/// Every exception the bindings throw, with the signature of the constructor
+/// they are thrown with
+const EXCEPTIONS: &[(&str, &[&str])] = &[
+    (NULL_POINTER_EXCEPTION, &[STRING]),
+    (ILLEGAL_STATE_EXCEPTION, &[STRING]),
+    (RUNTIME_EXCEPTION, &[STRING]),
+    (UNSATISFIED_LINK_ERROR, &[STRING]),
+    (INVALID_NODE_REF_EXCEPTION, &["I", STRING]),
+    (NATIVE_PANIC_EXCEPTION, &[STRING, STRING]),
+    (PARSE_EXCEPTION, &[STRING, STRING, "I", "I", "I", STRING]),
+    (
+        FRAGMENT_DECODE_EXCEPTION,
+        &[STRING, STRING, STRING, "I", "I"],
+    ),
+    (FRAGMENT_MERGE_EXCEPTION, &[STRING, STRING]),
+    (RENDER_EXCEPTION, &[STRING, STRING]),
+    (CHANNEL_DECODE_EXCEPTION, &[STRING, STRING]),
+    (EVENT_EXCEPTION, &[STRING, STRING]),
+    (JS_COMMAND_EXCEPTION, &[STRING, STRING]),
+    (DEAD_RENDER_EXCEPTION, &[STRING, STRING]),
+    (PATCH_APPLY_EXCEPTION, &[STRING, STRING, "I"]),
+];
+
+static CACHE: OnceLock<Cache> = OnceLock::new();
+
+pub(crate) struct Cache {
+    pub attribute: GlobalRef,
+    /// `Attribute.nativeObject`
+    pub attribute_native_object: JFieldID,
+    /// `Handler.ffiFailurePolicy`
+    pub handler_failure_policy: JMethodID,
+    /// `Handler.ffiOnHandle`
+    pub handler_on_handle: JMethodID,
+    /// `Handler.ffiOnComponents`
+    pub handler_on_components: JMethodID,
+    pub batch_handler: GlobalRef,
+    /// `BatchHandler.ffiOnBatch`
+    pub batch_handler_on_batch: JMethodID,
+    pub change_details: GlobalRef,
+    pub change_details_new: JMethodID,
+    pub attribute_change: GlobalRef,
+    pub attribute_change_new: JMethodID,
+    pub channel_event: GlobalRef,
+    /// `ChannelEvent.ffiNew`
+    pub channel_event_new: JStaticMethodID,
+    pub join_params: GlobalRef,
+    pub join_params_new: JMethodID,
+    exceptions: Vec<Exception>,
+}
+
+pub(crate) struct Exception {
+    pub name: &'static str,
+    pub class: GlobalRef,
+    pub constructor: JMethodID,
+    pub signature: String,
+}
+
+impl Cache {
+    /// The cached exception `name`, if its constructor has `signature`
+    pub(crate) fn exception(&self, name: &str, signature: &str) -> Option<&Exception> {
+        self.exceptions
+            .iter()
+            .find(|exception| exception.name == name && exception.signature == signature)
+    }
+}
+
+/// The cache, once `JNI_OnLoad` has run
+pub(crate) fn get() -> Result<&'static Cache, Error> {
+    CACHE.get().ok_or_else(|| {
+        Error::IllegalState("liveview_native_core was loaded without running JNI_OnLoad".into())
+    })
+}
+
+/// A global reference as a class, to pass to the JNI functions
+pub(crate) fn class(global: &GlobalRef) -> &JClass<'static> {
+    <&JClass>::from(global.as_obj())
+}
+
+/// Resolves everything in the cache, or fails with the list of what is missing
+pub(crate) fn init(env: &mut JNIEnv) -> Result<(), Error> {
+    if CACHE.get().is_some() {
+        return Ok(());
+    }
+
+    let mut resolver = Resolver {
+        env,
+        missing: vec![],
+    };
+    match resolver.cache() {
+        Some(cache) if resolver.missing.is_empty() => {
+            // Another thread may have won the race, with the same result
+            let _ = CACHE.set(cache);
+            Ok(())
+        }
+        _ => Err(resolver.error()),
+    }
+}
+
+/// Resolves each `[class, method, signature]` like [`init`] does, without
+/// caching anything, so that tests can check how missing members fail. An
+/// empty method looks up the class alone.
+#[cfg(feature = "native-checks")]
+pub(crate) fn check(env: &mut JNIEnv, lookups: &[[String; 3]]) -> Result<(), Error> {
+    let mut resolver = Resolver {
+        env,
+        missing: vec![],
+    };
+    for [class_name, name, signature] in lookups {
+        let class = resolver.class(class_name);
+        if !name.is_empty() {
+            resolver.method(&class, class_name, name, signature);
+        }
+    }
+    match resolver.missing.is_empty() {
+        true => Ok(()),
+        false => Err(resolver.error()),
+    }
+}
+
+/// Looks up classes and members, recording the ones that are missing instead
+/// of stopping at the first
+struct Resolver<'a, 'local> {
+    env: &'a mut JNIEnv<'local>,
+    missing: Vec<String>,
+}
+
+impl Resolver<'_, '_> {
+    /// The error listing everything that is missing
+    fn error(&self) -> Error {
+        Error::Load(format!(
+            "liveview_native_core could not find {} (renamed, or stripped by R8?)",
+            self.missing.join(", ")
+        ))
+    }
+
+    fn cache(&mut self) -> Option<Cache> {
+        // Only checked for, since it declares most of the natives
+        let document = self.class(DOCUMENT_CLASS);
+        let attribute = self.class(ATTRIBUTE_CLASS);
+        let attribute_native_object = self.field(&attribute, ATTRIBUTE_CLASS, "nativeObject", "J");
+        let handler = self.class(HANDLER_CLASS);
+        let handler_failure_policy =
+            self.method(&handler, HANDLER_CLASS, "ffiFailurePolicy", "()B");
+        let handler_on_handle =
+            self.method(&handler, HANDLER_CLASS, "ffiOnHandle", ON_HANDLE_SIGNATURE);
+        let handler_on_components = self.method(
+            &handler,
+            HANDLER_CLASS,
+            "ffiOnComponents",
+            ON_COMPONENTS_SIGNATURE,
+        );
+        let batch_handler = self.class(BATCH_HANDLER_CLASS);
+        let batch_handler_on_batch = self.method(
+            &batch_handler,
+            BATCH_HANDLER_CLASS,
+            "ffiOnBatch",
+            ON_BATCH_SIGNATURE,
+        );
+        let change_details = self.class(CHANGE_DETAILS_CLASS);
+        let change_details_new = self.method(
+            &change_details,
+            CHANGE_DETAILS_CLASS,
+            "<init>",
+            CHANGE_DETAILS_CONSTRUCTOR,
+        );
+        let attribute_change = self.class(ATTRIBUTE_CHANGE_CLASS);
+        let attribute_change_new = self.method(
+            &attribute_change,
+            ATTRIBUTE_CHANGE_CLASS,
+            "<init>",
+            ATTRIBUTE_CHANGE_CONSTRUCTOR,
+        );
+        let channel_event = self.class(CHANNEL_EVENT_CLASS);
+        let channel_event_new = self.static_method(
+            &channel_event,
+            CHANNEL_EVENT_CLASS,
+            "ffiNew",
+            CHANNEL_EVENT_FACTORY,
+        );
+        let join_params = self.class(JOIN_PARAMS_CLASS);
+        let join_params_new = self.method(
+            &join_params,
+            JOIN_PARAMS_CLASS,
+            "<init>",
+            JOIN_PARAMS_CONSTRUCTOR,
+        );
+
+        let mut exceptions = vec![];
+        for (name, args) in EXCEPTIONS {
+            let class = self.class(name);
+            let signature = format!("({})V", args.concat());
+            let constructor = self.method(&class, name, "<init>", &signature);
+            if let (Some(class), Some(constructor)) = (class, constructor) {
+                exceptions.push(Exception {
+                    name,
+                    class,
+                    constructor,
+                    signature,
+                });
+            }
+        }
+
+        document?;
+        Some(Cache {
+            attribute: attribute?,
+            attribute_native_object: attribute_native_object?,
+            handler_failure_policy: handler_failure_policy?,
+            handler_on_handle: handler_on_handle?,
+            handler_on_components: handler_on_components?,
+            batch_handler: batch_handler?,
+            batch_handler_on_batch: batch_handler_on_batch?,
+            change_details: change_details?,
+            change_details_new: change_details_new?,
+            attribute_change: attribute_change?,
+            attribute_change_new: attribute_change_new?,
+            channel_event: channel_event?,
+            channel_event_new: channel_event_new?,
+            join_params: join_params?,
+            join_params_new: join_params_new?,
+            exceptions,
+        })
+    }
+
+    fn class(&mut self, name: &str) -> Option<GlobalRef> {
+        let result = self
+            .env
+            .find_class(name)
+            .and_then(|class| self.env.new_global_ref(class));
+        self.check(result, || format!("class {name}"))
+    }
+
+    fn method(
+        &mut self,
+        owner: &Option<GlobalRef>,
+        class_name: &str,
+        name: &str,
+        signature: &str,
+    ) -> Option<JMethodID> {
+        let result = self
+            .env
+            .get_method_id(class(owner.as_ref()?), name, signature);
+        self.check(result, || format!("method {class_name}.{name}{signature}"))
+    }
+
+    fn static_method(
+        &mut self,
+        owner: &Option<GlobalRef>,
+        class_name: &str,
+        name: &str,
+        signature: &str,
+    ) -> Option<JStaticMethodID> {
+        let result = self
+            .env
+            .get_static_method_id(class(owner.as_ref()?), name, signature);
+        self.check(result, || {
+            format!("static method {class_name}.{name}{signature}")
+        })
+    }
+
+    fn field(
+        &mut self,
+        owner: &Option<GlobalRef>,
+        class_name: &str,
+        name: &str,
+        signature: &str,
+    ) -> Option<JFieldID> {
+        let result = self
+            .env
+            .get_field_id(class(owner.as_ref()?), name, signature);
+        self.check(result, || format!("field {class_name}.{name}: {signature}"))
+    }
+
+    /// Records a failed lookup, clearing the `NoClassDefFoundError` or
+    /// `NoSuchMethodError` it left pending
+    fn check<T>(
+        &mut self,
+        result: jni::errors::Result<T>,
+        describe: impl FnOnce() -> String,
+    ) -> Option<T> {
+        match result {
+            Ok(value) => Some(value),
+            Err(_) => {
+                let _ = self.env.exception_clear();
+                self.missing.push(describe());
+                None
+            }
+        }
+    }
+}
@@ -0,0 +1,271 @@
+//! Phoenix channel messages, in the array format of the V2 serializer:
+//! `[join_ref, ref, topic, event, payload]`.
+//!
+//! Messages that carry a rendering or a diff are merged into the document, and
+//! every message is handed back to Kotlin as a typed `ChannelEvent`.
+
+use jni::{
+    objects::{JObject, JValue},
+    signature::ReturnType,
+    sys::{jbyte, jlong},
+    JNIEnv,
+};
+use serde_json::{Map, Value};
+
+use crate::{cache, error::Error, merge};
+
+/// Mirrors the subclasses of `ChannelEvent`
+enum Event {
+    /// A join reply, whose rendering was merged
+    Rendered,
+    /// A `diff` push, which was merged
+    Diff,
+    /// Any other reply, e.g. to an event push, or to a failed join. A diff in
+    /// the response was merged, and taken out of it.
+    Reply {
+        status: String,
+        response: Value,
+    },
+    Error,
+    Close,
+    Redirect {
+        to: String,
+        flash: Option<String>,
+    },
+    LiveRedirect {
+        to: String,
+        kind: Option<String>,
+        flash: Option<String>,
+    },
+    LivePatch {
+        to: String,
+        kind: Option<String>,
+    },
+    /// An event the bindings do not interpret, e.g. a `push_event`
+    Other {
+        payload: Value,
+    },
+}
+
+impl Event {
+    fn ty(&self) -> jbyte {
+        match self {
+            Self::Rendered => 0,
+            Self::Diff => 1,
+            Self::Reply { .. } => 2,
+            Self::Error => 3,
+            Self::Close => 4,
+            Self::Redirect { .. } => 5,
+            Self::LiveRedirect { .. } => 6,
+            Self::LivePatch { .. } => 7,
+            Self::Other { .. } => 8,
+        }
+    }
+}
+
+/// What to do with a message
+enum Route {
+    /// Merge the rendering of a join reply
+    Join(Value),
+    /// Merge a diff, then hand over the event: `Diff` for a `diff` push, or
+    /// the `Reply` that carried it
+    Diff(Value, Event),
+    Event(Event),
+}
+
+/// A decoded channel message
+pub(crate) struct Message {
+    join_ref: Option<String>,
+    msg_ref: Option<String>,
+    topic: String,
+    event: String,
+    payload: Value,
+}
+
+/// Merges what a channel message carries into the document, and returns the
+/// `ChannelEvent` describing it
+pub(crate) fn handle<'local>(
+    env: &mut JNIEnv<'local>,
+    // handle of the document merged into
+    this: jlong,
+    // Callback handle interface
+    interface: &JObject<'local>,
+    mut message: Message,
+) -> Result<JObject<'local>, Error> {
+    let event = match message.route()? {
+        Route::Join(rendered) => {
+            merge::merge_and_notify(env, this, interface, |doc, journal| {
+                merge::merge_rendered(doc, journal, rendered)
+            })?;
+            Event::Rendered
+        }
+        Route::Diff(diff, event) => {
+            merge::merge_and_notify(env, this, interface, |doc, journal| {
+                merge::merge_fragment_value(doc, journal, diff)
+            })?;
+            event
+        }
+        Route::Event(event) => event,
+    };
+    message.to_java(env, event)
+}
+
+impl Message {
+    pub(crate) fn decode(json: &[u8]) -> Result<Self, Error> {
+        let value: Value = serde_json::from_slice(json).map_err(|err| Error::ChannelDecode {
+            kind: format!("{:?}", err.classify()),
+            message: format!("invalid channel message: {err}"),
+        })?;
+        let Value::Array(fields) = value else {
+            return Err(shape("expected an array"));
+        };
+        let [join_ref, msg_ref, topic, event, payload]: [Value; 5] =
+            fields.try_into().map_err(|fields: Vec<Value>| {
+                shape(&format!("expected 5 fields, found {}", fields.len()))
+            })?;
+        Ok(Self {
+            join_ref: optional_string(join_ref, "join_ref")?,
+            msg_ref: optional_string(msg_ref, "ref")?,
+            topic: optional_string(topic, "topic")?.ok_or_else(|| shape("`topic` is null"))?,
+            event: optional_string(event, "event")?.ok_or_else(|| shape("`event` is null"))?,
+            payload,
+        })
+    }
+
+    fn route(&mut self) -> Result<Route, Error> {
+        let mut payload = match self.payload.take() {
+            Value::Object(payload) => payload,
+            Value::Null => Map::new(),
+            payload => {
+                return Ok(Route::Event(Event::Other { payload }));
+            }
+        };
+        let event = match self.event.as_str() {
+            "phx_reply" => {
+                let status = take_string(&mut payload, "status")?.unwrap_or_default();
+                let mut response = payload.remove("response").unwrap_or(Value::Null);
+                if status == "ok" {
+                    if let Some(rendered) = take(&mut response, "rendered") {
+                        return Ok(Route::Join(rendered));
+                    }
+                    if let Some(diff) = take(&mut response, "diff") {
+                        return Ok(Route::Diff(diff, Event::Reply { status, response }));
+                    }
+                }
+                Event::Reply { status, response }
+            }
+            "diff" => return Ok(Route::Diff(Value::Object(payload), Event::Diff)),
+            "phx_error" => Event::Error,
+            "phx_close" => Event::Close,
+            "redirect" => Event::Redirect {
+                to: required_string(&mut payload, "to")?,
+                flash: take_string(&mut payload, "flash")?,
+            },
+            "live_redirect" => Event::LiveRedirect {
+                to: required_string(&mut payload, "to")?,
+                kind: take_string(&mut payload, "kind")?,
+                flash: take_string(&mut payload, "flash")?,
+            },
+            "live_patch" => Event::LivePatch {
+                to: required_string(&mut payload, "to")?,
+                kind: take_string(&mut payload, "kind")?,
+            },
+            _ => Event::Other {
+                payload: Value::Object(payload),
+            },
+        };
+        Ok(Route::Event(event))
+    }
+
+    /// Builds the equivalent `ChannelEvent` Kotlin object
+    fn to_java<'local>(
+        &self,
+        env: &mut JNIEnv<'local>,
+        event: Event,
+    ) -> Result<JObject<'local>, Error> {
+        let ty = event.ty();
+        let (status, to, kind, flash, payload) = match event {
+            Event::Reply { status, response } => {
+                (Some(status), None, None, None, Some(response.to_string()))
+            }
+            Event::Redirect { to, flash } => (None, Some(to), None, flash, None),
+            Event::LiveRedirect { to, kind, flash } => (None, Some(to), kind, flash, None),
+            Event::LivePatch { to, kind } => (None, Some(to), kind, None, None),
+            Event::Other { payload } => (None, None, None, None, Some(payload.to_string())),
+            Event::Rendered | Event::Diff | Event::Error | Event::Close => {
+                (None, None, None, None, None)
+            }
+        };
+
+        let mut strings = vec![];
+        for value in [
+            self.join_ref.as_deref(),
+            self.msg_ref.as_deref(),
+            Some(self.topic.as_str()),
+            Some(self.event.as_str()),
+            status.as_deref(),
+            to.as_deref(),
+            kind.as_deref(),
+            flash.as_deref(),
+            payload.as_deref(),
+        ] {
+            strings.push(match value {
+                Some(value) => env.new_string(value)?.into(),
+                None => JObject::null(),
+            });
+        }
+
+        let cache = cache::get()?;
+        let mut args = vec![JValue::Byte(ty).as_jni()];
+        args.extend(strings.iter().map(|string| JValue::Object(string).as_jni()));
+        // SAFETY: the arguments match `cache::CHANNEL_EVENT_FACTORY`
+        let event = unsafe {
+            env.call_static_method_unchecked(
+                cache::class(&cache.channel_event),
+                cache.channel_event_new,
+                ReturnType::Object,
+                &args,
+            )
+        }?
+        .l()?;
+        for string in strings {
+            env.delete_local_ref(string)?;
+        }
+        Ok(event)
+    }
+}
+
+fn shape(message: &str) -> Error {
+    Error::ChannelDecode {
+        kind: "Data".into(),
+        message: format!(
+            "invalid channel message: {message}, not [join_ref, ref, topic, event, payload]"
+        ),
+    }
+}
+
+fn optional_string(value: Value, field: &str) -> Result<Option<String>, Error> {
+    match value {
+        Value::Null => Ok(None),
+        Value::String(value) => Ok(Some(value)),
+        other => Err(Error::ChannelDecode {
+            kind: "Data".into(),
+            message: format!("invalid channel message: `{field}` is not a string: {other}"),
+        }),
+    }
+}
+
+fn take(value: &mut Value, field: &str) -> Option<Value> {
+    value.as_object_mut()?.remove(field)
+}
+
+fn take_string(payload: &mut Map<String, Value>, field: &str) -> Result<Option<String>, Error> {
+    optional_string(payload.remove(field).unwrap_or(Value::Null), field)
+}
+
+fn required_string(payload: &mut Map<String, Value>, field: &str) -> Result<String, Error> {
+    take_string(payload, field)?.ok_or_else(|| Error::ChannelDecode {
+        kind: "Data".into(),
+        message: format!("invalid channel message: the payload has no `{field}`"),
+    })
+}
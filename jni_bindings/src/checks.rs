@@ -0,0 +1,98 @@
+//! Natives of the `NativeChecks` object of the Kotlin tests, which exercise
+//! failure paths that are hard to reach otherwise.
+//!
+//! They are only built with the `native-checks` feature, so that release
+//! builds do not ship a way to panic on demand, see [`natives::register`].
+
+use jni::{
+    objects::{JObject, JObjectArray, JString},
+    sys::{jboolean, jlong},
+    JNIEnv,
+};
+
+use crate::{
+    cache,
+    error::{jni_call, Error},
+    handle, natives,
+};
+
+/// Panics with `message`, to check that panics reach Kotlin as a
+/// `NativePanicException`
+pub(crate) extern "system" fn native_checks_panic<'local>(
+    mut env: JNIEnv<'local>,
+    _: JObject<'local>,
+    message: JString<'local>,
+) {
+    jni_call(&mut env, |env| {
+        let message: String = env.get_string(&message)?.into();
+        panic!("{message}")
+    })
+}
+
+/// Whether the fragment diff `json` would be merged into the document in
+/// place, to check which diffs skip rendering the fragment template
+pub(crate) extern "system" fn native_checks_merges_in_place<'local>(
+    mut env: JNIEnv<'local>,
+    _: JObject<'local>,
+    // non-null handle of Document
+    this: jlong,
+    json: JString<'local>,
+) -> jboolean {
+    jni_call(&mut env, |env| {
+        let json: String = env.get_string(&json)?.into();
+        let diff: serde_json::Value =
+            serde_json::from_str(&json).map_err(|err| Error::fragment_decode(".".into(), err))?;
+        let doc = handle::document(this)?;
+        let applies = handle::lock(&doc)?.applies_in_place(&diff);
+        Ok(applies as jboolean)
+    })
+}
+
+/// Resolves classes and methods like `JNI_OnLoad` does, to check that missing
+/// ones fail with an `UnsatisfiedLinkError` listing all of them
+pub(crate) extern "system" fn native_checks_resolve<'local>(
+    mut env: JNIEnv<'local>,
+    _: JObject<'local>,
+    // `[class, method, signature]` triples, with an empty method for a class
+    lookups: JObjectArray<'local>,
+) {
+    jni_call(&mut env, |env| {
+        let lookups = triples(env, &lookups)?;
+        cache::check(env, &lookups)
+    })
+}
+
+/// Checks natives like `JNI_OnLoad` does before registering them, to check
+/// that missing or misspelled ones fail with an `UnsatisfiedLinkError` listing
+/// all of them
+pub(crate) extern "system" fn native_checks_check_natives<'local>(
+    mut env: JNIEnv<'local>,
+    _: JObject<'local>,
+    // `[class, method, signature]` triples
+    natives: JObjectArray<'local>,
+) {
+    jni_call(&mut env, |env| {
+        let natives = triples(env, &natives)?;
+        natives::check(env, &natives)
+    })
+}
+
+/// The strings of `array`, three at a time
+fn triples(env: &mut JNIEnv, array: &JObjectArray) -> Result<Vec<[String; 3]>, Error> {
+    let len = env.get_array_length(array)?;
+    if len % 3 != 0 {
+        return Err(Error::IllegalState(format!(
+            "expected [class, method, signature] triples, found {len} strings"
+        )));
+    }
+    let mut strings = Vec::with_capacity(len as usize);
+    for i in 0..len {
+        let string = JString::from(env.get_object_array_element(array, i)?);
+        strings.push(String::from(env.get_string(&string)?));
+        env.delete_local_ref(string)?;
+    }
+    Ok(strings
+        .chunks_exact(3)
+        .map(|triple| [triple[0].clone(), triple[1].clone(), triple[2].clone()])
+        .collect())
+}
@@ -0,0 +1,181 @@
+//! What exactly a `Change` patch changed about a node.
+
+use jni::{
+    objects::{JObject, JValue},
+    sys::{jbyte, jsize},
+    JNIEnv,
+};
+use liveview_native_core::dom;
+
+use crate::{cache, error::Error};
+
+/// Mirrors `AttributeChange.Kind`
+#[derive(Clone, Copy)]
+pub(crate) enum AttributeChangeKind {
+    Added,
+    Removed,
+    Modified,
+}
+
+/// An attribute that was added, removed, or given a new value
+pub(crate) struct AttributeChange {
+    pub kind: AttributeChangeKind,
+    pub namespace: Option<String>,
+    pub name: String,
+    /// `None` if the attribute was added, or had no value
+    pub old_value: Option<String>,
+    /// `None` if the attribute was removed, or has no value
+    pub new_value: Option<String>,
+}
+
+/// The difference between the old and new state of a changed node
+#[derive(Default)]
+pub(crate) struct ChangeDetails {
+    pub attributes: Vec<AttributeChange>,
+    /// For a leaf, its text before and after the change
+    pub text: Option<(String, String)>,
+}
+
+/// An attribute flattened to owned strings, so that it can be compared
+struct Flat {
+    namespace: Option<String>,
+    name: String,
+    value: Option<String>,
+}
+
+impl From<&dom::Attribute> for Flat {
+    fn from(attr: &dom::Attribute) -> Self {
+        Self {
+            namespace: attr.name.namespace.map(|ns| ns.as_str().to_owned()),
+            name: attr.name.name.as_str().to_owned(),
+            value: attr.value.as_str().map(str::to_owned),
+        }
+    }
+}
+
+impl ChangeDetails {
+    /// Compares the state of a node before and after a merge, returning `None`
+    /// if the node changed kind and so cannot be compared
+    pub(crate) fn between(old: &dom::Node, new: &dom::Node) -> Option<Self> {
+        match (old, new) {
+            (dom::Node::Element(old), dom::Node::Element(new)) => Some(Self {
+                attributes: attribute_changes(old, new),
+                text: None,
+            }),
+            (dom::Node::Leaf(old), dom::Node::Leaf(new)) => Some(Self {
+                attributes: vec![],
+                text: Some((old.to_string(), new.to_string())),
+            }),
+            (dom::Node::Root, dom::Node::Root) => Some(Self::default()),
+            _ => None,
+        }
+    }
+
+    /// Builds the equivalent `ChangeDetails` Kotlin object
+    pub(crate) fn to_java<'local>(
+        &self,
+        env: &mut JNIEnv<'local>,
+    ) -> Result<JObject<'local>, Error> {
+        let cache = cache::get()?;
+        let attributes = env.new_object_array(
+            self.attributes.len() as jsize,
+            cache::class(&cache.attribute_change),
+            JObject::null(),
+        )?;
+        for (i, attr) in self.attributes.iter().enumerate() {
+            let namespace = optional_string(env, attr.namespace.as_deref())?;
+            let name = env.new_string(&attr.name)?;
+            let old_value = optional_string(env, attr.old_value.as_deref())?;
+            let new_value = optional_string(env, attr.new_value.as_deref())?;
+            // SAFETY: the arguments match `cache::ATTRIBUTE_CHANGE_CONSTRUCTOR`
+            let change = unsafe {
+                env.new_object_unchecked(
+                    cache::class(&cache.attribute_change),
+                    cache.attribute_change_new,
+                    &[
+                        JValue::Byte(attr.kind as jbyte).as_jni(),
+                        JValue::Object(&namespace).as_jni(),
+                        JValue::Object(&name).as_jni(),
+                        JValue::Object(&old_value).as_jni(),
+                        JValue::Object(&new_value).as_jni(),
+                    ],
+                )
+            }?;
+            env.set_object_array_element(&attributes, i as jsize, &change)?;
+            for local in [namespace, name.into(), old_value, new_value, change] {
+                env.delete_local_ref(local)?;
+            }
+        }
+
+        let (old_text, new_text) = match &self.text {
+            Some((old, new)) => (env.new_string(old)?.into(), env.new_string(new)?.into()),
+            None => (JObject::null(), JObject::null()),
+        };
+        // SAFETY: the arguments match `cache::CHANGE_DETAILS_CONSTRUCTOR`
+        let details = unsafe {
+            env.new_object_unchecked(
+                cache::class(&cache.change_details),
+                cache.change_details_new,
+                &[
+                    JValue::Object(&attributes).as_jni(),
+                    JValue::Object(&old_text).as_jni(),
+                    JValue::Object(&new_text).as_jni(),
+                ],
+            )
+        }?;
+        Ok(details)
+    }
+}
+
+fn attribute_changes(old: &dom::Element, new: &dom::Element) -> Vec<AttributeChange> {
+    let old: Vec<Flat> = old.attributes().iter().map(Flat::from).collect();
+    let new: Vec<Flat> = new.attributes().iter().map(Flat::from).collect();
+    let find = |attrs: &'_ [Flat], attr: &Flat| -> Option<usize> {
+        attrs
+            .iter()
+            .position(|a| a.namespace == attr.namespace && a.name == attr.name)
+    };
+
+    let mut changes = vec![];
+    for attr in &old {
+        match find(&new, attr) {
+            None => changes.push(AttributeChange {
+                kind: AttributeChangeKind::Removed,
+                namespace: attr.namespace.clone(),
+                name: attr.name.clone(),
+                old_value: attr.value.clone(),
+                new_value: None,
+            }),
+            Some(i) if new[i].value != attr.value => changes.push(AttributeChange {
+                kind: AttributeChangeKind::Modified,
+                namespace: attr.namespace.clone(),
+                name: attr.name.clone(),
+                old_value: attr.value.clone(),
+                new_value: new[i].value.clone(),
+            }),
+            Some(_) => (),
+        }
+    }
+    for attr in &new {
+        if find(&old, attr).is_none() {
+            changes.push(AttributeChange {
+                kind: AttributeChangeKind::Added,
+                namespace: attr.namespace.clone(),
+                name: attr.name.clone(),
+                old_value: None,
+                new_value: attr.value.clone(),
+            });
+        }
+    }
+    changes
+}
+
+fn optional_string<'local>(
+    env: &mut JNIEnv<'local>,
+    value: Option<&str>,
+) -> Result<JObject<'local>, Error> {
+    Ok(match value {
+        Some(value) => env.new_string(value)?.into(),
+        None => JObject::null(),
+    })
+}
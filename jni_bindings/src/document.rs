@@ -0,0 +1,406 @@
+use std::{
+    collections::HashMap,
+    mem,
+    ops::Deref,
+    panic::{self, AssertUnwindSafe},
+    thread::ThreadId,
+};
+
+use cranelift_entity::{EntityRef, EntitySet};
+use jni::sys::jint;
+use liveview_native_core::{
+    diff::fragment::{MergeError, Root, RootDiff},
+    dom,
+    dom::{Document, NodeRef},
+};
+
+use serde_json::Value;
+
+use crate::{
+    error::Error,
+    fragment::{ComponentChanges, Edit, FragmentState, UnknownComponent},
+    stream::{self, StreamEdit, StreamOps},
+};
+
+/// A `Document` together with the bookkeeping the bindings keep about it.
+///
+/// All mutation goes through the methods below, which keep the index of
+/// attached nodes in sync with the tree and record what they change in a
+/// [`Journal`].
+pub(crate) struct NativeDocument {
+    doc: Document,
+    /// Every node reachable from the root. Nodes that were removed or detached
+    /// by a merge stay in the arena, but are not in this set.
+    attached: EntitySet<NodeRef>,
+    /// The attribute identifying keyed siblings, whose reordering is reported
+    /// as a `Move`. `None`, the default, disables move detection.
+    key_attribute: Option<String>,
+    /// The rendered LiveView, for merging fragment diffs in place
+    fragment: FragmentState,
+    /// The thread notifying the handler of a merge of its changes, if any
+    notifying: Option<ThreadId>,
+    /// Whether an edit failed halfway through patching the tree, which was not
+    /// recorded to undo it with
+    poisoned: bool,
+}
+
+impl NativeDocument {
+    pub(crate) fn new(doc: Document) -> Self {
+        let attached = attached_nodes(&doc);
+        Self {
+            doc,
+            attached,
+            key_attribute: None,
+            fragment: FragmentState::default(),
+            notifying: None,
+            poisoned: false,
+        }
+    }
+
+    /// The thread notifying the handler of a merge of its changes, if any, in
+    /// which case the document must not be merged into
+    pub(crate) fn notifying(&self) -> Option<ThreadId> {
+        self.notifying
+    }
+
+    pub(crate) fn set_notifying(&mut self, notifying: Option<ThreadId>) {
+        self.notifying = notifying;
+    }
+
+    /// Whether an edit failed halfway and could not be undone, leaving the
+    /// document in an unknown state
+    pub(crate) fn is_poisoned(&self) -> bool {
+        self.poisoned
+    }
+
+    pub(crate) fn key_attribute(&self) -> Option<&str> {
+        self.key_attribute.as_deref()
+    }
+
+    /// Sets the attribute identifying keyed siblings, or disables move
+    /// detection with `None`
+    pub(crate) fn set_key_attribute(&mut self, key_attribute: Option<String>) {
+        self.key_attribute = key_attribute;
+    }
+
+    /// Applies `f` to the underlying document, then re-indexes it.
+    ///
+    /// As `f` may change any part of the tree, the whole tree is recorded in
+    /// `journal` first, but only if the journal needs it, see
+    /// [`Journal::record_tree`]. Otherwise the edit can not be undone once `f`
+    /// ran, so nothing after it may fail.
+    pub(crate) fn update<R>(
+        &mut self,
+        journal: &mut Journal,
+        f: impl FnOnce(&mut Document) -> R,
+    ) -> R {
+        journal.record_tree(self);
+        let result = f(&mut self.doc);
+        self.attached = attached_nodes(&self.doc);
+        result
+    }
+
+    /// Applies edits that change nodes in place. Only the edited nodes are
+    /// recorded in `journal`, and the document is not re-indexed, since such
+    /// edits never attach or detach a node.
+    pub(crate) fn apply_edits(&mut self, journal: &mut Journal, edits: &[Edit]) {
+        for edit in edits {
+            journal.record_node(self, edit.node());
+            edit.apply(&mut self.doc);
+        }
+    }
+
+    /// Merges a fragment diff into the fragment template
+    pub(crate) fn merge_fragment(
+        &mut self,
+        journal: &mut Journal,
+        fragment: RootDiff,
+    ) -> Result<(), MergeError> {
+        journal.record_template(self);
+        self.doc.merge_fragment(fragment)
+    }
+
+    /// Merges a fragment diff into the rendered LiveView, see
+    /// [`FragmentState::merge`]
+    pub(crate) fn merge_fragment_state(
+        &mut self,
+        journal: &mut Journal,
+        diff: Value,
+    ) -> Result<ComponentChanges, Error> {
+        if journal.rollback {
+            journal.record_fragment(self);
+        }
+        self.fragment.merge(diff)
+    }
+
+    pub(crate) fn fragment_mut(&mut self) -> &mut FragmentState {
+        &mut self.fragment
+    }
+
+    /// Forgets the fragment template and rendered LiveView, so that the next
+    /// fragment diff is taken as a whole new rendering
+    pub(crate) fn reset_fragment(&mut self, journal: &mut Journal) {
+        // Both are moved into the journal, rather than copied
+        let template = self.doc.fragment_template.take();
+        let fragment = mem::take(&mut self.fragment);
+        journal.template.get_or_insert(template);
+        journal.fragment.get_or_insert(fragment);
+    }
+
+    /// Applies the stream operations of a fragment diff to the document, and
+    /// gives the stream containers of `rendered` the resulting items, see
+    /// [`stream::apply`]
+    pub(crate) fn apply_streams(
+        &mut self,
+        journal: &mut Journal,
+        rendered: &mut Document,
+        ops: &StreamOps,
+    ) -> Vec<StreamEdit> {
+        journal.record_tree(self);
+        journal.record_streams(self);
+        let edits = stream::apply(&mut self.doc, rendered, ops, self.fragment.streams_mut());
+        self.attached = attached_nodes(&self.doc);
+        edits
+    }
+
+    /// The edits that apply a fragment diff in place, see
+    /// [`FragmentState::plan`]
+    pub(crate) fn plan_fragment(&mut self, diff: &Value) -> Option<Vec<Edit>> {
+        self.fragment.plan(diff, &self.doc)
+    }
+
+    /// Whether a fragment diff would be applied in place, see
+    /// [`FragmentState::applies_in_place`]
+    #[cfg(feature = "native-checks")]
+    pub(crate) fn applies_in_place(&mut self, diff: &Value) -> bool {
+        self.fragment.applies_in_place(diff, &self.doc)
+    }
+
+    /// The cid of the live component that rendered `node`, see
+    /// [`FragmentState::component_of`]
+    pub(crate) fn component_of(&mut self, node: NodeRef) -> Result<Option<i32>, UnknownComponent> {
+        self.fragment.component_of(node, &self.doc)
+    }
+
+    /// Puts back everything recorded in `journal`, leaving the document
+    /// (including its fragment template and rendered LiveView) as it was
+    /// before the edits the journal recorded
+    pub(crate) fn undo(&mut self, journal: Journal) {
+        if journal.patched && journal.tree.is_none() {
+            self.poisoned = true;
+            return;
+        }
+        if let Some((mut doc, attached)) = journal.tree {
+            // The template is recorded on its own, and left out of the tree
+            doc.fragment_template = self.doc.fragment_template.take();
+            self.doc = doc;
+            self.attached = attached;
+        }
+        for (node, old) in journal.nodes {
+            *self.doc.get_mut(node) = old;
+        }
+        if let Some(template) = journal.template {
+            self.doc.fragment_template = template;
+        }
+        match (journal.fragment, journal.streams) {
+            (Some(fragment), _) => self.fragment = fragment,
+            (None, Some(streams)) => *self.fragment.streams_mut() = streams,
+            (None, None) => (),
+        }
+        // Slot values are updated when edits are planned, before the rendered
+        // LiveView is recorded
+        self.fragment.invalidate();
+    }
+
+    /// Runs `f` as a single all-or-nothing edit, recording it in `journal`.
+    ///
+    /// If `f` fails or panics, the document is restored with the journal.
+    pub(crate) fn transaction<R, E>(
+        &mut self,
+        journal: &mut Journal,
+        f: impl FnOnce(&mut Self, &mut Journal) -> Result<R, E>,
+    ) -> Result<R, E> {
+        match panic::catch_unwind(AssertUnwindSafe(|| f(self, journal))) {
+            Ok(Ok(result)) => Ok(result),
+            Ok(Err(err)) => {
+                self.undo(mem::take(journal));
+                Err(err)
+            }
+            Err(payload) => {
+                self.undo(mem::take(journal));
+                panic::resume_unwind(payload)
+            }
+        }
+    }
+
+    /// Validates a `NodeRef` received from Kotlin against this document
+    pub(crate) fn node(&self, node_ref: jint) -> Result<NodeRef, Error> {
+        if node_ref < 0 {
+            return Err(Error::InvalidNodeRef {
+                node_ref,
+                reason: "is negative",
+            });
+        }
+
+        let node = NodeRef::new(node_ref as usize);
+        if self.attached.contains(node) {
+            Ok(node)
+        } else {
+            Err(Error::InvalidNodeRef {
+                node_ref,
+                reason: "does not exist, or was removed or detached from the document",
+            })
+        }
+    }
+}
+
+/// What an edit of a [`NativeDocument`] changed, recorded as it goes so that
+/// the edit can be undone.
+///
+/// Only what the edit touches is copied: the nodes it changes in place, or
+/// the whole tree before its first patch, and the fragment template and
+/// rendered LiveView it replaces.
+#[derive(Default)]
+pub(crate) struct Journal {
+    /// Whether the edit may be undone after it succeeded, in which case the
+    /// changes made by its last, infallible step are recorded too
+    rollback: bool,
+    /// The tree before it was patched, and its attached nodes
+    tree: Option<(Document, EntitySet<NodeRef>)>,
+    /// Whether the tree was patched without being recorded
+    patched: bool,
+    /// Nodes changed in place, before their first change
+    nodes: HashMap<NodeRef, dom::Node>,
+    template: Option<Option<Root>>,
+    fragment: Option<FragmentState>,
+    streams: Option<HashMap<String, String>>,
+}
+
+impl Journal {
+    /// A journal for an edit that is undone if it fails, and also afterwards
+    /// if `rollback` is set
+    pub(crate) fn new(rollback: bool) -> Self {
+        Self {
+            rollback,
+            ..Self::default()
+        }
+    }
+
+    /// The node as it was before the edit, if it was attached then and the
+    /// edit changed it
+    pub(crate) fn get(&self, node: NodeRef) -> Option<&dom::Node> {
+        // Nodes are only recorded until the tree is
+        if let Some(old) = self.nodes.get(&node) {
+            return Some(old);
+        }
+        let (doc, attached) = self.tree.as_ref()?;
+        attached.contains(node).then(|| doc.get(node))
+    }
+
+    /// The children of the node before the edit, if the edit patched the tree
+    pub(crate) fn children(&self, node: NodeRef) -> &[NodeRef] {
+        match &self.tree {
+            Some((doc, attached)) if attached.contains(node) => doc.children(node),
+            _ => &[],
+        }
+    }
+
+    /// Records the whole tree, if the edit may be rolled back after it
+    /// succeeded, or if moves are detected, which compares the children of
+    /// nodes before and after the edit. Edits are otherwise only undone when
+    /// they fail, which patching the tree does not, short of a bug.
+    fn record_tree(&mut self, doc: &mut NativeDocument) {
+        if self.tree.is_some() {
+            return;
+        }
+        if !self.rollback && doc.key_attribute.is_none() {
+            self.patched = true;
+            return;
+        }
+        // The template is recorded on its own, when it changes
+        let template = doc.doc.fragment_template.take();
+        self.tree = Some((doc.doc.clone(), doc.attached.clone()));
+        doc.doc.fragment_template = template;
+    }
+
+    fn record_node(&mut self, doc: &NativeDocument, node: NodeRef) {
+        // A recorded tree already holds the node as it was
+        if self.tree.is_none() {
+            self.nodes
+                .entry(node)
+                .or_insert_with(|| doc.doc.get(node).clone());
+        }
+    }
+
+    /// Records the nodes of `doc` that differ from their counterpart in
+    /// `other`, which `doc` is about to be patched into, so that what changed
+    /// about them can be described without recording the whole tree.
+    ///
+    /// Counterparts are found by position, as the diff does for nodes without
+    /// an id. Nodes that are paired up differently by the diff may be left
+    /// out, and are then changed without details.
+    pub(crate) fn record_counterparts(&mut self, doc: &NativeDocument, other: &Document) {
+        if !self.rollback && doc.key_attribute.is_none() {
+            let mut stack = vec![(doc.root(), other.root())];
+            while let Some((node, counterpart)) = stack.pop() {
+                let (old, new) = (doc.get(node), other.get(counterpart));
+                match (old, new) {
+                    (dom::Node::Element(a), dom::Node::Element(b)) if a.name == b.name => {
+                        if a.attributes != b.attributes {
+                            self.record_node(doc, node);
+                        }
+                    }
+                    (dom::Node::Leaf(a), dom::Node::Leaf(b)) if a != b => {
+                        self.record_node(doc, node);
+                        continue;
+                    }
+                    (dom::Node::Root, dom::Node::Root) => (),
+                    _ => continue,
+                }
+                stack.extend(
+                    doc.children(node)
+                        .iter()
+                        .copied()
+                        .zip(other.children(counterpart).iter().copied()),
+                );
+            }
+        }
+    }
+
+    fn record_template(&mut self, doc: &NativeDocument) {
+        if self.template.is_none() {
+            self.template = Some(doc.doc.fragment_template.clone());
+        }
+    }
+
+    fn record_fragment(&mut self, doc: &NativeDocument) {
+        if self.fragment.is_none() {
+            self.fragment = Some(doc.fragment.clone());
+        }
+    }
+
+    fn record_streams(&mut self, doc: &mut NativeDocument) {
+        if self.streams.is_none() {
+            self.streams = Some(doc.fragment.streams_mut().clone());
+        }
+    }
+}
+
+impl Deref for NativeDocument {
+    type Target = Document;
+
+    fn deref(&self) -> &Document {
+        &self.doc
+    }
+}
+
+fn attached_nodes(doc: &Document) -> EntitySet<NodeRef> {
+    let mut attached = EntitySet::new();
+    let mut stack = vec![doc.root()];
+    while let Some(node) = stack.pop() {
+        if attached.insert(node) {
+            stack.extend_from_slice(doc.children(node));
+        }
+    }
+    attached
+}
@@ -0,0 +1,390 @@
+use std::panic::{self, AssertUnwindSafe};
+
+use jni::{
+    objects::{JThrowable, JValue, JValueOwned},
+    sys::jint,
+    JNIEnv,
+};
+
+use liveview_native_core::{
+    diff::fragment::{MergeError, RenderError},
+    parser::ParseError,
+};
+
+use crate::{
+    cache,
+    handle::HandleError,
+    location::{self, SourceLocation},
+    panic::PanicReport,
+};
+
+/// Errors raised by a binding, each of which is rethrown as a Java exception
+#[derive(Debug)]
+pub(crate) enum Error {
+    Handle(HandleError),
+    /// A `NodeRef` received from Kotlin that is not attached to the document
+    InvalidNodeRef {
+        node_ref: jint,
+        reason: &'static str,
+    },
+    IllegalState(String),
+    Runtime(String),
+    Jni(jni::errors::Error),
+    /// Classes or members of the Kotlin side could not be resolved at load time
+    Load(String),
+    /// A Rust panic caught at the JNI boundary
+    Panic(PanicReport),
+    /// Markup could not be parsed into a `Document`
+    Parse {
+        kind: String,
+        message: String,
+        location: Option<SourceLocation>,
+    },
+    /// A fragment diff is not valid JSON, or does not have the shape of a `RootDiff`
+    FragmentDecode {
+        kind: String,
+        message: String,
+        path: String,
+        line: usize,
+        column: usize,
+    },
+    /// A decoded fragment diff could not be merged into the fragment template
+    FragmentMerge {
+        kind: String,
+        message: String,
+    },
+    /// The merged fragment template could not be rendered to markup
+    Render {
+        kind: String,
+        message: String,
+    },
+    /// A Phoenix channel message is not valid JSON, or not a
+    /// `[join_ref, ref, topic, event, payload]` array
+    ChannelDecode {
+        kind: String,
+        message: String,
+    },
+    /// The payload of an event push could not be built, e.g. because the
+    /// element is not bound to the event
+    Event {
+        kind: String,
+        message: String,
+    },
+    /// A binding attribute is not valid JSON, or holds commands that are
+    /// unknown or malformed
+    JsCommand {
+        kind: String,
+        message: String,
+    },
+    /// The dead render lacks a parameter needed to join the LiveView channel
+    DeadRender {
+        kind: String,
+        message: String,
+    },
+    /// A patch could not be applied to the document
+    PatchApply {
+        kind: String,
+        message: String,
+        patch_index: usize,
+    },
+}
+
+impl From<HandleError> for Error {
+    fn from(err: HandleError) -> Self {
+        Self::Handle(err)
+    }
+}
+
+impl From<jni::errors::Error> for Error {
+    fn from(err: jni::errors::Error) -> Self {
+        Self::Jni(err)
+    }
+}
+
+/// A constructor argument of a Java exception
+enum Arg {
+    Int(jint),
+    Str(String),
+}
+
+impl Arg {
+    fn signature(&self) -> &'static str {
+        match self {
+            Self::Int(_) => "I",
+            Self::Str(_) => "Ljava/lang/String;",
+        }
+    }
+}
+
+impl Error {
+    /// A parse failure of `source`, with where it most likely is. The parser
+    /// does not say, so the location is guessed, see [`location`].
+    pub(crate) fn parse(err: &ParseError, context: &str, source: &str) -> Self {
+        let location = location::locate(source);
+        let message = match &location {
+            Some(location) => format!(
+                "{context}: {err}\nThe parser does not report where; rescanning the source \
+                 suggests line {}, column {}: {}\n{}",
+                location.line, location.column, location.reason, location.excerpt
+            ),
+            None => format!(
+                "{context}: {err}\nThe parser does not report where, and rescanning the \
+                 source found nothing suspicious"
+            ),
+        };
+        Self::Parse {
+            kind: parse_kind(err).into(),
+            message,
+            location,
+        }
+    }
+
+    /// A failure to merge a fragment diff into the fragment template
+    pub(crate) fn fragment_merge(err: &MergeError) -> Self {
+        Self::FragmentMerge {
+            kind: merge_kind(err).into(),
+            message: format!("failed to merge fragment diff: {err}"),
+        }
+    }
+
+    /// A failure to render the fragment template to markup
+    pub(crate) fn render(err: &RenderError) -> Self {
+        Self::Render {
+            kind: render_kind(err).into(),
+            message: format!("failed to render fragment template: {err}"),
+        }
+    }
+
+    pub(crate) fn fragment_decode(path: String, err: serde_json::Error) -> Self {
+        Self::FragmentDecode {
+            kind: format!("{:?}", err.classify()),
+            message: format!("invalid fragment diff at `{path}`: {err}"),
+            path,
+            line: err.line(),
+            column: err.column(),
+        }
+    }
+
+    /// The exception class to throw, and the arguments of its constructor
+    fn exception(self) -> (&'static str, Vec<Arg>) {
+        match self {
+            Self::Handle(err @ HandleError::Null { .. }) => (
+                cache::NULL_POINTER_EXCEPTION,
+                vec![Arg::Str(err.to_string())],
+            ),
+            Self::Handle(err) => (
+                cache::ILLEGAL_STATE_EXCEPTION,
+                vec![Arg::Str(err.to_string())],
+            ),
+            Self::InvalidNodeRef { node_ref, reason } => (
+                cache::INVALID_NODE_REF_EXCEPTION,
+                vec![
+                    Arg::Int(node_ref),
+                    Arg::Str(format!("NodeRef {node_ref} {reason}")),
+                ],
+            ),
+            Self::IllegalState(message) => {
+                (cache::ILLEGAL_STATE_EXCEPTION, vec![Arg::Str(message)])
+            }
+            Self::Runtime(message) => (cache::RUNTIME_EXCEPTION, vec![Arg::Str(message)]),
+            Self::Jni(err) => (
+                cache::RUNTIME_EXCEPTION,
+                vec![Arg::Str(format!("JNI call failed: {err}"))],
+            ),
+            Self::Load(message) => (cache::UNSATISFIED_LINK_ERROR, vec![Arg::Str(message)]),
+            Self::Panic(PanicReport { message, backtrace }) => (
+                cache::NATIVE_PANIC_EXCEPTION,
+                vec![Arg::Str(message), Arg::Str(backtrace)],
+            ),
+            Self::Parse {
+                kind,
+                message,
+                location,
+            } => {
+                let (line, column, offset, excerpt) = match location {
+                    Some(location) => (
+                        location.line as jint,
+                        location.column as jint,
+                        location.offset as jint,
+                        location.excerpt,
+                    ),
+                    None => (0, 0, -1, String::new()),
+                };
+                (
+                    cache::PARSE_EXCEPTION,
+                    vec![
+                        Arg::Str(kind),
+                        Arg::Str(message),
+                        Arg::Int(line),
+                        Arg::Int(column),
+                        Arg::Int(offset),
+                        Arg::Str(excerpt),
+                    ],
+                )
+            }
+            Self::FragmentDecode {
+                kind,
+                message,
+                path,
+                line,
+                column,
+            } => (
+                cache::FRAGMENT_DECODE_EXCEPTION,
+                vec![
+                    Arg::Str(kind),
+                    Arg::Str(message),
+                    Arg::Str(path),
+                    Arg::Int(line as jint),
+                    Arg::Int(column as jint),
+                ],
+            ),
+            Self::FragmentMerge { kind, message } => (
+                cache::FRAGMENT_MERGE_EXCEPTION,
+                vec![Arg::Str(kind), Arg::Str(message)],
+            ),
+            Self::Render { kind, message } => (
+                cache::RENDER_EXCEPTION,
+                vec![Arg::Str(kind), Arg::Str(message)],
+            ),
+            Self::ChannelDecode { kind, message } => (
+                cache::CHANNEL_DECODE_EXCEPTION,
+                vec![Arg::Str(kind), Arg::Str(message)],
+            ),
+            Self::Event { kind, message } => (
+                cache::EVENT_EXCEPTION,
+                vec![Arg::Str(kind), Arg::Str(message)],
+            ),
+            Self::JsCommand { kind, message } => (
+                cache::JS_COMMAND_EXCEPTION,
+                vec![Arg::Str(kind), Arg::Str(message)],
+            ),
+            Self::DeadRender { kind, message } => (
+                cache::DEAD_RENDER_EXCEPTION,
+                vec![Arg::Str(kind), Arg::Str(message)],
+            ),
+            Self::PatchApply {
+                kind,
+                message,
+                patch_index,
+            } => (
+                cache::PATCH_APPLY_EXCEPTION,
+                vec![
+                    Arg::Str(kind),
+                    Arg::Str(message),
+                    Arg::Int(patch_index as jint),
+                ],
+            ),
+        }
+    }
+
+    /// Raises this error as a pending Java exception.
+    ///
+    /// If a Java exception is already pending (e.g. thrown by a callback), it is
+    /// left untouched so that it reaches the caller as is.
+    pub(crate) fn throw(self, env: &mut JNIEnv) {
+        if env.exception_check().unwrap_or(false) {
+            return;
+        }
+
+        let (class, args) = self.exception();
+        if let Err(err) = throw_new(env, class, args) {
+            log::error!("unable to throw {class}: {err}");
+        }
+    }
+}
+
+/// Constructs an exception from `args` and throws it
+fn throw_new(env: &mut JNIEnv, class: &str, args: Vec<Arg>) -> jni::errors::Result<()> {
+    let ctor = format!("({})V", args.iter().map(Arg::signature).collect::<String>());
+    let mut values = Vec::with_capacity(args.len());
+    for arg in args {
+        values.push(match arg {
+            Arg::Int(value) => JValueOwned::Int(value),
+            Arg::Str(value) => JValueOwned::Object(env.new_string(value)?.into()),
+        });
+    }
+    let values: Vec<_> = values.iter().map(|value| value.borrow()).collect();
+
+    // Exceptions are looked up by name if the cache is not available, e.g. to
+    // report that it could not be built
+    let exception = match cache::get()
+        .ok()
+        .and_then(|cache| cache.exception(class, &ctor))
+    {
+        Some(cached) => {
+            let values: Vec<_> = values.iter().map(JValue::as_jni).collect();
+            // SAFETY: the constructor was resolved with the signature of `args`
+            unsafe {
+                env.new_object_unchecked(cache::class(&cached.class), cached.constructor, &values)
+            }?
+        }
+        None => env.new_object(class, ctor, &values)?,
+    };
+    env.throw(JThrowable::from(exception))
+}
+
+// The `kind` of the exception a core error is reported as is the name of its
+// variant. They are matched exhaustively, so that a variant added to the core
+// does not go unnoticed.
+
+fn parse_kind(err: &ParseError) -> &'static str {
+    match err {
+        ParseError::Reader(_) => "Reader",
+        ParseError::Tokenizer(_) => "Tokenizer",
+    }
+}
+
+fn merge_kind(err: &MergeError) -> &'static str {
+    match err {
+        MergeError::FragmentTypeMismatch => "FragmentTypeMismatch",
+        MergeError::CreateComponentFromUpdate => "CreateComponentFromUpdate",
+        MergeError::CreateChildFromUpdateFragment => "CreateChildFromUpdateFragment",
+        MergeError::AddChildToExisting => "AddChildToExisting",
+        MergeError::StreamIDMismatch => "StreamIDMismatch",
+    }
+}
+
+fn render_kind(err: &RenderError) -> &'static str {
+    match err {
+        RenderError::NoComponents => "NoComponents",
+        RenderError::NoTemplates => "NoTemplates",
+        RenderError::TemplateNotFound(_) => "TemplateNotFound",
+        RenderError::ComponentNotFound(_) => "ComponentNotFound",
+        RenderError::MergeError(_) => "MergeError",
+        RenderError::ChildNotFoundForTemplate(_) => "ChildNotFoundForTemplate",
+        RenderError::ChildNotFoundForStatic(_) => "ChildNotFoundForStatic",
+        RenderError::CousinNotFound(_) => "CousinNotFound",
+        RenderError::SerdeError(_) => "SerdeError",
+        RenderError::ParseError(_) => "ParseError",
+    }
+}
+
+/// Runs the body of a binding, turning an `Err` or a panic into a pending Java
+/// exception.
+///
+/// Every exported function goes through here, since unwinding across the
+/// `extern "system"` boundary would abort the whole app. The returned default
+/// is never observed by Kotlin, since the exception is raised as soon as the
+/// native call returns.
+pub(crate) fn jni_call<'local, T: Default>(
+    env: &mut JNIEnv<'local>,
+    f: impl FnOnce(&mut JNIEnv<'local>) -> Result<T, Error>,
+) -> T {
+    match catch_panic(|| f(env)) {
+        Ok(value) => value,
+        Err(err) => {
+            err.throw(env);
+            T::default()
+        }
+    }
+}
+
+/// Runs `f`, turning a panic into an `Error::Panic`
+pub(crate) fn catch_panic<T>(f: impl FnOnce() -> Result<T, Error>) -> Result<T, Error> {
+    crate::panic::ensure_hook();
+
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(result) => result,
+        Err(payload) => Err(Error::Panic(crate::panic::report(payload))),
+    }
+}
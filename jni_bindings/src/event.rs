@@ -0,0 +1,379 @@
+//! Payloads of the `event` pushes sent when the user interacts with an element,
+//! built the way the LiveView JS client builds them.
+//!
+//! A click-like binding such as `phx-click` sends the `phx-value-*` attributes
+//! of the bound element. `phx-submit` and `phx-change` send the fields of the
+//! enclosing `LiveForm`, URL-encoded as `FormData` would be, so that the server
+//! decodes nested names like `user[email]` the same way. A `phx-change` also
+//! names the field that changed in `_target`.
+//!
+//! Like the JS client, `phx-target` is either a cid, or a selector whose
+//! matching elements each receive the event, in the live component that
+//! rendered them.
+
+use liveview_native_core::{
+    dom,
+    dom::{Document, NodeRef},
+};
+use serde_json::{json, Map, Value};
+
+use crate::{document::NativeDocument, error::Error, fragment::UnknownComponent};
+
+const PHX_PREFIX: &str = "phx-";
+const PHX_VALUE_PREFIX: &str = "phx-value-";
+const PHX_TARGET: &str = "phx-target";
+const FORM_TAG: &str = "LiveForm";
+const SUBMIT_BUTTON_TAG: &str = "LiveSubmitButton";
+
+/// The payloads of the `event` pushes for `binding`, e.g. `click` for
+/// `phx-click`, triggered on `node`: one per target of the event
+pub(crate) fn payloads(
+    doc: &mut NativeDocument,
+    node: NodeRef,
+    binding: &str,
+) -> Result<Vec<String>, Error> {
+    let attribute = format!("{PHX_PREFIX}{binding}");
+    let Some((bound, event)) = closest(doc, node, |elem| attribute_of(elem, &attribute)) else {
+        return Err(Error::Event {
+            kind: "MissingBinding".into(),
+            message: format!(
+                "no element at or above NodeRef {} has a `{attribute}` attribute",
+                node.as_u32()
+            ),
+        });
+    };
+    let event = event.to_owned();
+
+    let mut payload = Map::new();
+    match binding {
+        "submit" | "change" => {
+            let form = match binding {
+                "submit" => Some(bound),
+                _ => closest(doc, node, |elem| {
+                    (elem.name.name.as_str() == FORM_TAG).then_some(())
+                })
+                .map(|(form, _)| form),
+            };
+            let mut fields = Fields::default();
+            match form {
+                Some(form) => fields.collect(doc, form, node),
+                None => fields.add(doc.get(node)),
+            }
+            if binding == "change" {
+                if let Some(name) =
+                    element(doc.get(node)).and_then(|elem| attribute_of(elem, "name"))
+                {
+                    fields.push("_target", name);
+                }
+            }
+            payload.insert("type".into(), "form".into());
+            payload.insert("event".into(), event.into());
+            payload.insert("value".into(), fields.encoded.into());
+            match binding {
+                "submit" => payload.insert("meta".into(), meta(doc.get(bound), false).into()),
+                _ => payload.insert("uploads".into(), json!({})),
+            };
+        }
+        _ => {
+            payload.insert("type".into(), binding.into());
+            payload.insert("event".into(), event.into());
+            payload.insert("value".into(), meta(doc.get(bound), true).into());
+        }
+    }
+
+    let cids = targets(doc, bound)?;
+    Ok(cids
+        .into_iter()
+        .map(|cid| {
+            let mut payload = payload.clone();
+            if let Some(cid) = cid {
+                payload.insert("cid".into(), cid.into());
+            }
+            Value::Object(payload).to_string()
+        })
+        .collect())
+}
+
+/// The `phx-value-*` attributes of an element, and its `value` for a
+/// click-like binding
+fn meta(node: &dom::Node, with_value: bool) -> Map<String, Value> {
+    let mut meta = Map::new();
+    let Some(elem) = element(node) else {
+        return meta;
+    };
+    for attr in elem.attributes() {
+        if attr.name.namespace.is_some() {
+            continue;
+        }
+        let value = attr.value.as_str().unwrap_or_default();
+        if let Some(key) = attr.name.name.as_str().strip_prefix(PHX_VALUE_PREFIX) {
+            meta.insert(key.to_owned(), value.into());
+        } else if with_value && attr.name.name.as_str() == "value" {
+            meta.insert("value".into(), value.into());
+        }
+    }
+    meta
+}
+
+/// The cid of the live component receiving the event from `bound`, once per
+/// target: `None` for the LiveView itself
+fn targets(doc: &mut NativeDocument, bound: NodeRef) -> Result<Vec<Option<i32>>, Error> {
+    let Some(target) = element(doc.get(bound))
+        .and_then(|elem| attribute_of(elem, PHX_TARGET))
+        .map(str::to_owned)
+    else {
+        return Ok(vec![None]);
+    };
+    if let Ok(cid) = target.parse::<i32>() {
+        return Ok(vec![Some(cid)]);
+    }
+
+    let selector = Selector::parse(&target)?;
+    let mut matches = vec![];
+    let mut stack = vec![doc.root()];
+    while let Some(node) = stack.pop() {
+        if element(doc.get(node)).is_some_and(|elem| selector.matches(elem)) {
+            matches.push(node);
+        }
+        stack.extend(doc.children(node).iter().rev());
+    }
+    if matches.is_empty() {
+        return Err(Error::Event {
+            kind: "TargetNotFound".into(),
+            message: format!("nothing found matching the phx-target selector \"{target}\""),
+        });
+    }
+    let mut cids = vec![];
+    for node in matches {
+        let cid = doc
+            .component_of(node)
+            .map_err(|UnknownComponent| Error::Event {
+                kind: "TargetUnknown".into(),
+                message: format!(
+                    "an element matching the phx-target selector \"{target}\" does not match the \
+                 rendering of the LiveView, so the live component it belongs to is unknown"
+                ),
+            })?;
+        if !cids.contains(&cid) {
+            cids.push(cid);
+        }
+    }
+    Ok(cids)
+}
+
+/// The closest element at or above `node` for which `f` returns something
+fn closest<'a, T>(
+    doc: &'a Document,
+    node: NodeRef,
+    f: impl Fn(&'a dom::Element) -> Option<T>,
+) -> Option<(NodeRef, T)> {
+    let mut node = Some(node);
+    while let Some(current) = node {
+        if let Some(found) = element(doc.get(current)).and_then(&f) {
+            return Some((current, found));
+        }
+        node = doc.parent(current);
+    }
+    None
+}
+
+/// The fields of a form, URL-encoded in document order
+#[derive(Default)]
+struct Fields {
+    encoded: String,
+}
+
+impl Fields {
+    /// Adds every named field below `form`, and the submit button that
+    /// triggered the event, if any
+    fn collect(&mut self, doc: &Document, form: NodeRef, trigger: NodeRef) {
+        let submitter = closest(doc, trigger, |elem| is_submit_button(elem).then_some(()))
+            .map(|(submitter, _)| submitter);
+        let mut stack: Vec<NodeRef> = doc.children(form).iter().rev().copied().collect();
+        while let Some(node) = stack.pop() {
+            match element(doc.get(node)) {
+                // Only the button that submitted the form is sent
+                Some(elem) if is_submit_button(elem) && Some(node) != submitter => (),
+                Some(_) => self.add(doc.get(node)),
+                None => (),
+            }
+            stack.extend(doc.children(node).iter().rev());
+        }
+    }
+
+    /// Adds `node` if it is an enabled field with a name, and, if it can be
+    /// checked, checked
+    fn add(&mut self, node: &dom::Node) {
+        let Some(elem) = element(node) else {
+            return;
+        };
+        let Some(name) = attribute_of(elem, "name") else {
+            return;
+        };
+        if is_set(elem, "disabled") {
+            return;
+        }
+        let checkable = matches!(attribute_of(elem, "type"), Some("checkbox" | "radio"));
+        if checkable && !is_set(elem, "checked") {
+            return;
+        }
+        let value = attribute_of(elem, "value").unwrap_or(if checkable { "on" } else { "" });
+        self.push(name, value);
+    }
+
+    fn push(&mut self, name: &str, value: &str) {
+        if !self.encoded.is_empty() {
+            self.encoded.push('&');
+        }
+        encode(name, &mut self.encoded);
+        self.encoded.push('=');
+        encode(value, &mut self.encoded);
+    }
+}
+
+/// `application/x-www-form-urlencoded`, as `URLSearchParams` encodes it
+fn encode(s: &str, out: &mut String) {
+    for byte in s.bytes() {
+        match byte {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'*' | b'-' | b'.' | b'_' => {
+                out.push(byte as char)
+            }
+            b' ' => out.push('+'),
+            _ => out.push_str(&format!("%{byte:02X}")),
+        }
+    }
+}
+
+fn is_submit_button(elem: &dom::Element) -> bool {
+    elem.name.name.as_str() == SUBMIT_BUTTON_TAG || attribute_of(elem, "type") == Some("submit")
+}
+
+/// Whether a boolean attribute is present, and not `false`
+fn is_set(elem: &dom::Element, name: &str) -> bool {
+    elem.attributes().iter().any(|attr| {
+        attr.name.namespace.is_none()
+            && attr.name.name.as_str() == name
+            && attr.value.as_str() != Some("false")
+    })
+}
+
+fn element(node: &dom::Node) -> Option<&dom::Element> {
+    match node {
+        dom::Node::Element(elem) => Some(elem),
+        _ => None,
+    }
+}
+
+fn attribute_of<'a>(elem: &'a dom::Element, name: &str) -> Option<&'a str> {
+    elem.attributes()
+        .iter()
+        .find(|attr| attr.name.namespace.is_none() && attr.name.name.as_str() == name)
+        .and_then(|attr| attr.value.as_str())
+}
+
+/// A list of compound selectors, like `Text#title.big, [data-role=item]`.
+/// Combinators and pseudo-classes are not supported.
+struct Selector {
+    alternatives: Vec<Compound>,
+}
+
+#[derive(Default)]
+struct Compound {
+    tag: Option<String>,
+    id: Option<String>,
+    classes: Vec<String>,
+    /// Attribute names, and the value they must equal, if any
+    attributes: Vec<(String, Option<String>)>,
+}
+
+impl Selector {
+    fn parse(selector: &str) -> Result<Self, Error> {
+        let unsupported = |reason: &str| Error::Event {
+            kind: "UnsupportedSelector".into(),
+            message: format!("unsupported phx-target selector \"{selector}\": {reason}"),
+        };
+        let mut alternatives = vec![];
+        for alternative in selector.split(',').map(str::trim) {
+            let mut compound = Compound::default();
+            let mut rest = alternative;
+            if rest.is_empty() {
+                return Err(unsupported("empty selector"));
+            }
+            while let Some(c) = rest.chars().next() {
+                match c {
+                    '#' | '.' => {
+                        let (name, tail) = split_name(&rest[1..]);
+                        if name.is_empty() {
+                            return Err(unsupported(&format!("`{c}` without a name")));
+                        }
+                        match c {
+                            '#' => compound.id = Some(name.to_owned()),
+                            _ => compound.classes.push(name.to_owned()),
+                        }
+                        rest = tail;
+                    }
+                    '[' => {
+                        let end = rest
+                            .find(']')
+                            .ok_or_else(|| unsupported("unterminated `[`"))?;
+                        let (name, value) = match rest[1..end].split_once('=') {
+                            Some((name, value)) => {
+                                let value = value.trim().trim_matches(|c| c == '"' || c == '\'');
+                                (name.trim(), Some(value.to_owned()))
+                            }
+                            None => (rest[1..end].trim(), None),
+                        };
+                        compound.attributes.push((name.to_owned(), value));
+                        rest = &rest[end + 1..];
+                    }
+                    _ if compound.tag.is_none() && rest.len() == alternative.len() => {
+                        let (name, tail) = split_name(rest);
+                        if name.is_empty() {
+                            return Err(unsupported(&format!("unexpected `{c}`")));
+                        }
+                        compound.tag = Some(name.to_owned());
+                        rest = tail;
+                    }
+                    _ => return Err(unsupported(&format!("unexpected `{c}`"))),
+                }
+            }
+            alternatives.push(compound);
+        }
+        Ok(Self { alternatives })
+    }
+
+    fn matches(&self, elem: &dom::Element) -> bool {
+        self.alternatives.iter().any(|compound| {
+            compound
+                .tag
+                .as_ref()
+                .map_or(true, |tag| elem.name.name.as_str() == tag)
+                && compound
+                    .id
+                    .as_ref()
+                    .map_or(true, |id| attribute_of(elem, "id") == Some(id))
+                && compound.classes.iter().all(|class| {
+                    attribute_of(elem, "class")
+                        .is_some_and(|classes| classes.split_whitespace().any(|c| c == class))
+                })
+                && compound.attributes.iter().all(|(name, value)| {
+                    let actual = elem.attributes().iter().find(|attr| {
+                        attr.name.namespace.is_none() && attr.name.name.as_str() == name
+                    });
+                    match (actual, value) {
+                        (None, _) => false,
+                        (Some(_), None) => true,
+                        (Some(attr), Some(value)) => attr.value.as_str() == Some(value),
+                    }
+                })
+        })
+    }
+}
+
+/// Splits an identifier off the start of `s`
+fn split_name(s: &str) -> (&str, &str) {
+    let end = s
+        .find(|c: char| !(c.is_alphanumeric() || c == '-' || c == '_'))
+        .unwrap_or(s.len());
+    s.split_at(end)
+}
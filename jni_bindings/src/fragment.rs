@@ -0,0 +1,966 @@
+//! A JSON model of the rendered LiveView, kept alongside the document's
+//! fragment template.
+//!
+//! Most server diffs only change a few dynamic strings. When every string a
+//! diff changes is rendered into text or into an attribute value, we patch
+//! those nodes in place instead of rendering the whole template, parsing it and
+//! diffing the result against the document. To learn where each dynamic ends
+//! up, the model is rendered once with a unique marker in place of each dynamic
+//! string, and the markers found in the parsed rendering are matched up with
+//! the nodes of the document.
+
+use std::collections::{HashMap, HashSet};
+
+use liveview_native_core::dom::{self, Document, NodeRef};
+use serde_json::{Map, Value};
+
+use crate::{
+    error::Error,
+    update::{self, Mode},
+};
+
+/// Delimits the id of a dynamic in the marked rendering. Private use
+/// characters, so they never clash with content.
+const MARK_START: char = '\u{E000}';
+const MARK_END: char = '\u{E001}';
+
+const STATICS: &str = "s";
+const DYNAMICS: &str = "d";
+const COMPONENTS: &str = "c";
+const TEMPLATES: &str = "p";
+
+/// Set by the LiveView client on the root element of each live component
+const PHX_COMPONENT: &str = "data-phx-component";
+
+/// The keys leading from the root of the rendered tree to a dynamic
+type Path = Vec<String>;
+
+/// The rendered tree of a LiveView, merged with every fragment diff
+#[derive(Clone, Default)]
+pub(crate) struct FragmentState {
+    /// `None` until the first diff
+    rendered: Option<Map<String, Value>>,
+    slots: Slots,
+    /// The DOM id of the container of each stream, by stream ref
+    streams: HashMap<String, String>,
+}
+
+#[derive(Clone, Default)]
+enum Slots {
+    /// Not built since the document was last merged in full
+    #[default]
+    Stale,
+    /// The rendering could not be matched up with the document
+    Unavailable,
+    Ready(Box<SlotIndex>),
+}
+
+/// Where the dynamic strings of the rendered tree are in the document
+#[derive(Clone)]
+struct SlotIndex {
+    /// The slot of each dynamic that can be patched in place
+    by_path: HashMap<Path, usize>,
+    /// The current, unescaped value of each slot
+    values: Vec<String>,
+    /// The target each slot is rendered into
+    slot_targets: Vec<Option<usize>>,
+    targets: Vec<Target>,
+    /// The root element of each live component, and its cid
+    components: HashMap<NodeRef, i32>,
+    /// Elements that could not be matched with the rendering, below which
+    /// the components are unknown
+    unmatched: HashSet<NodeRef>,
+}
+
+/// A text or attribute value made of static text and slots
+#[derive(Clone)]
+struct Target {
+    node: NodeRef,
+    kind: TargetKind,
+    parts: Vec<Part>,
+}
+
+#[derive(Clone)]
+enum TargetKind {
+    Text,
+    Attribute {
+        namespace: Option<String>,
+        name: String,
+    },
+}
+
+#[derive(Clone)]
+enum Part {
+    Literal(String),
+    Slot(usize),
+}
+
+/// An in-place change to a node of the document
+pub(crate) enum Edit {
+    Text {
+        node: NodeRef,
+        text: String,
+    },
+    Attribute {
+        node: NodeRef,
+        namespace: Option<String>,
+        name: String,
+        value: String,
+    },
+}
+
+/// The live component that rendered a node can not be told, as the document
+/// does not match the rendered tree there
+pub(crate) struct UnknownComponent;
+
+/// The live components touched by a fragment diff, by cid
+#[derive(Default)]
+pub(crate) struct ComponentChanges {
+    /// Re-rendered by the diff
+    pub rendered: Vec<i32>,
+    /// No longer rendered anywhere since the diff, so that the server can be
+    /// told they were destroyed
+    pub destroyed: Vec<i32>,
+}
+
+impl ComponentChanges {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.rendered.is_empty() && self.destroyed.is_empty()
+    }
+}
+
+impl Edit {
+    pub(crate) fn node(&self) -> NodeRef {
+        match self {
+            Self::Text { node, .. } | Self::Attribute { node, .. } => *node,
+        }
+    }
+
+    pub(crate) fn apply(&self, doc: &mut Document) {
+        match (self, doc.get_mut(self.node())) {
+            (Self::Text { text, .. }, dom::Node::Leaf(leaf)) => {
+                leaf.clear();
+                leaf.push_str(text);
+            }
+            (
+                Self::Attribute {
+                    namespace,
+                    name,
+                    value,
+                    ..
+                },
+                dom::Node::Element(elem),
+            ) => {
+                for attr in elem.attributes.iter_mut() {
+                    if attribute_is(attr, namespace.as_deref(), name) {
+                        attr.value = dom::AttributeValue::String(value.as_str().into());
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+impl FragmentState {
+    /// Merges a fragment diff into the rendered tree, as the LiveView client
+    /// does, and returns the live components it touched.
+    ///
+    /// Fails, leaving the rendered tree unchanged, if live components share
+    /// statics in a cycle.
+    pub(crate) fn merge(&mut self, diff: Value) -> Result<ComponentChanges, Error> {
+        let Value::Object(diff) = diff else {
+            return Ok(ComponentChanges::default());
+        };
+        let mut rendered: Vec<i32> = diff
+            .get(COMPONENTS)
+            .and_then(Value::as_object)
+            .map(|components| {
+                components
+                    .keys()
+                    .filter_map(|cid| cid.parse().ok())
+                    .collect()
+            })
+            .unwrap_or_default();
+        rendered.sort_unstable();
+        let orphaned = self.orphaned_components();
+
+        match &mut self.rendered {
+            Some(rendered) => merge_diff(rendered, diff)?,
+            None => {
+                let mut rendered = Map::new();
+                merge_diff(&mut rendered, diff)?;
+                self.rendered = Some(rendered);
+            }
+        }
+
+        let mut destroyed: Vec<i32> = self
+            .orphaned_components()
+            .difference(&orphaned)
+            .copied()
+            .collect();
+        destroyed.sort_unstable();
+        rendered.retain(|cid| !destroyed.contains(cid));
+        Ok(ComponentChanges {
+            rendered,
+            destroyed,
+        })
+    }
+
+    /// Drops live components from the rendered tree, once the server has
+    /// acknowledged they were destroyed
+    pub(crate) fn prune_components(&mut self, cids: &[i32]) {
+        let Some(Value::Object(components)) = self
+            .rendered
+            .as_mut()
+            .and_then(|rendered| rendered.get_mut(COMPONENTS))
+        else {
+            return;
+        };
+        for cid in cids {
+            components.remove(&cid.to_string());
+        }
+    }
+
+    /// The cid of the live component that rendered `node`, if any, or
+    /// `UnknownComponent` if `node` is in a part of the document that could
+    /// not be matched with the rendered tree
+    pub(crate) fn component_of(
+        &mut self,
+        node: NodeRef,
+        doc: &Document,
+    ) -> Result<Option<i32>, UnknownComponent> {
+        let has_components = self
+            .rendered
+            .as_ref()
+            .and_then(|rendered| rendered.get(COMPONENTS))
+            .and_then(Value::as_object)
+            .is_some_and(|components| !components.is_empty());
+        if !has_components {
+            return Ok(None);
+        }
+
+        let index = self.index(doc).ok_or(UnknownComponent)?;
+        let mut node = Some(node);
+        while let Some(current) = node {
+            if let Some(&cid) = index.components.get(&current) {
+                return Ok(Some(cid));
+            }
+            if index.unmatched.contains(&current) {
+                return Err(UnknownComponent);
+            }
+            node = doc.parent(current);
+        }
+        Ok(None)
+    }
+
+    /// The cids of the live components that nothing renders any more
+    fn orphaned_components(&self) -> HashSet<i32> {
+        let Some(rendered) = &self.rendered else {
+            return HashSet::new();
+        };
+        let Some(components) = rendered.get(COMPONENTS).and_then(Value::as_object) else {
+            return HashSet::new();
+        };
+
+        let mut live = HashSet::new();
+        let mut stack = vec![rendered];
+        while let Some(rendered) = stack.pop() {
+            let mut values: Vec<&Value> = rendered
+                .iter()
+                .filter(|(key, _)| key.parse::<usize>().is_ok() || *key == DYNAMICS)
+                .map(|(_, value)| value)
+                .collect();
+            while let Some(value) = values.pop() {
+                match value {
+                    Value::Number(cid) => {
+                        let component = cid
+                            .as_i64()
+                            .filter(|cid| live.insert(*cid as i32))
+                            .and_then(|cid| components.get(&cid.to_string()))
+                            .and_then(Value::as_object);
+                        stack.extend(component);
+                    }
+                    Value::Array(rows) => values.extend(rows),
+                    Value::Object(rendered) => stack.push(rendered),
+                    _ => (),
+                }
+            }
+        }
+
+        components
+            .keys()
+            .filter_map(|cid| cid.parse().ok())
+            .filter(|cid| !live.contains(cid))
+            .collect()
+    }
+
+    pub(crate) fn streams_mut(&mut self) -> &mut HashMap<String, String> {
+        &mut self.streams
+    }
+
+    /// Forgets where dynamics are in the document, after it was merged in full
+    pub(crate) fn invalidate(&mut self) {
+        self.slots = Slots::Stale;
+    }
+
+    /// Returns the edits that apply `diff` to `doc` in place, or `None` if the
+    /// diff changes more than dynamic text and attribute values.
+    ///
+    /// Must be called before the diff is [`merge`]d.
+    ///
+    /// [`merge`]: FragmentState::merge
+    pub(crate) fn plan(&mut self, diff: &Value, doc: &Document) -> Option<Vec<Edit>> {
+        let (edits, updates) = self.edits(diff, doc)?;
+        let Slots::Ready(index) = &mut self.slots else {
+            return None;
+        };
+        for (slot, value) in updates {
+            index.values[slot] = value;
+        }
+        Some(edits)
+    }
+
+    /// Whether [`plan`] would apply `diff` to `doc` in place, without updating
+    /// the slots
+    ///
+    /// [`plan`]: FragmentState::plan
+    #[cfg(feature = "native-checks")]
+    pub(crate) fn applies_in_place(&mut self, diff: &Value, doc: &Document) -> bool {
+        self.edits(diff, doc).is_some()
+    }
+
+    /// The edits that apply `diff` in place, and the new value of each slot
+    /// they change
+    fn edits(
+        &mut self,
+        diff: &Value,
+        doc: &Document,
+    ) -> Option<(Vec<Edit>, HashMap<usize, String>)> {
+        let index = self.index(doc)?;
+
+        let mut updates = HashMap::new();
+        index.collect(diff, &mut vec![], &mut updates)?;
+
+        let mut targets: Vec<usize> = updates
+            .keys()
+            .filter_map(|&slot| index.slot_targets[slot])
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        targets.sort_unstable();
+
+        let mut edits = Vec::with_capacity(targets.len());
+        for target in targets.into_iter().map(|i| &index.targets[i]) {
+            let old = target.evaluate(&index.values, &HashMap::new());
+            let new = target.evaluate(&index.values, &updates);
+            edits.push(match &target.kind {
+                TargetKind::Text => {
+                    // The parser trims and drops blank text, so a change to
+                    // either is left to a full merge
+                    let trimmed = |s: &str, f: fn(&str) -> &str| f(s).len() != s.len();
+                    if new.trim().is_empty()
+                        || trimmed(&old, str::trim_start) != trimmed(&new, str::trim_start)
+                        || trimmed(&old, str::trim_end) != trimmed(&new, str::trim_end)
+                    {
+                        return None;
+                    }
+                    Edit::Text {
+                        node: target.node,
+                        text: new,
+                    }
+                }
+                TargetKind::Attribute { namespace, name } => Edit::Attribute {
+                    node: target.node,
+                    namespace: namespace.clone(),
+                    name: name.clone(),
+                    value: new,
+                },
+            });
+        }
+
+        Some((edits, updates))
+    }
+
+    /// Where dynamics and components are in `doc`, built if the document was
+    /// merged in full since
+    fn index(&mut self, doc: &Document) -> Option<&mut SlotIndex> {
+        let rendered = self.rendered.as_ref()?;
+        if let Slots::Stale = self.slots {
+            self.slots = match SlotIndex::build(rendered, doc) {
+                Some(index) => Slots::Ready(Box::new(index)),
+                None => Slots::Unavailable,
+            };
+        }
+        match &mut self.slots {
+            Slots::Ready(index) => Some(index),
+            _ => None,
+        }
+    }
+}
+
+impl SlotIndex {
+    /// Renders `rendered` with markers, and matches the parsed rendering with
+    /// `doc`.
+    ///
+    /// Where the rendering and the document differ, only the dynamics of that
+    /// subtree are left out, and left to full merges.
+    fn build(rendered: &Map<String, Value>, doc: &Document) -> Option<Self> {
+        let mut renderer = Renderer {
+            components: rendered.get(COMPONENTS).and_then(Value::as_object),
+            templates: rendered.get(TEMPLATES).and_then(Value::as_object),
+            marks: vec![],
+        };
+        let mut html = String::new();
+        renderer.render(rendered, &mut vec![], true, &mut html)?;
+        let marked = Document::parse(&html).ok()?;
+
+        let mut targets = vec![];
+        let mut components = HashMap::new();
+        let mut unmatched = HashSet::new();
+        // Each node of the rendering, the node of the document it stands for,
+        // and whether its dynamics can be patched in place
+        let mut stack = vec![(marked.root(), doc.root(), true)];
+        while let Some((marked_node, node, slots)) = stack.pop() {
+            let marked_children = marked.children(marked_node);
+            let children = doc.children(node);
+            let mode = match marked.get(marked_node) {
+                dom::Node::Element(elem) => Mode::of(elem),
+                _ => Mode::Replace,
+            };
+
+            match (marked.get(marked_node), doc.get(node)) {
+                (dom::Node::Root, dom::Node::Root) => (),
+                (dom::Node::Leaf(text), dom::Node::Leaf(_)) => {
+                    if !slots {
+                        continue;
+                    }
+                    if let Some(parts) = text.contains(MARK_START).then(|| parts(text)).flatten() {
+                        targets.push(Target {
+                            node,
+                            kind: TargetKind::Text,
+                            parts,
+                        });
+                    }
+                }
+                (dom::Node::Element(marked_elem), dom::Node::Element(elem)) => {
+                    if marked_elem.name.name.as_str() != elem.name.name.as_str() {
+                        unmatched.insert(node);
+                        continue;
+                    }
+                    let cid = marked_elem
+                        .attributes()
+                        .iter()
+                        .find(|attr| attribute_is(attr, None, PHX_COMPONENT))
+                        .and_then(|attr| attr.value.as_str()?.parse().ok());
+                    if let Some(cid) = cid {
+                        components.insert(node, cid);
+                    }
+                    // Only the `data-*` attributes of an ignored element are
+                    // merged, which is left to a full merge
+                    let attributes = match mode {
+                        _ if !slots => &[][..],
+                        Mode::Ignore => &[][..],
+                        _ => marked_elem.attributes(),
+                    };
+                    for attr in attributes {
+                        let Some(value) = attr.value.as_str() else {
+                            continue;
+                        };
+                        let Some(parts) =
+                            value.contains(MARK_START).then(|| parts(value)).flatten()
+                        else {
+                            continue;
+                        };
+                        targets.push(Target {
+                            node,
+                            kind: TargetKind::Attribute {
+                                namespace: attr.name.namespace.map(|ns| ns.as_str().to_owned()),
+                                name: attr.name.name.as_str().to_owned(),
+                            },
+                            parts,
+                        });
+                    }
+                }
+                _ => {
+                    unmatched.insert(node);
+                    continue;
+                }
+            }
+
+            let is_element = |child: &&NodeRef| matches!(doc.get(**child), dom::Node::Element(_));
+            if mode == Mode::Replace {
+                if marked_children.len() == children.len() {
+                    stack.extend(
+                        marked_children
+                            .iter()
+                            .zip(children)
+                            .map(|(&marked_child, &child)| (marked_child, child, slots)),
+                    );
+                } else {
+                    unmatched.extend(children.iter().filter(is_element));
+                }
+                continue;
+            }
+
+            // What a `phx-update` container keeps is in the document but not
+            // in the rendering, so dynamics inside it are left to a full merge.
+            // Its children are matched by id, to still find the components
+            // among them.
+            let by_id: HashMap<&str, NodeRef> = marked_children
+                .iter()
+                .filter_map(|&child| Some((update::id_of(marked.get(child))?, child)))
+                .collect();
+            for child in children.iter().filter(is_element) {
+                match update::id_of(doc.get(*child)).and_then(|id| by_id.get(id)) {
+                    Some(&marked_child) => stack.push((marked_child, *child, false)),
+                    None => {
+                        unmatched.insert(*child);
+                    }
+                }
+            }
+        }
+
+        let (paths, values): (Vec<_>, Vec<_>) = renderer.marks.into_iter().unzip();
+        // Only trust the targets that reproduce the document as it is
+        targets.retain(|target| {
+            let known = |part: &Part| match part {
+                Part::Slot(slot) => *slot < values.len(),
+                Part::Literal(_) => true,
+            };
+            target.parts.iter().all(known) && target.matches(doc, &values)
+        });
+        let mut slot_targets = vec![None; paths.len()];
+        for (i, target) in targets.iter().enumerate() {
+            for part in &target.parts {
+                if let Part::Slot(slot) = part {
+                    slot_targets[*slot] = Some(i);
+                }
+            }
+        }
+        let by_path = paths
+            .into_iter()
+            .enumerate()
+            .filter(|(slot, _)| slot_targets[*slot].is_some())
+            .map(|(slot, path)| (path, slot))
+            .collect();
+
+        Some(Self {
+            by_path,
+            values,
+            slot_targets,
+            targets,
+            components,
+            unmatched,
+        })
+    }
+
+    /// Collects the new value of every slot changed by `diff`, or `None` if it
+    /// changes anything else
+    fn collect(
+        &self,
+        diff: &Value,
+        path: &mut Path,
+        updates: &mut HashMap<usize, String>,
+    ) -> Option<()> {
+        let Value::Object(diff) = diff else {
+            return None;
+        };
+        for (key, value) in diff {
+            // Statics, comprehensions, components, titles and events all need
+            // a full merge
+            key.parse::<usize>().ok()?;
+
+            path.push(key.clone());
+            match value {
+                Value::String(raw) => {
+                    let slot = *self.by_path.get(path)?;
+                    updates.insert(slot, text_of(raw)?);
+                }
+                Value::Object(_) => self.collect(value, path, updates)?,
+                _ => return None,
+            }
+            path.pop();
+        }
+        Some(())
+    }
+}
+
+impl Target {
+    fn evaluate(&self, values: &[String], updates: &HashMap<usize, String>) -> String {
+        let mut out = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Literal(text) => out.push_str(text),
+                Part::Slot(slot) => out.push_str(updates.get(slot).unwrap_or(&values[*slot])),
+            }
+        }
+        out
+    }
+
+    /// Whether the node currently holds what the target evaluates to
+    fn matches(&self, doc: &Document, values: &[String]) -> bool {
+        let expected = self.evaluate(values, &HashMap::new());
+        match (&self.kind, doc.get(self.node)) {
+            (TargetKind::Text, dom::Node::Leaf(text)) => **text == *expected,
+            (TargetKind::Attribute { namespace, name }, dom::Node::Element(elem)) => {
+                elem.attributes()
+                    .iter()
+                    .find(|attr| attribute_is(attr, namespace.as_deref(), name))
+                    .and_then(|attr| attr.value.as_str())
+                    == Some(expected.as_str())
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Renders the rendered tree to markup, like the LiveView client does
+struct Renderer<'a> {
+    components: Option<&'a Map<String, Value>>,
+    templates: Option<&'a Map<String, Value>>,
+    /// The path and unescaped value of each dynamic rendered as a marker
+    marks: Vec<(Path, String)>,
+}
+
+impl<'a> Renderer<'a> {
+    fn render(
+        &mut self,
+        rendered: &'a Map<String, Value>,
+        path: &mut Path,
+        mark: bool,
+        out: &mut String,
+    ) -> Option<()> {
+        let templates = rendered
+            .get(TEMPLATES)
+            .and_then(Value::as_object)
+            .or(self.templates);
+        let statics = statics(rendered.get(STATICS)?, templates)?;
+
+        if let Some(rows) = rendered.get(DYNAMICS) {
+            // A comprehension renders its statics once per row, so its
+            // dynamics can not be told apart
+            let outer = std::mem::replace(&mut self.templates, templates);
+            for row in rows.as_array()? {
+                let row = row.as_array()?;
+                for (i, text) in statics.iter().enumerate() {
+                    out.push_str(text);
+                    if i + 1 < statics.len() {
+                        self.dynamic(row.get(i)?, path, false, out)?;
+                    }
+                }
+            }
+            self.templates = outer;
+            return Some(());
+        }
+
+        for (i, text) in statics.iter().enumerate() {
+            out.push_str(text);
+            if i + 1 < statics.len() {
+                let key = i.to_string();
+                let value = rendered.get(&key).unwrap_or(&Value::Null);
+                path.push(key);
+                self.dynamic(value, path, mark, out)?;
+                path.pop();
+            }
+        }
+        Some(())
+    }
+
+    fn dynamic(
+        &mut self,
+        value: &'a Value,
+        path: &mut Path,
+        mark: bool,
+        out: &mut String,
+    ) -> Option<()> {
+        match value {
+            Value::Null => Some(()),
+            Value::String(raw) => {
+                // The parser drops blank text, so a marker for a blank value
+                // would have no node to be matched with
+                match text_of(raw).filter(|text| mark && !text.trim().is_empty()) {
+                    Some(text) => {
+                        out.push(MARK_START);
+                        out.push_str(&self.marks.len().to_string());
+                        out.push(MARK_END);
+                        self.marks.push((path.clone(), text));
+                    }
+                    None => out.push_str(raw),
+                }
+                Some(())
+            }
+            // A component, by cid, tagged with its cid like the LiveView
+            // client does
+            Value::Number(cid) => {
+                let cid = cid.to_string();
+                let component = self.components?.get(&cid)?.as_object()?;
+                let mut html = String::new();
+                self.render(component, &mut vec![], false, &mut html)?;
+                tag_component(&html, &cid, out);
+                Some(())
+            }
+            Value::Object(rendered) => self.render(rendered, path, mark, out),
+            _ => None,
+        }
+    }
+}
+
+/// The statics of a rendered tree, which may refer to a shared template
+fn statics<'a>(
+    statics: &'a Value,
+    templates: Option<&'a Map<String, Value>>,
+) -> Option<Vec<&'a str>> {
+    let statics = match statics {
+        Value::Number(template) => templates?.get(&template.to_string())?,
+        statics => statics,
+    };
+    statics.as_array()?.iter().map(Value::as_str).collect()
+}
+
+/// Adds `data-phx-component` to the root element of a rendered component.
+/// Components have a single root element, the first tag that is not a comment.
+fn tag_component(html: &str, cid: &str, out: &mut String) {
+    let root = html
+        .match_indices('<')
+        .map(|(i, _)| i + 1)
+        .find(|&i| html[i..].starts_with(|c: char| c.is_ascii_alphabetic()));
+    let Some(start) = root else {
+        out.push_str(html);
+        return;
+    };
+    let end = html[start..]
+        .find(|c: char| c.is_whitespace() || c == '>' || c == '/')
+        .map_or(html.len(), |len| start + len);
+    out.push_str(&html[..end]);
+    out.push_str(&format!(" {PHX_COMPONENT}=\"{cid}\""));
+    out.push_str(&html[end..]);
+}
+
+/// Splits marked text into literal text and slots
+fn parts(text: &str) -> Option<Vec<Part>> {
+    let mut parts = vec![];
+    let mut rest = text;
+    while let Some(start) = rest.find(MARK_START) {
+        if start > 0 {
+            parts.push(Part::Literal(rest[..start].to_owned()));
+        }
+        rest = &rest[start + MARK_START.len_utf8()..];
+        let end = rest.find(MARK_END)?;
+        parts.push(Part::Slot(rest[..end].parse().ok()?));
+        rest = &rest[end + MARK_END.len_utf8()..];
+    }
+    if !rest.is_empty() {
+        parts.push(Part::Literal(rest.to_owned()));
+    }
+    Some(parts)
+}
+
+/// The text an escaped dynamic string stands for, or `None` if it contains
+/// markup.
+///
+/// Only the references that `Phoenix.HTML` escapes produce, and numeric ones
+/// every HTML tokenizer decodes the same, are decoded here. Anything else is
+/// `None` too, so that the core parser decodes it when the fragment template
+/// is rendered.
+fn text_of(raw: &str) -> Option<String> {
+    if raw.contains('<') || raw.contains(MARK_START) {
+        return None;
+    }
+
+    let mut out = String::with_capacity(raw.len());
+    let mut rest = raw;
+    while let Some(start) = rest.find('&') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start + 1..];
+        let end = rest.find(';')?;
+        let c = match &rest[..end] {
+            "amp" => '&',
+            "lt" => '<',
+            "gt" => '>',
+            "quot" => '"',
+            "apos" => '\'',
+            "nbsp" => '\u{a0}',
+            entity => {
+                let code = entity.strip_prefix('#')?;
+                let code = match code.strip_prefix(['x', 'X']) {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                    None => code.parse().ok()?,
+                };
+                // Tokenizers replace NUL, controls and C1 codes, each its own way
+                match code {
+                    0x20..=0x7e | 0xa0.. => char::from_u32(code)?,
+                    _ => return None,
+                }
+            }
+        };
+        out.push(c);
+        rest = &rest[end + 1..];
+    }
+    out.push_str(rest);
+    Some(out)
+}
+
+fn attribute_is(attr: &dom::Attribute, namespace: Option<&str>, name: &str) -> bool {
+    attr.name.namespace.map(|ns| ns.as_str()) == namespace && attr.name.name.as_str() == name
+}
+
+/// Merges `diff` into `rendered`, following `Rendered.mergeDiff` of the
+/// LiveView client.
+///
+/// Live components are resolved first, so that `rendered` is left unchanged
+/// if they can not be.
+fn merge_diff(
+    rendered: &mut Map<String, Value>,
+    mut diff: Map<String, Value>,
+) -> Result<(), Error> {
+    let new_components = diff.remove(COMPONENTS);
+    let replaced = diff.contains_key(STATICS);
+
+    let resolved = match new_components {
+        Some(Value::Object(new)) => {
+            let empty = Map::new();
+            let old = match rendered.get(COMPONENTS) {
+                Some(Value::Object(old)) if !replaced => old,
+                _ => &empty,
+            };
+            let mut resolved = Map::new();
+            for cid in new.keys() {
+                resolve_component(cid, &new, old, &mut resolved, &mut vec![])?;
+            }
+            Some(resolved)
+        }
+        _ => None,
+    };
+
+    if replaced {
+        *rendered = diff;
+    } else {
+        merge_into(rendered, diff);
+    }
+
+    let components = rendered
+        .entry(COMPONENTS)
+        .or_insert_with(|| Value::Object(Map::new()));
+    if let (Some(resolved), Value::Object(old)) = (resolved, components) {
+        old.extend(resolved);
+    }
+    Ok(())
+}
+
+fn merge_into(target: &mut Map<String, Value>, source: Map<String, Value>) {
+    for (key, value) in source {
+        match (target.get_mut(&key), value) {
+            (Some(Value::Object(target)), Value::Object(value)) if !value.contains_key(STATICS) => {
+                merge_into(target, value)
+            }
+            (_, value) => {
+                target.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Resolves the diff of a component against its previous state, or against
+/// the component it shares statics with.
+///
+/// `resolving` holds the cids whose statics are being looked up, so that
+/// components sharing statics in a cycle fail instead of recursing forever.
+fn resolve_component(
+    cid: &str,
+    new: &Map<String, Value>,
+    old: &Map<String, Value>,
+    resolved: &mut Map<String, Value>,
+    resolving: &mut Vec<String>,
+) -> Result<Option<Value>, Error> {
+    if let Some(component) = resolved.get(cid) {
+        return Ok(Some(component.clone()));
+    }
+    let Some(diff) = new.get(cid).and_then(Value::as_object) else {
+        return Ok(None);
+    };
+
+    let component = match diff.get(STATICS).and_then(Value::as_i64) {
+        // Statics shared with a new component (positive) or with the previous
+        // render of a component (negative)
+        Some(shared) => {
+            let template = if shared > 0 {
+                if resolving.iter().any(|resolving| resolving == cid) {
+                    return Err(shared_statics_cycle(cid));
+                }
+                resolving.push(cid.to_owned());
+                let template =
+                    resolve_component(&shared.to_string(), new, old, resolved, resolving)?;
+                resolving.pop();
+                template
+            } else {
+                old.get(&(-shared).to_string()).cloned()
+            };
+            let Some(template) = template.as_ref().and_then(Value::as_object) else {
+                return Ok(None);
+            };
+            let Some(statics) = template.get(STATICS) else {
+                return Ok(None);
+            };
+            let mut component = template.clone();
+            merge_into(&mut component, diff.clone());
+            component.insert(STATICS.to_owned(), statics.clone());
+            component
+        }
+        None => match old.get(cid).and_then(Value::as_object) {
+            Some(previous) if !diff.contains_key(STATICS) => {
+                let mut component = previous.clone();
+                merge_into(&mut component, diff.clone());
+                component
+            }
+            _ => diff.clone(),
+        },
+    };
+
+    let component = Value::Object(component);
+    resolved.insert(cid.to_owned(), component.clone());
+    Ok(Some(component))
+}
+
+/// Checks that no live component of a fragment diff shares statics with
+/// itself, directly or through other components, before anything is merged
+pub(crate) fn check_shared_statics(diff: &Value) -> Result<(), Error> {
+    let Some(components) = diff.get(COMPONENTS).and_then(Value::as_object) else {
+        return Ok(());
+    };
+    let shared = |cid: &str| {
+        let shared = components.get(cid)?.get(STATICS)?.as_i64()?;
+        (shared > 0).then(|| shared.to_string())
+    };
+    // Components whose statics were already followed to their end
+    let mut acyclic = HashSet::new();
+    for cid in components.keys() {
+        let mut chain = vec![cid.clone()];
+        while let Some(next) = shared(chain.last().unwrap()) {
+            if acyclic.contains(&next) {
+                break;
+            }
+            if chain.contains(&next) {
+                return Err(shared_statics_cycle(&next));
+            }
+            chain.push(next);
+        }
+        acyclic.extend(chain);
+    }
+    Ok(())
+}
+
+/// The error for a live component that shares statics with itself, directly
+/// or through other components
+fn shared_statics_cycle(cid: &str) -> Error {
+    let path = format!("{COMPONENTS}.{cid}.{STATICS}");
+    Error::FragmentDecode {
+        kind: "Data".to_owned(),
+        message: format!(
+            "invalid fragment diff at `{path}`: live component {cid} shares statics with itself"
+        ),
+        path,
+        line: 0,
+        column: 0,
+    }
+}
@@ -0,0 +1,334 @@
+//! Generational handle table.
+//!
+//! Every native object handed out to Kotlin lives in a slot of this table, and
+//! Kotlin only ever sees an opaque `jlong` handle: the slot index (plus one, so
+//! that `0` stays the null handle) in the low 32 bits and the slot generation
+//! in the high 32 bits. Freeing a slot bumps its generation, so a stale or
+//! double-freed handle is detected instead of dereferenced.
+
+use std::{
+    cell::RefCell,
+    fmt,
+    ops::{Deref, DerefMut},
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use crate::{
+    document::NativeDocument,
+    node::{Attribute, Element, Node},
+};
+use jni::sys::jlong;
+
+/// A `Document` shared between its owning Kotlin wrapper and any borrowed
+/// wrappers handed to callbacks.
+pub(crate) type SharedDocument = Arc<Mutex<NativeDocument>>;
+
+static HANDLES: Mutex<HandleTable> = Mutex::new(HandleTable::new());
+
+thread_local! {
+    /// The documents borrowed by the current thread
+    static BORROWED: RefCell<Vec<*const Mutex<NativeDocument>>> = const { RefCell::new(Vec::new()) };
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Kind {
+    Document,
+    Node,
+    Element,
+    Attribute,
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Self::Document => "Document",
+            Self::Node => "Node",
+            Self::Element => "Node.Element",
+            Self::Attribute => "Attribute",
+        };
+        f.write_str(name)
+    }
+}
+
+pub(crate) enum Object {
+    Document(SharedDocument),
+    Node(Node),
+    Element(Element),
+    Attribute(Attribute),
+}
+
+impl Object {
+    fn kind(&self) -> Kind {
+        match self {
+            Self::Document(_) => Kind::Document,
+            Self::Node(_) => Kind::Node,
+            Self::Element(_) => Kind::Element,
+            Self::Attribute(_) => Kind::Attribute,
+        }
+    }
+}
+
+/// Implemented by every type that can be stored in the handle table.
+pub(crate) trait Handled: Sized {
+    const KIND: Kind;
+
+    fn into_object(self) -> Object;
+
+    fn from_object(object: &Object) -> Option<&Self>;
+
+    fn take_object(object: Object) -> Result<Self, Object>;
+}
+
+macro_rules! handled {
+    ($ty:ty, $variant:ident) => {
+        impl Handled for $ty {
+            const KIND: Kind = Kind::$variant;
+
+            fn into_object(self) -> Object {
+                Object::$variant(self)
+            }
+
+            fn from_object(object: &Object) -> Option<&Self> {
+                match object {
+                    Object::$variant(value) => Some(value),
+                    _ => None,
+                }
+            }
+
+            fn take_object(object: Object) -> Result<Self, Object> {
+                match object {
+                    Object::$variant(value) => Ok(value),
+                    other => Err(other),
+                }
+            }
+        }
+    };
+}
+
+handled!(SharedDocument, Document);
+handled!(Node, Node);
+handled!(Element, Element);
+handled!(Attribute, Attribute);
+
+#[derive(Debug)]
+pub(crate) enum HandleError {
+    /// The handle was `0`
+    Null { expected: Kind },
+    /// The slot was freed, or reused by a newer object
+    Stale { expected: Kind, handle: jlong },
+    /// The handle is live but refers to a different kind of object
+    WrongType {
+        expected: Kind,
+        found: Kind,
+        handle: jlong,
+    },
+    /// The document is already borrowed by the calling thread, which would
+    /// deadlock waiting for itself
+    Busy,
+    /// A panic in native code, or an edit that failed halfway and could not
+    /// be undone, left the document in an unknown state
+    Poisoned,
+}
+
+impl fmt::Display for HandleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Null { expected } => write!(f, "{expected} handle is null"),
+            Self::Stale { expected, handle } => write!(
+                f,
+                "{expected} handle {handle:#x} is stale: the object was already freed"
+            ),
+            Self::WrongType {
+                expected,
+                found,
+                handle,
+            } => write!(
+                f,
+                "handle {handle:#x} refers to a {found}, but a {expected} was expected"
+            ),
+            Self::Busy => f.write_str(
+                "Document is already borrowed by this thread; it cannot be accessed from a \
+                 callback of a native call that borrows it",
+            ),
+            Self::Poisoned => f.write_str(
+                "Document can no longer be used: native code panicked while it was borrowed, \
+                 or failed halfway through an edit it could not undo",
+            ),
+        }
+    }
+}
+
+struct Slot {
+    generation: u32,
+    object: Option<Object>,
+}
+
+struct HandleTable {
+    slots: Vec<Slot>,
+    free: Vec<u32>,
+}
+
+impl HandleTable {
+    const fn new() -> Self {
+        Self {
+            slots: Vec::new(),
+            free: Vec::new(),
+        }
+    }
+
+    fn insert(&mut self, object: Object) -> jlong {
+        let index = match self.free.pop() {
+            Some(index) => {
+                self.slots[index as usize].object = Some(object);
+                index
+            }
+            None => {
+                let index = u32::try_from(self.slots.len()).expect("handle table is full");
+                self.slots.push(Slot {
+                    generation: 1,
+                    object: Some(object),
+                });
+                index
+            }
+        };
+        encode(index, self.slots[index as usize].generation)
+    }
+
+    fn get(&self, handle: jlong, expected: Kind) -> Result<&Object, HandleError> {
+        let (index, generation) = decode(handle).ok_or(HandleError::Null { expected })?;
+        match self.slots.get(index as usize) {
+            Some(Slot {
+                generation: current,
+                object: Some(object),
+            }) if *current == generation => {
+                if object.kind() == expected {
+                    Ok(object)
+                } else {
+                    Err(HandleError::WrongType {
+                        expected,
+                        found: object.kind(),
+                        handle,
+                    })
+                }
+            }
+            _ => Err(HandleError::Stale { expected, handle }),
+        }
+    }
+
+    fn remove(&mut self, handle: jlong, expected: Kind) -> Result<Object, HandleError> {
+        self.get(handle, expected)?;
+        let (index, _) = decode(handle).unwrap();
+        let slot = &mut self.slots[index as usize];
+        let object = slot.object.take().unwrap();
+        slot.generation = slot.generation.wrapping_add(1).max(1);
+        self.free.push(index);
+        Ok(object)
+    }
+}
+
+fn encode(index: u32, generation: u32) -> jlong {
+    (((generation as u64) << 32) | (index as u64 + 1)) as jlong
+}
+
+fn decode(handle: jlong) -> Option<(u32, u32)> {
+    let handle = handle as u64;
+    let index = (handle & 0xFFFF_FFFF) as u32;
+    if index == 0 {
+        return None;
+    }
+    Some((index - 1, (handle >> 32) as u32))
+}
+
+fn table() -> MutexGuard<'static, HandleTable> {
+    // The table is never left in an inconsistent state, so a poisoned lock is still usable
+    HANDLES
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Moves `value` into the table and returns its new handle
+pub(crate) fn insert<T: Handled>(value: T) -> jlong {
+    table().insert(value.into_object())
+}
+
+/// Removes the object behind `handle` from the table, invalidating the handle
+pub(crate) fn remove<T: Handled>(handle: jlong) -> Result<T, HandleError> {
+    let object = table().remove(handle, T::KIND)?;
+    Ok(T::take_object(object).unwrap_or_else(|_| unreachable!()))
+}
+
+/// Runs `f` against the object behind `handle`, while the table is locked
+pub(crate) fn with<T: Handled, R>(
+    handle: jlong,
+    f: impl FnOnce(&T) -> R,
+) -> Result<R, HandleError> {
+    let table = table();
+    let object = table.get(handle, T::KIND)?;
+    Ok(f(T::from_object(object).unwrap()))
+}
+
+/// Returns the document behind `handle`, without keeping the table locked
+pub(crate) fn document(handle: jlong) -> Result<SharedDocument, HandleError> {
+    with::<SharedDocument, _>(handle, Arc::clone)
+}
+
+/// Borrows a document for the duration of a single binding call, waiting for
+/// other threads to release it.
+///
+/// Fails if the calling thread already borrows it, since waiting would then
+/// never end, or if it was poisoned: edits undo themselves when they fail,
+/// but nothing undoes one that was cut short by a panic, nor one that failed
+/// after patching a tree it did not record.
+pub(crate) fn lock(document: &SharedDocument) -> Result<DocumentGuard<'_>, HandleError> {
+    let ptr = Arc::as_ptr(document);
+    if BORROWED.with(|borrowed| borrowed.borrow().contains(&ptr)) {
+        return Err(HandleError::Busy);
+    }
+    let guard = document.lock().map_err(|_| HandleError::Poisoned)?;
+    if guard.is_poisoned() {
+        return Err(HandleError::Poisoned);
+    }
+    BORROWED.with(|borrowed| borrowed.borrow_mut().push(ptr));
+    Ok(DocumentGuard { guard, ptr })
+}
+
+/// Borrows two different documents, always in the same order, so that two
+/// threads borrowing the same pair can not deadlock
+pub(crate) fn lock_pair<'a>(
+    a: &'a SharedDocument,
+    b: &'a SharedDocument,
+) -> Result<(DocumentGuard<'a>, DocumentGuard<'a>), HandleError> {
+    if Arc::as_ptr(a) < Arc::as_ptr(b) {
+        let a = lock(a)?;
+        Ok((a, lock(b)?))
+    } else {
+        let b = lock(b)?;
+        Ok((lock(a)?, b))
+    }
+}
+
+/// A document borrowed by the current thread, see [`lock`]
+pub(crate) struct DocumentGuard<'a> {
+    guard: MutexGuard<'a, NativeDocument>,
+    ptr: *const Mutex<NativeDocument>,
+}
+
+impl Deref for DocumentGuard<'_> {
+    type Target = NativeDocument;
+
+    fn deref(&self) -> &NativeDocument {
+        &self.guard
+    }
+}
+
+impl DerefMut for DocumentGuard<'_> {
+    fn deref_mut(&mut self) -> &mut NativeDocument {
+        &mut self.guard
+    }
+}
+
+impl Drop for DocumentGuard<'_> {
+    fn drop(&mut self) {
+        BORROWED.with(|borrowed| borrowed.borrow_mut().retain(|&ptr| ptr != self.ptr));
+    }
+}
@@ -0,0 +1,598 @@
+//! `Phoenix.LiveView.JS` commands, encoded by the server into binding
+//! attributes such as `phx-click` as `[[kind, args], ..]`.
+//!
+//! Commands are decoded and checked here, and handed to Kotlin in one buffer
+//! read by `JsCommand.decode`. Every integer is big endian:
+//!
+//! ```text
+//! commands   := u32 count, command*
+//! command    := u8 kind, args
+//! str        := u32 byte_len, UTF-8 bytes
+//! str?       := i32 byte_len, or -1 if absent, UTF-8 bytes
+//! bool       := u8 0 or 1
+//! strs       := u32 count, str*
+//! to         := u8 0 (the element itself), 1 (selector), 2 (closest), or
+//!               3 (inner), then str selector unless 0
+//! transition := strs classes, strs start, strs end
+//! ```
+//!
+//! The arguments of each kind are listed on [`Command`], in order. JSON
+//! values, the `value` of a push and the `detail` of a dispatch, are sent as
+//! JSON text.
+
+use serde_json::{Map, Value};
+
+use crate::error::Error;
+
+/// The duration of a transition the server did not give one, in milliseconds
+const DEFAULT_TIME: i32 = 200;
+
+/// Mirrors the subclasses of `JsCommand`
+enum Command {
+    /// `str event, str? target, str? loading, bool page_loading, str? value`
+    Push {
+        event: String,
+        /// A cid or a selector
+        target: Option<String>,
+        loading: Option<String>,
+        page_loading: bool,
+        value: Option<Value>,
+    },
+    /// `str href, bool replace`
+    Navigate { href: String, replace: bool },
+    /// `str href, bool replace`
+    Patch { href: String, replace: bool },
+    /// `to, transition, i32 time, str? display, bool blocking`
+    Show {
+        to: To,
+        transition: Transition,
+        time: i32,
+        display: Option<String>,
+        blocking: bool,
+    },
+    /// `to, transition, i32 time, bool blocking`
+    Hide {
+        to: To,
+        transition: Transition,
+        time: i32,
+        blocking: bool,
+    },
+    /// `to, transition ins, transition outs, i32 time, str? display, bool
+    /// blocking`
+    Toggle {
+        to: To,
+        ins: Transition,
+        outs: Transition,
+        time: i32,
+        display: Option<String>,
+        blocking: bool,
+    },
+    /// `to, strs names, transition, i32 time, bool blocking`
+    AddClass {
+        to: To,
+        names: Vec<String>,
+        transition: Transition,
+        time: i32,
+        blocking: bool,
+    },
+    /// `to, strs names, transition, i32 time, bool blocking`
+    RemoveClass {
+        to: To,
+        names: Vec<String>,
+        transition: Transition,
+        time: i32,
+        blocking: bool,
+    },
+    /// `to, str name, str value`
+    SetAttr { to: To, name: String, value: String },
+    /// `to, str name`
+    RemoveAttr { to: To, name: String },
+    /// `to, str event, str? detail, bool bubbles`
+    Dispatch {
+        to: To,
+        event: String,
+        detail: Option<Value>,
+        bubbles: bool,
+    },
+    /// `to, str attr`
+    Exec { to: To, attr: String },
+    /// `to`
+    Focus { to: To },
+    /// `to, transition, i32 time, bool blocking`
+    Transition {
+        to: To,
+        transition: Transition,
+        time: i32,
+        blocking: bool,
+    },
+}
+
+/// The elements a command applies to
+enum To {
+    /// The element whose binding runs the command
+    Element,
+    /// Every element matching the selector
+    Selector(String),
+    /// The closest ancestor matching the selector
+    Closest(String),
+    /// The descendants of the element matching the selector
+    Inner(String),
+}
+
+/// Classes applied while transitioning, from `start` to `end`
+#[derive(Default)]
+struct Transition {
+    classes: Vec<String>,
+    start: Vec<String>,
+    end: Vec<String>,
+}
+
+/// Decodes the value of a binding attribute, and encodes the commands for
+/// `JsCommand.decode`
+pub(crate) fn decode(value: &str) -> Result<Vec<u8>, Error> {
+    let value: Value = serde_json::from_str(value).map_err(|err| Error::JsCommand {
+        kind: format!("{:?}", err.classify()),
+        message: format!("invalid JS commands: {err}"),
+    })?;
+    let Value::Array(commands) = value else {
+        return Err(invalid("expected an array of [kind, args] commands".into()));
+    };
+
+    let mut buf = vec![];
+    put_u32(&mut buf, commands.len());
+    for (i, command) in commands.into_iter().enumerate() {
+        Command::decode(command)
+            .map_err(|err| match err {
+                Error::JsCommand { kind, message } => Error::JsCommand {
+                    kind,
+                    message: format!("{message}, in command {i}"),
+                },
+                err => err,
+            })?
+            .encode(&mut buf);
+    }
+    Ok(buf)
+}
+
+impl Command {
+    fn decode(command: Value) -> Result<Self, Error> {
+        let Value::Array(command) = command else {
+            return Err(invalid(format!(
+                "expected a [kind, args] array, found {command}"
+            )));
+        };
+        let [kind, args]: [Value; 2] = command.try_into().map_err(|command: Vec<Value>| {
+            invalid(format!(
+                "expected a [kind, args] array, found {} elements",
+                command.len()
+            ))
+        })?;
+        let Value::String(kind) = kind else {
+            return Err(invalid(format!("the kind is not a string: {kind}")));
+        };
+        let mut args = match args {
+            Value::Object(args) => Args { kind: &kind, args },
+            Value::Null => Args {
+                kind: &kind,
+                args: Map::new(),
+            },
+            args => {
+                return Err(invalid(format!(
+                    "the arguments of `{kind}` are not an object: {args}"
+                )));
+            }
+        };
+
+        Ok(match kind.as_str() {
+            "push" => Self::Push {
+                event: args.string("event")?,
+                target: args.target()?,
+                loading: args.optional_string("loading")?,
+                page_loading: args.bool("page_loading")?,
+                value: args.json("value"),
+            },
+            "navigate" => Self::Navigate {
+                href: args.string("href")?,
+                replace: args.bool("replace")?,
+            },
+            "patch" => Self::Patch {
+                href: args.string("href")?,
+                replace: args.bool("replace")?,
+            },
+            "show" => Self::Show {
+                to: args.to()?,
+                transition: args.transition("transition")?,
+                time: args.time()?,
+                display: args.optional_string("display")?,
+                blocking: args.blocking()?,
+            },
+            "hide" => Self::Hide {
+                to: args.to()?,
+                transition: args.transition("transition")?,
+                time: args.time()?,
+                blocking: args.blocking()?,
+            },
+            "toggle" => Self::Toggle {
+                to: args.to()?,
+                ins: args.transition("ins")?,
+                outs: args.transition("outs")?,
+                time: args.time()?,
+                display: args.optional_string("display")?,
+                blocking: args.blocking()?,
+            },
+            "add_class" => Self::AddClass {
+                to: args.to()?,
+                names: args.strings("names")?,
+                transition: args.transition("transition")?,
+                time: args.time()?,
+                blocking: args.blocking()?,
+            },
+            "remove_class" => Self::RemoveClass {
+                to: args.to()?,
+                names: args.strings("names")?,
+                transition: args.transition("transition")?,
+                time: args.time()?,
+                blocking: args.blocking()?,
+            },
+            "set_attr" => {
+                let to = args.to()?;
+                let [name, value]: [String; 2] =
+                    args.strings("attr")?.try_into().map_err(|_| {
+                        invalid("`attr` of `set_attr` is not a [name, value] pair".into())
+                    })?;
+                Self::SetAttr { to, name, value }
+            }
+            "remove_attr" => Self::RemoveAttr {
+                to: args.to()?,
+                name: args.string("attr")?,
+            },
+            "dispatch" => Self::Dispatch {
+                to: args.to()?,
+                event: args.string("event")?,
+                detail: args.json("detail"),
+                // Events bubble unless told otherwise, as in the JS client
+                bubbles: args.optional_bool("bubbles")?.unwrap_or(true),
+            },
+            "exec" => Self::Exec {
+                to: args.to()?,
+                attr: args.string("attr")?,
+            },
+            "focus" => Self::Focus { to: args.to()? },
+            "transition" => Self::Transition {
+                to: args.to()?,
+                transition: args.transition("transition")?,
+                time: args.time()?,
+                blocking: args.blocking()?,
+            },
+            _ => {
+                return Err(Error::JsCommand {
+                    kind: "UnknownCommand".into(),
+                    message: format!("unknown JS command `{kind}`"),
+                });
+            }
+        })
+    }
+
+    fn kind(&self) -> u8 {
+        match self {
+            Self::Push { .. } => 0,
+            Self::Navigate { .. } => 1,
+            Self::Patch { .. } => 2,
+            Self::Show { .. } => 3,
+            Self::Hide { .. } => 4,
+            Self::Toggle { .. } => 5,
+            Self::AddClass { .. } => 6,
+            Self::RemoveClass { .. } => 7,
+            Self::SetAttr { .. } => 8,
+            Self::RemoveAttr { .. } => 9,
+            Self::Dispatch { .. } => 10,
+            Self::Exec { .. } => 11,
+            Self::Focus { .. } => 12,
+            Self::Transition { .. } => 13,
+        }
+    }
+
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.push(self.kind());
+        match self {
+            Self::Push {
+                event,
+                target,
+                loading,
+                page_loading,
+                value,
+            } => {
+                put_str(buf, event);
+                put_optional_str(buf, target.as_deref());
+                put_optional_str(buf, loading.as_deref());
+                put_bool(buf, *page_loading);
+                put_optional_str(buf, value.as_ref().map(Value::to_string).as_deref());
+            }
+            Self::Navigate { href, replace } | Self::Patch { href, replace } => {
+                put_str(buf, href);
+                put_bool(buf, *replace);
+            }
+            Self::Show {
+                to,
+                transition,
+                time,
+                display,
+                blocking,
+            } => {
+                to.encode(buf);
+                transition.encode(buf);
+                put_i32(buf, *time);
+                put_optional_str(buf, display.as_deref());
+                put_bool(buf, *blocking);
+            }
+            Self::Hide {
+                to,
+                transition,
+                time,
+                blocking,
+            }
+            | Self::Transition {
+                to,
+                transition,
+                time,
+                blocking,
+            } => {
+                to.encode(buf);
+                transition.encode(buf);
+                put_i32(buf, *time);
+                put_bool(buf, *blocking);
+            }
+            Self::Toggle {
+                to,
+                ins,
+                outs,
+                time,
+                display,
+                blocking,
+            } => {
+                to.encode(buf);
+                ins.encode(buf);
+                outs.encode(buf);
+                put_i32(buf, *time);
+                put_optional_str(buf, display.as_deref());
+                put_bool(buf, *blocking);
+            }
+            Self::AddClass {
+                to,
+                names,
+                transition,
+                time,
+                blocking,
+            }
+            | Self::RemoveClass {
+                to,
+                names,
+                transition,
+                time,
+                blocking,
+            } => {
+                to.encode(buf);
+                put_strs(buf, names);
+                transition.encode(buf);
+                put_i32(buf, *time);
+                put_bool(buf, *blocking);
+            }
+            Self::SetAttr { to, name, value } => {
+                to.encode(buf);
+                put_str(buf, name);
+                put_str(buf, value);
+            }
+            Self::RemoveAttr { to, name } => {
+                to.encode(buf);
+                put_str(buf, name);
+            }
+            Self::Dispatch {
+                to,
+                event,
+                detail,
+                bubbles,
+            } => {
+                to.encode(buf);
+                put_str(buf, event);
+                put_optional_str(buf, detail.as_ref().map(Value::to_string).as_deref());
+                put_bool(buf, *bubbles);
+            }
+            Self::Exec { to, attr } => {
+                to.encode(buf);
+                put_str(buf, attr);
+            }
+            Self::Focus { to } => to.encode(buf),
+        }
+    }
+}
+
+impl To {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        let (kind, selector) = match self {
+            Self::Element => (0, None),
+            Self::Selector(selector) => (1, Some(selector)),
+            Self::Closest(selector) => (2, Some(selector)),
+            Self::Inner(selector) => (3, Some(selector)),
+        };
+        buf.push(kind);
+        if let Some(selector) = selector {
+            put_str(buf, selector);
+        }
+    }
+}
+
+impl Transition {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        put_strs(buf, &self.classes);
+        put_strs(buf, &self.start);
+        put_strs(buf, &self.end);
+    }
+}
+
+/// The arguments of a command, taken out one by one
+struct Args<'a> {
+    kind: &'a str,
+    args: Map<String, Value>,
+}
+
+impl Args<'_> {
+    fn invalid(&self, field: &str, expected: &str, found: &Value) -> Error {
+        invalid(format!(
+            "`{field}` of `{}` is not {expected}: {found}",
+            self.kind
+        ))
+    }
+
+    fn string(&mut self, field: &str) -> Result<String, Error> {
+        self.optional_string(field)?
+            .ok_or_else(|| invalid(format!("`{}` has no `{field}`", self.kind)))
+    }
+
+    fn optional_string(&mut self, field: &str) -> Result<Option<String>, Error> {
+        match self.args.remove(field) {
+            None | Some(Value::Null) => Ok(None),
+            Some(Value::String(value)) => Ok(Some(value)),
+            Some(other) => Err(self.invalid(field, "a string", &other)),
+        }
+    }
+
+    fn bool(&mut self, field: &str) -> Result<bool, Error> {
+        Ok(self.optional_bool(field)?.unwrap_or(false))
+    }
+
+    fn optional_bool(&mut self, field: &str) -> Result<Option<bool>, Error> {
+        match self.args.remove(field) {
+            None | Some(Value::Null) => Ok(None),
+            Some(Value::Bool(value)) => Ok(Some(value)),
+            Some(other) => Err(self.invalid(field, "a boolean", &other)),
+        }
+    }
+
+    fn blocking(&mut self) -> Result<bool, Error> {
+        // Transitions block the next command unless told otherwise, as in the
+        // JS client
+        Ok(self.optional_bool("blocking")?.unwrap_or(true))
+    }
+
+    fn time(&mut self) -> Result<i32, Error> {
+        match self.args.remove("time") {
+            None | Some(Value::Null) => Ok(DEFAULT_TIME),
+            Some(Value::Number(time)) if time.as_i64().is_some_and(|t| t >= 0) => {
+                Ok(time.as_i64().unwrap_or_default().min(i32::MAX as i64) as i32)
+            }
+            Some(other) => Err(self.invalid("time", "a duration in milliseconds", &other)),
+        }
+    }
+
+    fn json(&mut self, field: &str) -> Option<Value> {
+        self.args.remove(field).filter(|value| !value.is_null())
+    }
+
+    fn strings(&mut self, field: &str) -> Result<Vec<String>, Error> {
+        let value = self.args.remove(field).unwrap_or(Value::Null);
+        strings(&value).ok_or_else(|| self.invalid(field, "a list of strings", &value))
+    }
+
+    fn transition(&mut self, field: &str) -> Result<Transition, Error> {
+        let value = self.args.remove(field).unwrap_or(Value::Null);
+        let parts = match &value {
+            Value::Null => return Ok(Transition::default()),
+            Value::Array(parts) if parts.len() == 3 => parts,
+            _ => {
+                return Err(self.invalid(field, "a [classes, start, end] transition", &value));
+            }
+        };
+        match (strings(&parts[0]), strings(&parts[1]), strings(&parts[2])) {
+            (Some(classes), Some(start), Some(end)) => Ok(Transition {
+                classes,
+                start,
+                end,
+            }),
+            _ => Err(self.invalid(field, "a [classes, start, end] transition", &value)),
+        }
+    }
+
+    /// `to`, a selector, or `{"closest": selector}` or `{"inner": selector}`
+    fn to(&mut self) -> Result<To, Error> {
+        let value = self.args.remove("to").unwrap_or(Value::Null);
+        let to = match &value {
+            Value::Null => Some(To::Element),
+            Value::String(selector) => Some(To::Selector(selector.clone())),
+            Value::Object(to) => match to.iter().next() {
+                Some((kind, Value::String(selector))) if to.len() == 1 => match kind.as_str() {
+                    "closest" => Some(To::Closest(selector.clone())),
+                    "inner" => Some(To::Inner(selector.clone())),
+                    _ => None,
+                },
+                _ => None,
+            },
+            _ => None,
+        };
+        to.ok_or_else(|| {
+            self.invalid(
+                "to",
+                "a selector, or {\"closest\"|\"inner\": selector}",
+                &value,
+            )
+        })
+    }
+
+    /// The `target` of a push, a cid or a selector
+    fn target(&mut self) -> Result<Option<String>, Error> {
+        match self.args.remove("target") {
+            None | Some(Value::Null) => Ok(None),
+            Some(Value::String(selector)) => Ok(Some(selector)),
+            Some(Value::Number(cid)) if cid.is_i64() => Ok(Some(cid.to_string())),
+            Some(other) => Err(self.invalid("target", "a cid or a selector", &other)),
+        }
+    }
+}
+
+fn strings(value: &Value) -> Option<Vec<String>> {
+    match value {
+        Value::Null => Some(vec![]),
+        Value::Array(values) => values
+            .iter()
+            .map(|value| value.as_str().map(str::to_owned))
+            .collect(),
+        _ => None,
+    }
+}
+
+fn invalid(message: String) -> Error {
+    Error::JsCommand {
+        kind: "Data".into(),
+        message: format!("invalid JS commands: {message}"),
+    }
+}
+
+fn put_u32(buf: &mut Vec<u8>, value: usize) {
+    buf.extend_from_slice(&(value as u32).to_be_bytes());
+}
+
+fn put_i32(buf: &mut Vec<u8>, value: i32) {
+    buf.extend_from_slice(&value.to_be_bytes());
+}
+
+fn put_bool(buf: &mut Vec<u8>, value: bool) {
+    buf.push(value as u8);
+}
+
+fn put_str(buf: &mut Vec<u8>, value: &str) {
+    put_u32(buf, value.len());
+    buf.extend_from_slice(value.as_bytes());
+}
+
+fn put_optional_str(buf: &mut Vec<u8>, value: Option<&str>) {
+    match value {
+        Some(value) => put_str(buf, value),
+        None => put_i32(buf, -1),
+    }
+}
+
+fn put_strs(buf: &mut Vec<u8>, values: &[String]) {
+    put_u32(buf, values.len());
+    for value in values {
+        put_str(buf, value);
+    }
+}
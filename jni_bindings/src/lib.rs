@@ -1,903 +1,806 @@
-use std::hint::unreachable_unchecked;
+mod bootstrap;
+mod bytes;
+mod cache;
+mod channel;
+#[cfg(feature = "native-checks")]
+mod checks;
+mod details;
+mod document;
+mod error;
+mod event;
+mod fragment;
+mod handle;
+mod js;
+mod location;
+mod merge;
+mod natives;
+mod node;
+mod panic;
+mod snapshot;
+mod stream;
+mod symbols;
+mod update;
 
 #[cfg(target_os = "android")]
 use android_logger::Config;
-use cranelift_entity::EntityRef;
+use std::ffi::c_void;
+
 use jni::{
-    objects::{JClass, JIntArray, JObject, JObjectArray, JString, JValue},
-    sys::{jbyte, jint, jlong, jsize},
-    JNIEnv,
-};
-use liveview_native_core::{
-    diff,
-    diff::PatchResult,
-    dom,
-    dom::{Document, NodeRef},
-    ffi::{Attribute, AttributeVec, ChangeType, Element, Node, NodeData, NodeType, RustStr},
+    objects::{JByteArray, JByteBuffer, JClass, JIntArray, JObject, JObjectArray, JString, JValue},
+    sys::{jbyte, jint, jlong, jsize, JNI_ERR, JNI_VERSION_1_6},
+    JNIEnv, JavaVM,
 };
+use liveview_native_core::{dom, dom::Document};
 #[cfg(target_os = "android")]
 use log::LevelFilter;
 
-pub struct JavaResult {
-    /// Raw pointer to Document
-    pub val: jlong,
-    pub error_msg: String,
-}
+use crate::{
+    bootstrap::JoinParams,
+    document::NativeDocument,
+    error::{catch_panic, jni_call, Error},
+    fragment::UnknownComponent,
+    handle::SharedDocument,
+    node::{Attribute, Element, Node},
+};
 
+/// Resolves the Kotlin classes the bindings use, see [`cache`], and registers
+/// the bindings as their native methods, see [`natives`]. If anything is
+/// missing, `System.loadLibrary` fails with an `UnsatisfiedLinkError` listing
+/// it.
 #[no_mangle]
-pub unsafe extern "system" fn Java_org_phoenixframework_liveview_lib_Document_drop(
+pub extern "system" fn JNI_OnLoad(vm: JavaVM, _: *mut c_void) -> jint {
+    let Ok(mut env) = vm.get_env() else {
+        return JNI_ERR;
+    };
+
+    let loaded = catch_panic(|| cache::init(&mut env).and_then(|()| natives::register(&mut env)));
+    match loaded {
+        Ok(()) => JNI_VERSION_1_6,
+        Err(err) => {
+            err.throw(&mut env);
+            JNI_ERR
+        }
+    }
+}
+
+pub(crate) extern "system" fn document_drop(
     mut env: JNIEnv,
     _: JClass,
-    // non-null raw pointer to Document
+    // non-null handle of Document
     this: jlong,
 ) {
-    let this = this as *mut Document;
-
-    if this.is_null() {
-        env.throw_new(
-            "java/lang/NullPointerException",
-            "Document::drop called with `this` as null pointer",
-        )
-        .unwrap();
-    } else {
-        let _doc = Box::from_raw(this);
-    }
+    jni_call(&mut env, |_| {
+        handle::remove::<SharedDocument>(this)?;
+        Ok(())
+    })
 }
 
-#[no_mangle]
-pub extern "system" fn Java_org_phoenixframework_liveview_lib_Document_empty(
-    _env: JNIEnv,
+pub(crate) extern "system" fn document_empty(
+    mut env: JNIEnv,
     _: JClass,
-    // raw pointer to new, empty Document
+    // handle of new, empty Document
 ) -> jlong {
-    let doc = Box::new(Document::empty());
-    Box::into_raw(doc) as jlong
+    jni_call(&mut env, |_| {
+        Ok(handle::insert(SharedDocument::new(
+            NativeDocument::new(Document::empty()).into(),
+        )))
+    })
 }
 
-#[no_mangle]
-pub extern "system" fn Java_org_phoenixframework_liveview_lib_Document_00024Companion_initialize_1log<
-    'local,
->(
-    _env: JNIEnv<'local>,
+pub(crate) extern "system" fn document_companion_initialize_log<'local>(
+    mut env: JNIEnv<'local>,
     _: JClass<'local>,
 ) {
-    #[cfg(target_os = "android")]
-    android_logger::init_once(
-        Config::default()
-            .with_max_level(LevelFilter::Trace)
-            .with_tag("RustLog"),
-    );
-    log_panics::init();
-    log::error!("Logging initialised from Rust");
+    jni_call(&mut env, |_| {
+        #[cfg(target_os = "android")]
+        android_logger::init_once(
+            Config::default()
+                .with_max_level(LevelFilter::Trace)
+                .with_tag("RustLog"),
+        );
+        // `log_panics` replaces the panic hook, so ours is chained back in front
+        // of it
+        panic::init_logging(log_panics::init);
+        log::error!("Logging initialised from Rust");
+        Ok(())
+    })
 }
 
-/// Returns raw pointer to JavaResult
-#[no_mangle]
-pub extern "system" fn Java_org_phoenixframework_liveview_lib_Document_00024Companion_do_1parse<
-    'local,
->(
+/// Returns handle of the parsed Document
+pub(crate) extern "system" fn document_companion_do_parse<'local>(
     mut env: JNIEnv<'local>,
     _: JClass<'local>,
     text: JString<'local>,
-    // raw pointer to JavaResult
+    // handle of Document
 ) -> jlong {
-    let text: String = env.get_string(&text).unwrap().into();
-    let result = match Document::parse(text) {
-        Ok(doc) => {
-            let doc = Box::new(doc);
-            JavaResult {
-                val: Box::into_raw(doc) as jlong,
-                error_msg: String::new(),
-            }
-        }
-        Err(err) => JavaResult {
-            val: 0,
-            error_msg: err.to_string(),
-        },
-    };
-    let result = Box::new(result);
-    Box::into_raw(result) as jlong
+    jni_call(&mut env, |env| {
+        let text: String = env.get_string(&text)?.into();
+        parse(&text)
+    })
 }
 
-#[no_mangle]
-pub unsafe extern "system" fn Java_org_phoenixframework_liveview_lib_JavaResult_drop<'local>(
+/// Returns handle of the Document parsed from `length` bytes of UTF-8 at `offset`
+pub(crate) extern "system" fn document_companion_do_parse_bytes<'local>(
     mut env: JNIEnv<'local>,
     _: JClass<'local>,
-    // non-null raw pointer to JavaResult
-    this: jlong,
-) {
-    let this = this as *mut JavaResult;
-
-    if this.is_null() {
-        env.throw_new(
-            "java/lang/NullPointerException",
-            "JavaResult::drop called with `this` as null pointer",
-        )
-        .unwrap();
-    } else {
-        let _result = Box::from_raw(this);
-    }
+    text: JByteArray<'local>,
+    offset: jint,
+    length: jint,
+    // handle of Document
+) -> jlong {
+    jni_call(&mut env, |env| {
+        bytes::with_array(env, &text, offset, length, |text| parse(bytes::utf8(text)?))
+    })
 }
 
-/// Returns raw pointer to Document else 0
-#[no_mangle]
-pub unsafe extern "system" fn Java_org_phoenixframework_liveview_lib_JavaResult_get_1val<'local>(
+/// Returns handle of the Document parsed from `length` bytes of UTF-8 at
+/// `offset` of a direct buffer
+pub(crate) extern "system" fn document_companion_do_parse_buffer<'local>(
     mut env: JNIEnv<'local>,
     _: JClass<'local>,
-    // non-null raw pointer to JavaResult wrapping *mut Document
-    this: jlong,
-    // Raw pointer to Document
+    text: JByteBuffer<'local>,
+    offset: jint,
+    length: jint,
+    // handle of Document
 ) -> jlong {
-    let this = this as *mut JavaResult;
-
-    if this.is_null() {
-        env.throw_new(
-            "java/lang/NullPointerException",
-            "JavaResult::get_val called with `this` as null pointer",
-        )
-        .unwrap();
-
-        0
-    } else {
-        let result = &mut *this;
-        result.val as jlong
-    }
+    jni_call(&mut env, |env| {
+        bytes::with_buffer(env, &text, offset, length, |text| parse(bytes::utf8(text)?))
+    })
 }
 
-#[no_mangle]
-pub unsafe extern "system" fn Java_org_phoenixframework_liveview_lib_JavaResult_get_1error<
-    'local,
->(
-    mut env: JNIEnv<'local>,
-    _: JClass<'local>,
-    // non-null raw pointer to JavaResult
-    this: jlong,
-) -> JString<'local> {
-    let this = this as *mut JavaResult;
-
-    if this.is_null() {
-        env.throw_new(
-            "java/lang/NullPointerException",
-            "JavaResult::get_error called with `this` as null pointer",
-        )
-        .unwrap();
-
-        JObject::null().into()
-    } else {
-        let result = &*this;
-
-        env.new_string(result.error_msg.as_str())
-            .unwrap_or_else(|_| JObject::null().into())
+fn parse(text: &str) -> Result<jlong, Error> {
+    match Document::parse(text) {
+        Ok(doc) => Ok(handle::insert(SharedDocument::new(
+            NativeDocument::new(doc).into(),
+        ))),
+        Err(err) => Err(Error::parse(&err, "failed to parse document", text)),
     }
 }
 
-#[no_mangle]
-pub unsafe extern "system" fn Java_org_phoenixframework_liveview_lib_Document_do_1to_1string<
-    'local,
->(
+pub(crate) extern "system" fn document_do_to_string<'local>(
     mut env: JNIEnv<'local>,
     _: JClass<'local>,
-    // non-null raw pointer to Document
+    // non-null handle of Document
     this: jlong,
 ) -> JString<'local> {
-    let this = this as *mut Document;
-
-    if this.is_null() {
-        env.throw_new(
-            "java/lang/NullPointerException",
-            "Document::do_to_string call with `this` as null pointer",
-        )
-        .unwrap();
-
-        JObject::null().into()
-    } else {
-        let doc = &*this;
-
-        env.new_string(doc.to_string())
-            .unwrap_or_else(|_| JObject::null().into())
-    }
+    jni_call(&mut env, |env| {
+        let doc = handle::document(this)?;
+        let doc = handle::lock(&doc)?;
+        Ok(env.new_string(doc.to_string())?)
+    })
 }
 
 // Java side should ensure only u32 is passed as the node parameter
-#[no_mangle]
-pub unsafe extern "system" fn Java_org_phoenixframework_liveview_lib_Document_node_1to_1string<
-    'local,
->(
+pub(crate) extern "system" fn document_node_to_string<'local>(
     mut env: JNIEnv<'local>,
     _: JClass<'local>,
-    // non-null raw pointer to Document
+    // non-null handle of Document
     this: jlong,
-    // NodeRef is the distinct u32 key mapped to a Node (and not a Node raw pointer)
+    // NodeRef is the distinct u32 key mapped to a Node (and not a Node handle)
     node_ref: jint,
 ) -> JString<'local> {
-    let this = this as *mut Document;
-
-    if this.is_null() {
-        env.throw_new(
-            "java/lang/NullPointerException",
-            "Document::node_to_string called with `this` as null pointer",
-        )
-        .unwrap();
-
-        JObject::null().into()
-    } else {
-        let node_ref = NodeRef::new(node_ref as usize);
-        let doc = &*this;
+    jni_call(&mut env, |env| {
+        let doc = handle::document(this)?;
+        let doc = handle::lock(&doc)?;
+        let node_ref = doc.node(node_ref)?;
         let mut buf = String::new();
 
         if let Err(err) = doc.print_node(node_ref, &mut buf, dom::PrintOptions::Pretty) {
-            let message = format!("Document::node_to_string failed: {:?}", err);
-            env.throw_new("java/lang/RuntimeException", message)
-                .unwrap();
-
-            JObject::null().into()
-        } else {
-            env.new_string(buf)
-                .unwrap_or_else(|_| JObject::null().into())
+            return Err(Error::Runtime(format!(
+                "Document::node_to_string failed: {:?}",
+                err
+            )));
         }
-    }
+
+        Ok(env.new_string(buf)?)
+    })
 }
 
-#[no_mangle]
-pub unsafe extern "system" fn Java_org_phoenixframework_liveview_lib_Document_root<'local>(
+pub(crate) extern "system" fn document_root<'local>(
     mut env: JNIEnv<'local>,
     _: JClass<'local>,
-    // non-null raw pointer to Document
+    // non-null handle of Document
     this: jlong,
     // NodeRef of root
 ) -> jint {
-    let this = this as *mut Document;
-
-    if this.is_null() {
-        env.throw_new(
-            "java/lang/NullPointerException",
-            "Document::root called with `this` as null pointer",
-        )
-        .unwrap();
-
-        0
-    } else {
-        let doc = &*this;
-        doc.root().as_u32() as jint
-    }
+    jni_call(&mut env, |_| {
+        let doc = handle::document(this)?;
+        let doc = handle::lock(&doc)?;
+        Ok(doc.root().as_u32() as jint)
+    })
 }
 
-#[no_mangle]
-pub unsafe extern "system" fn Java_org_phoenixframework_liveview_lib_Document_get_1node<'local>(
+pub(crate) extern "system" fn document_get_node<'local>(
     mut env: JNIEnv<'local>,
     _: JClass<'local>,
-    // non-null raw pointer to Document
+    // non-null handle of Document
     this: jlong,
-    // NodeRef is the distinct u32 key mapped to a Node (and not a Node raw pointer)
+    // NodeRef is the distinct u32 key mapped to a Node (and not a Node handle)
     node_ref: jint,
-    // Raw pointer to Node
+    // handle of Node
 ) -> jlong {
-    let this = this as *mut Document;
-
-    if this.is_null() {
-        env.throw_new(
-            "java/lang/NullPointerException",
-            "Document::get_node called with `this` as null pointer",
-        )
-        .unwrap();
-
-        0
-    } else {
-        let node = NodeRef::new(node_ref as usize);
-        let doc = &*this;
-        let node = Box::new(from_node(&doc, node));
-        Box::into_raw(node) as jlong
-    }
+    jni_call(&mut env, |_| {
+        let doc = handle::document(this)?;
+        let doc = handle::lock(&doc)?;
+        let node = doc.node(node_ref)?;
+        Ok(handle::insert(Node::from(doc.get(node))))
+    })
 }
 
-fn from_node(doc: &Document, node: NodeRef) -> Node<'_> {
-    match doc.get(node) {
-        dom::Node::Root => Node {
-            ty: NodeType::Root,
-            data: NodeData { root: () },
-        },
-        dom::Node::Leaf(ref s) => Node {
-            ty: NodeType::Leaf,
-            data: NodeData {
-                leaf: RustStr::from_str(s.as_str()),
-            },
-        },
-        dom::Node::Element(ref elem) => {
-            let attrs = elem.attributes();
-            let mut attributes = Vec::with_capacity(attrs.len());
-            for attr in attrs {
-                attributes.push(from_attr(attr));
-            }
-            Node {
-                ty: NodeType::Element,
-                data: NodeData {
-                    element: Element {
-                        namespace: elem
-                            .name
-                            .namespace
-                            .map(|ns| RustStr::from_str(ns.as_str()))
-                            .unwrap_or_default(),
-                        tag: RustStr::from_str(elem.name.name.as_str()),
-                        attributes: AttributeVec::from_vec(attributes),
-                    },
-                },
-            }
-        }
-    }
-}
-
-fn from_attr(attr: &dom::Attribute) -> Attribute {
-    Attribute {
-        namespace: attr
-            .name
-            .namespace
-            .map(|ns| RustStr::from_str(ns.as_str()))
-            .unwrap_or_default(),
-        name: RustStr::from_str(attr.name.name.as_str()),
-        value: attr
-            .value
-            .as_str()
-            .map(|v| RustStr::from_str(v))
-            .unwrap_or_default(),
-    }
+pub(crate) extern "system" fn document_drop_node(
+    mut env: JNIEnv,
+    _: JClass,
+    // non-null handle of Node
+    node: jlong,
+) {
+    jni_call(&mut env, |_| {
+        handle::remove::<Node>(node)?;
+        Ok(())
+    })
 }
 
-#[no_mangle]
-pub unsafe extern "system" fn Java_org_phoenixframework_liveview_lib_Document_get_1node_1leaf_1string<
-    'local,
->(
+pub(crate) extern "system" fn document_get_node_leaf_string<'local>(
     mut env: JNIEnv<'local>,
     _: JClass<'local>,
-    // non-null raw pointer to Document
+    // non-null handle of Document
     this: jlong,
-    // NodeRef is the distinct u32 key mapped to a Node (and not a Node raw pointer)
+    // NodeRef is the distinct u32 key mapped to a Node (and not a Node handle)
     node_ref: jint,
 ) -> JString<'local> {
-    let this = this as *mut Document;
-
-    if this.is_null() {
-        env.throw_new(
-            "java/lang/NullPointerException",
-            "Document::get_node_leaf_string called with `this` as null pointer",
-        )
-        .unwrap();
-
-        JObject::null().into()
-    } else {
-        let node = NodeRef::new(node_ref as usize);
-        let doc = &*this;
+    jni_call(&mut env, |env| {
+        let doc = handle::document(this)?;
+        let doc = handle::lock(&doc)?;
+        let node = doc.node(node_ref)?;
 
         match doc.get(node) {
-            dom::Node::Leaf(ref s) => env
-                .new_string(s.as_str())
-                .unwrap_or_else(|_| JObject::null().into()),
-            _ => JObject::null().into(),
+            dom::Node::Leaf(ref s) => Ok(env.new_string(s.as_str())?),
+            _ => Ok(JObject::null().into()),
         }
-    }
+    })
 }
 
-#[no_mangle]
-pub unsafe extern "system" fn Java_org_phoenixframework_liveview_lib_Document_get_1node_1type(
+pub(crate) extern "system" fn document_get_node_type(
     mut env: JNIEnv,
     _: JClass,
-    // non-null raw pointer to Node
+    // non-null handle of Node
     node: jlong,
 ) -> jbyte {
-    let node = node as *mut Node;
-
-    if node.is_null() {
-        env.throw_new(
-            "java/lang/NullPointerException",
-            "Document::get_node_type called with `node` as null pointer",
-        )
-        .unwrap();
-
-        0
-    } else {
-        let node = &*node;
-        node.ty as jbyte
-    }
+    jni_call(&mut env, |_| Ok(handle::with(node, Node::ty)?))
 }
 
-#[no_mangle]
-pub unsafe extern "system" fn Java_org_phoenixframework_liveview_lib_Document_get_1node_1element(
+pub(crate) extern "system" fn document_get_node_element(
     mut env: JNIEnv,
     _: JClass,
-    // non-null raw pointer to node
+    // non-null handle of Node
     node: jlong,
-    // Raw pointer to Element
+    // handle of Element
 ) -> jlong {
-    let node = node as *mut Node;
-
-    if node.is_null() {
-        env.throw_new(
-            "java/lang/NullPointerException",
-            "Document::get_node_element called with `node` as null pointer",
-        )
-        .unwrap();
-
-        0
-    } else {
-        let node = &*node;
-        let element = Box::new(node.data.element.clone());
-
-        Box::into_raw(element) as jlong
-    }
+    jni_call(&mut env, |_| {
+        let element = handle::with(node, |node: &Node| match node {
+            Node::Element(element) => Some(element.clone()),
+            _ => None,
+        })?;
+
+        match element {
+            Some(element) => Ok(handle::insert(element)),
+            None => Err(Error::IllegalState(
+                "Document::get_node_element called on a node that is not an element".into(),
+            )),
+        }
+    })
 }
 
-#[no_mangle]
-pub unsafe extern "system" fn Java_org_phoenixframework_liveview_lib_Node_00024Element_drop(
+pub(crate) extern "system" fn element_drop(
     mut env: JNIEnv,
     _: JClass,
-    // non-null raw pointer to Element
+    // non-null handle of Element
     this: jlong,
 ) {
-    let this = this as *mut Element;
-
-    if this.is_null() {
-        env.throw_new(
-            "java/lang/NullPointerException",
-            "Node.Element::drop called with null pointer",
-        )
-        .unwrap();
-    } else {
-        let _element = Box::from_raw(this);
-    }
+    jni_call(&mut env, |_| {
+        handle::remove::<Element>(this)?;
+        Ok(())
+    })
 }
 
-#[no_mangle]
-pub unsafe extern "system" fn Java_org_phoenixframework_liveview_lib_Node_00024Element_get_1namespace<
-    'local,
->(
+pub(crate) extern "system" fn element_get_namespace(
+    mut env: JNIEnv,
+    _: JClass,
+    // non-null handle of Element
+    this: jlong,
+) -> jint {
+    jni_call(&mut env, |_| {
+        Ok(handle::with(this, |element: &Element| {
+            element.namespace.symbol()
+        })?)
+    })
+}
+
+/// The name itself, if it was not interned, see [`symbols::Name`]
+pub(crate) extern "system" fn element_get_namespace_string<'local>(
     mut env: JNIEnv<'local>,
     _: JClass<'local>,
-    // non-null raw pointer to Element
+    // non-null handle of Element
     this: jlong,
 ) -> JString<'local> {
-    let this = this as *mut Element;
-
-    if this.is_null() {
-        env.throw_new(
-            "java/lang/NullPointerException",
-            "Document::get_node_element_namespace called with null pointer",
-        )
-        .unwrap();
-
-        JObject::null().into()
-    } else {
-        let element = &*this;
-        env.new_string(element.namespace.to_str())
-            .unwrap_or_else(|_| JObject::null().into())
-    }
+    jni_call(&mut env, |env| {
+        let name = handle::with(this, |element: &Element| {
+            element.namespace.string().map(str::to_owned)
+        })?;
+        match name {
+            Some(name) => Ok(env.new_string(name)?),
+            None => Ok(JString::default()),
+        }
+    })
 }
 
-#[no_mangle]
-pub unsafe extern "system" fn Java_org_phoenixframework_liveview_lib_Node_00024Element_get_1tag<
-    'local,
->(
+pub(crate) extern "system" fn element_get_tag(
+    mut env: JNIEnv,
+    _: JClass,
+    // non-null handle of Element
+    this: jlong,
+) -> jint {
+    jni_call(&mut env, |_| {
+        Ok(handle::with(this, |element: &Element| {
+            element.tag.symbol()
+        })?)
+    })
+}
+
+/// The name itself, if it was not interned, see [`symbols::Name`]
+pub(crate) extern "system" fn element_get_tag_string<'local>(
     mut env: JNIEnv<'local>,
     _: JClass<'local>,
-    // non-null raw pointer to Element
+    // non-null handle of Element
     this: jlong,
 ) -> JString<'local> {
-    let this = this as *mut Element;
-
-    if this.is_null() {
-        env.throw_new(
-            "java/lang/NullPointerException",
-            "Document::get_node_element_tag called with null pointer",
-        )
-        .unwrap();
-
-        JObject::null().into()
-    } else {
-        let element = &*this;
-        env.new_string(element.tag.to_str())
-            .unwrap_or_else(|_| JObject::null().into())
-    }
+    jni_call(&mut env, |env| {
+        let name = handle::with(this, |element: &Element| {
+            element.tag.string().map(str::to_owned)
+        })?;
+        match name {
+            Some(name) => Ok(env.new_string(name)?),
+            None => Ok(JString::default()),
+        }
+    })
 }
 
-#[no_mangle]
-pub unsafe extern "system" fn Java_org_phoenixframework_liveview_lib_Node_00024Element_get_1attributes<
-    'local,
->(
+pub(crate) extern "system" fn element_get_attributes<'local>(
     mut env: JNIEnv<'local>,
     _: JClass<'local>,
-    // non-null raw pointer to Element
+    // non-null handle of Element
     this: jlong,
 ) -> JObjectArray<'local> {
-    let this = this as *mut Element;
-
-    if this.is_null() {
-        env.throw_new(
-            "java/lang/NullPointerException",
-            "Document::get_node_element_attributes called with null pointer",
-        )
-        .unwrap();
-
-        JObject::null().into()
-    } else {
-        let element = &*this;
-
-        let mut attributes = element.attributes.to_vec();
-
-        let attribute_class = env
-            .find_class("org/phoenixframework/liveview/lib/Attribute")
-            .expect("No such class");
-
-        let array = env
-            .new_object_array(attributes.len() as jsize, &attribute_class, JObject::null())
-            .expect("unable to create array");
-
-        for (i, obj) in attributes.drain(..).enumerate() {
-            let obj = Box::new(obj);
-            let obj = Box::into_raw(obj) as jlong;
-            let java_object = env.alloc_object(&attribute_class).unwrap();
-            env.set_field(&java_object, "nativeObject", "J", JValue::from(obj))
-                .expect("unable to set nativeObject");
-            env.set_object_array_element(&array, i as jsize, &java_object)
-                .unwrap();
+    jni_call(&mut env, |env| {
+        let attributes = handle::with(this, |element: &Element| element.attributes.clone())?;
+
+        let cache = cache::get()?;
+        let attribute_class = cache::class(&cache.attribute);
+
+        let array =
+            env.new_object_array(attributes.len() as jsize, attribute_class, JObject::null())?;
+
+        for (i, attr) in attributes.into_iter().enumerate() {
+            let java_object = env.alloc_object(attribute_class)?;
+            env.set_field_unchecked(
+                &java_object,
+                cache.attribute_native_object,
+                JValue::from(handle::insert(attr)),
+            )?;
+            env.set_object_array_element(&array, i as jsize, &java_object)?;
+            env.delete_local_ref(java_object)?;
         }
 
-        array
-    }
+        Ok(array)
+    })
 }
 
-#[no_mangle]
-pub unsafe extern "system" fn Java_org_phoenixframework_liveview_lib_Attribute_drop(
+pub(crate) extern "system" fn attribute_drop(
     mut env: JNIEnv,
     _: JClass,
-    // non-null raw pointer to Attribute
+    // non-null handle of Attribute
     this: jlong,
 ) {
-    let this = this as *mut Attribute;
-
-    if this.is_null() {
-        env.throw_new(
-            "java/lang/NullPointerException",
-            "Attribute::drop called with `this` as null pointer",
-        )
-        .unwrap();
-    } else {
-        let _attr = Box::from_raw(this);
-    }
+    jni_call(&mut env, |_| {
+        handle::remove::<Attribute>(this)?;
+        Ok(())
+    })
 }
 
-#[no_mangle]
-pub unsafe extern "system" fn Java_org_phoenixframework_liveview_lib_Attribute_get_1name<'local>(
+pub(crate) extern "system" fn attribute_get_name(
+    mut env: JNIEnv,
+    _: JClass,
+    // non-null handle of Attribute
+    this: jlong,
+) -> jint {
+    jni_call(&mut env, |_| {
+        Ok(handle::with(this, |attr: &Attribute| attr.name.symbol())?)
+    })
+}
+
+/// The name itself, if it was not interned, see [`symbols::Name`]
+pub(crate) extern "system" fn attribute_get_name_string<'local>(
     mut env: JNIEnv<'local>,
     _: JClass<'local>,
-    // non-null raw pointer to Attribute
+    // non-null handle of Attribute
     this: jlong,
 ) -> JString<'local> {
-    let this = this as *mut Attribute;
-
-    if this.is_null() {
-        env.throw_new(
-            "java/lang/NullPointerException",
-            "Attribute::get_name called with null pointer",
-        )
-        .unwrap();
-
-        JObject::null().into()
-    } else {
-        let attr = &*(this as *mut Attribute);
-        env.new_string(attr.name.to_str())
-            .unwrap_or_else(|_| JObject::null().into())
-    }
+    jni_call(&mut env, |env| {
+        let name = handle::with(this, |attr: &Attribute| {
+            attr.name.string().map(str::to_owned)
+        })?;
+        match name {
+            Some(name) => Ok(env.new_string(name)?),
+            None => Ok(JString::default()),
+        }
+    })
 }
 
-#[no_mangle]
-pub unsafe extern "system" fn Java_org_phoenixframework_liveview_lib_Attribute_get_1namespace<
-    'local,
->(
+pub(crate) extern "system" fn attribute_get_namespace(
+    mut env: JNIEnv,
+    _: JClass,
+    // non-null handle of Attribute
+    this: jlong,
+) -> jint {
+    jni_call(&mut env, |_| {
+        Ok(handle::with(this, |attr: &Attribute| {
+            attr.namespace.symbol()
+        })?)
+    })
+}
+
+/// The name itself, if it was not interned, see [`symbols::Name`]
+pub(crate) extern "system" fn attribute_get_namespace_string<'local>(
     mut env: JNIEnv<'local>,
     _: JClass<'local>,
-    // non-null raw pointer to Attribute
+    // non-null handle of Attribute
     this: jlong,
 ) -> JString<'local> {
-    let this = this as *mut Attribute;
-
-    if this.is_null() {
-        env.throw_new(
-            "java/lang/NullPointerException",
-            "Attribute::get_namespace called with null pointer",
-        )
-        .unwrap();
-
-        JObject::null().into()
-    } else {
-        let attr = &*this;
-        env.new_string(attr.namespace.to_str())
-            .unwrap_or_else(|_| JObject::null().into())
-    }
+    jni_call(&mut env, |env| {
+        let name = handle::with(this, |attr: &Attribute| {
+            attr.namespace.string().map(str::to_owned)
+        })?;
+        match name {
+            Some(name) => Ok(env.new_string(name)?),
+            None => Ok(JString::default()),
+        }
+    })
 }
 
-#[no_mangle]
-pub unsafe extern "system" fn Java_org_phoenixframework_liveview_lib_Attribute_get_1value<
-    'local,
->(
+pub(crate) extern "system" fn attribute_get_value<'local>(
     mut env: JNIEnv<'local>,
     _: JClass<'local>,
-    // non-null raw-pointer to Attribute
+    // non-null handle of Attribute
     this: jlong,
 ) -> JString<'local> {
-    let this = this as *mut Attribute;
-
-    if this.is_null() {
-        env.throw_new(
-            "java/lang/NullPointerException",
-            "Attribute::get_value called with null pointer",
-        )
-        .unwrap();
-
-        JObject::null().into()
-    } else {
-        let attr = &*this;
-        env.new_string(attr.value.to_str())
-            .unwrap_or_else(|_| JObject::null().into())
-    }
+    jni_call(&mut env, |env| {
+        let value = handle::with(this, |attr: &Attribute| attr.value.clone())?;
+        Ok(env.new_string(value)?)
+    })
 }
 
-#[no_mangle]
-pub unsafe extern "system" fn Java_org_phoenixframework_liveview_lib_Document_get_1children<
-    'local,
->(
+pub(crate) extern "system" fn symbols_resolve<'local>(
+    mut env: JNIEnv<'local>,
+    _: JObject<'local>,
+    // The first symbol the caller has not resolved yet
+    from: jint,
+) -> JObjectArray<'local> {
+    jni_call(&mut env, |env| {
+        let names = symbols::since(from);
+        let array =
+            env.new_object_array(names.len() as jsize, "java/lang/String", JObject::null())?;
+        for (i, name) in names.iter().enumerate() {
+            let name = env.new_string(name)?;
+            env.set_object_array_element(&array, i as jsize, &name)?;
+            env.delete_local_ref(name)?;
+        }
+        Ok(array)
+    })
+}
+
+/// Returns the commands encoded in a binding attribute, serialised for
+/// `JsCommand.decode`
+pub(crate) extern "system" fn js_command_companion_do_decode<'local>(
+    mut env: JNIEnv<'local>,
+    _: JObject<'local>,
+    // The value of a binding attribute, e.g. `phx-click`
+    value: JString<'local>,
+) -> JByteArray<'local> {
+    jni_call(&mut env, |env| {
+        let value: String = env.get_string(&value)?.into();
+        let buf = js::decode(&value)?;
+        Ok(env.byte_array_from_slice(&buf)?)
+    })
+}
+
+pub(crate) extern "system" fn document_get_children<'local>(
     mut env: JNIEnv<'local>,
     _: JClass<'local>,
-    // non-null raw pointer to Document
+    // non-null handle of Document
     this: jlong,
-    // NodeRef is the distinct u32 key mapped to a Node (and not a Node raw pointer)
+    // NodeRef is the distinct u32 key mapped to a Node (and not a Node handle)
     node_ref: jint,
 ) -> JIntArray<'local> {
-    let this = this as *mut Document;
-
-    if this.is_null() {
-        env.throw_new(
-            "java/lang/NullPointerException",
-            "Document::get_children called with null pointer",
-        )
-        .unwrap();
-
-        JObject::null().into()
-    } else {
-        let node = NodeRef::new(node_ref as usize);
-        let doc = &*this;
-        let children = doc.children(node);
-        let buff = env.new_int_array(children.len() as jsize).unwrap();
-        let children: Vec<jint> = children.into_iter().map(|n| n.as_u32() as jint).collect();
-        env.set_int_array_region(&buff, 0, &children).unwrap();
-        buff
-    }
+    jni_call(&mut env, |env| {
+        let doc = handle::document(this)?;
+        let doc = handle::lock(&doc)?;
+        let node = doc.node(node_ref)?;
+        let children: Vec<jint> = doc
+            .children(node)
+            .iter()
+            .map(|n| n.as_u32() as jint)
+            .collect();
+        let buff = env.new_int_array(children.len() as jsize)?;
+        env.set_int_array_region(&buff, 0, &children)?;
+        Ok(buff)
+    })
 }
 
 // Java side should ensure only u32 is passed as the node parameter
 // Note! this function returns -1 when there's no parent
-#[no_mangle]
-pub unsafe extern "system" fn Java_org_phoenixframework_liveview_lib_Document_get_1parent(
+pub(crate) extern "system" fn document_get_parent(
     mut env: JNIEnv,
     _: JClass,
-    // non-null raw pointer to Document
+    // non-null handle of Document
     this: jlong,
-    // NodeRef is the distinct u32 key mapped to a Node (and not a Node raw pointer)
+    // NodeRef is the distinct u32 key mapped to a Node (and not a Node handle)
     node_ref: jint,
 ) -> jint {
-    let this = this as *mut Document;
-
-    if this.is_null() {
-        env.throw_new(
-            "java/lang/NullPointerException",
-            "Document::get_parent called with null pointer",
-        )
-        .unwrap();
-
-        -1
-    } else {
-        let node = NodeRef::new(node_ref as usize);
-        let doc = &*this;
+    jni_call(&mut env, |_| {
+        let doc = handle::document(this)?;
+        let doc = handle::lock(&doc)?;
+        let node = doc.node(node_ref)?;
 
         match doc.parent(node) {
-            Some(parent) => parent.as_u32() as jint,
-            None => -1,
+            Some(parent) => Ok(parent.as_u32() as jint),
+            None => Ok(-1),
         }
-    }
+    })
 }
 
-#[no_mangle]
-pub unsafe extern "system" fn Java_org_phoenixframework_liveview_lib_Document_merge<'local>(
+pub(crate) extern "system" fn document_snapshot<'local>(
     mut env: JNIEnv<'local>,
     _: JClass<'local>,
-    // non-null raw pointer to Document
+    // non-null handle of Document
     this: jlong,
-    // non-null raw pointer to other Document
-    other: jlong,
-    // Callback handle interface
-    interface: JObject<'local>,
+    // NodeRef is the distinct u32 key mapped to a Node (and not a Node handle)
+    node_ref: jint,
+) -> JByteArray<'local> {
+    jni_call(&mut env, |env| {
+        let doc = handle::document(this)?;
+        let doc = handle::lock(&doc)?;
+        let node = doc.node(node_ref)?;
+        let buf = snapshot::encode(&doc, node);
+        drop(doc);
+        Ok(env.byte_array_from_slice(&buf)?)
+    })
+}
+
+pub(crate) extern "system" fn document_set_key_attribute<'local>(
+    mut env: JNIEnv<'local>,
+    _: JClass<'local>,
+    // non-null handle of Document
+    this: jlong,
+    // Attribute identifying keyed siblings, or null to disable move detection
+    name: JString<'local>,
 ) {
-    let this = this as *mut Document;
-    let other = other as *mut Document;
-
-    if this.is_null() || other.is_null() {
-        let message = match (this.is_null(), other.is_null()) {
-            (true, true) => "Documment::merge called with `this` and `other` as null pointers",
-            (true, false) => "Document::merge called with `this` as null pointer",
-            (false, true) => "Document::merge called with `other` as null pointer",
-            (false, false) => unreachable_unchecked(),
+    jni_call(&mut env, |env| {
+        let name: Option<String> = if name.is_null() {
+            None
+        } else {
+            Some(env.get_string(&name)?.into())
         };
 
-        env.throw_new("java/lang/NullPointerException", message)
-            .unwrap();
-
-        return;
-    }
+        let doc = handle::document(this)?;
+        let mut doc = handle::lock(&doc)?;
+        doc.set_key_attribute(name);
+        Ok(())
+    })
+}
 
-    let doc = &mut *this;
-    let other = &*other;
-    merge(env, this, doc, other, interface);
+// Note! this function returns -1 when the node was not rendered by a live component
+pub(crate) extern "system" fn document_component_of(
+    mut env: JNIEnv,
+    _: JClass,
+    // non-null handle of Document
+    this: jlong,
+    // NodeRef is the distinct u32 key mapped to a Node (and not a Node handle)
+    node_ref: jint,
+) -> jint {
+    jni_call(&mut env, |_| {
+        let doc = handle::document(this)?;
+        let mut doc = handle::lock(&doc)?;
+        let node = doc.node(node_ref)?;
+        match doc.component_of(node) {
+            Ok(cid) => Ok(cid.unwrap_or(-1)),
+            Err(UnknownComponent) => Err(Error::IllegalState(format!(
+                "node {node_ref} is in a part of the document that does not match its rendering, \
+                 so the live component that rendered it is unknown"
+            ))),
+        }
+    })
 }
 
-#[no_mangle]
-pub unsafe extern "system" fn Java_org_phoenixframework_liveview_lib_Document_merge_1fragment_1json<'local>(
+pub(crate) extern "system" fn document_prune_components<'local>(
     mut env: JNIEnv<'local>,
     _: JClass<'local>,
-    // non-null raw pointer to Document
+    // non-null handle of Document
     this: jlong,
-    // A json string ofr the fragment
-    other_json: JString<'local>,
-    // Callback handle interface
-    interface: JObject<'local>,
+    // cids acknowledged as destroyed by the server
+    cids: JIntArray<'local>,
 ) {
-    use liveview_native_core::diff::fragment::RootDiff;
-
-    let this = this as *mut Document;
+    jni_call(&mut env, |env| {
+        let mut buf = vec![0; env.get_array_length(&cids)? as usize];
+        env.get_int_array_region(&cids, 0, &mut buf)?;
+
+        let doc = handle::document(this)?;
+        let mut doc = handle::lock(&doc)?;
+        doc.fragment_mut().prune_components(&buf);
+        Ok(())
+    })
+}
 
-    if this.is_null() {
-        let message = match this.is_null() {
-            true => "Document::merge called with `this` as null pointer",
-            false => unreachable_unchecked(),
+/// Returns the JSON payloads of the `event` pushes, one per target
+pub(crate) extern "system" fn document_event_payloads<'local>(
+    mut env: JNIEnv<'local>,
+    _: JClass<'local>,
+    // non-null handle of Document
+    this: jlong,
+    // NodeRef is the distinct u32 key mapped to a Node (and not a Node handle)
+    node_ref: jint,
+    // The binding without its `phx-` prefix, e.g. `click`
+    binding: JString<'local>,
+) -> JObjectArray<'local> {
+    jni_call(&mut env, |env| {
+        let binding: String = env.get_string(&binding)?.into();
+        let payloads = {
+            let doc = handle::document(this)?;
+            let mut doc = handle::lock(&doc)?;
+            let node = doc.node(node_ref)?;
+            event::payloads(&mut doc, node, &binding)?
         };
-
-        env.throw_new("java/lang/NullPointerException", message)
-            .unwrap();
-
-        return;
-    }
-    let doc = &mut *this;
-
-    let other_json: String = env.get_string(&other_json).unwrap().into();
-    let other_fragment : Result<RootDiff, _> = serde_json::from_str(&other_json);
-    let other_fragment = match other_fragment {
-        Ok(fragment) => fragment,
-        Err(err) => {
-            log::error!("{err:?}");
-            let message = format!("Documment::merge_fragment_json called with invalid json {err}");
-            env.throw_new("java/lang/RuntimeException", message)
-                .unwrap();
-            return;
+        let array =
+            env.new_object_array(payloads.len() as jsize, "java/lang/String", JObject::null())?;
+        for (i, payload) in payloads.iter().enumerate() {
+            let payload = env.new_string(payload)?;
+            env.set_object_array_element(&array, i as jsize, &payload)?;
+            env.delete_local_ref(payload)?;
         }
-    };
+        Ok(array)
+    })
+}
 
-    if let Err(err) = doc.merge_fragment(other_fragment.clone()) {
-        log::error!("{err:?}");
-        let message = format!("Documment::merge_fragment_json called with invalid json {err:?}");
-        env.throw_new("java/lang/RuntimeException", message)
-            .unwrap();
-        return;
-    }
-    let new_root = if let Some(fragment) = doc.fragment_template.clone() {
-        fragment
-    } else {
-        let message = format!("Documment::merge_fragment_json Fragment template is Note!");
-        env.throw_new("java/lang/RuntimeException", message)
-            .unwrap();
-        return;
-    };
+/// Returns the `JoinParams` found in a dead render
+pub(crate) extern "system" fn document_join_params<'local>(
+    mut env: JNIEnv<'local>,
+    _: JClass<'local>,
+    // non-null handle of Document
+    this: jlong,
+) -> JObject<'local> {
+    jni_call(&mut env, |env| {
+        let params = {
+            let doc = handle::document(this)?;
+            let doc = handle::lock(&doc)?;
+            JoinParams::extract(&doc)?
+        };
+        params.to_java(env)
+    })
+}
 
-    let other_doc : String = match new_root.try_into() {
-        Ok(rendered) => rendered,
-        Err(err) => {
-            log::error!("{err:?}");
-            let message = format!("Documment::merge_fragment_json failed to render {err:?}");
-            env.throw_new("java/lang/RuntimeException", message)
-                .unwrap();
-            return;
-        }
-    };
+pub(crate) extern "system" fn document_merge<'local>(
+    mut env: JNIEnv<'local>,
+    _: JClass<'local>,
+    // non-null handle of Document
+    this: jlong,
+    // non-null handle of other Document
+    other: jlong,
+    // Callback handle interface
+    interface: JObject<'local>,
+) {
+    jni_call(&mut env, |env| {
+        let doc = handle::document(this)?;
+        let other = handle::document(other)?;
 
-    let other_doc = match dom::Document::parse(other_doc) {
-        Ok(doc) => doc,
-        Err(err) => {
-            log::error!("{err:?}");
-            let message = format!("Documment::merge_fragment_json failed to parse {err}");
-            env.throw_new("java/lang/RuntimeException", message)
-                .unwrap();
-            return;
+        // Merging a document into itself never produces any patches
+        if SharedDocument::ptr_eq(&doc, &other) {
+            return Ok(());
         }
-    };
 
-    merge(env, this, doc, &other_doc, interface);
+        merge::merge_document_and_notify(env, this, &other, &interface)
+    })
 }
-fn merge<'local>(
+
+pub(crate) extern "system" fn document_merge_fragment_json<'local>(
     mut env: JNIEnv<'local>,
-    this: * mut Document,
-    doc: &mut Document,
-    other_doc: &Document,
+    _: JClass<'local>,
+    // non-null handle of Document
+    this: jlong,
+    // A json string ofr the fragment
+    other_json: JString<'local>,
     // Callback handle interface
     interface: JObject<'local>,
 ) {
-    let patches = diff::diff(doc, &other_doc);
+    jni_call(&mut env, |env| {
+        let other_json: String = env.get_string(&other_json)?.into();
+        let diff = merge::decode_fragment(other_json.as_bytes())?;
+        merge::merge_and_notify(env, this, &interface, |doc, journal| {
+            merge::merge_fragment(doc, journal, diff)
+        })
+    })
+}
 
-    if patches.is_empty() {
-        return;
-    }
+pub(crate) extern "system" fn document_merge_fragment_json_bytes<'local>(
+    mut env: JNIEnv<'local>,
+    _: JClass<'local>,
+    // non-null handle of Document
+    this: jlong,
+    // UTF-8 json of the fragment, `length` bytes at `offset`
+    other_json: JByteArray<'local>,
+    offset: jint,
+    length: jint,
+    // Callback handle interface
+    interface: JObject<'local>,
+) {
+    jni_call(&mut env, |env| {
+        let diff = bytes::with_array(env, &other_json, offset, length, merge::decode_fragment)?;
+        merge::merge_and_notify(env, this, &interface, |doc, journal| {
+            merge::merge_fragment(doc, journal, diff)
+        })
+    })
+}
 
-    let mut editor = doc.edit();
-    let mut stack = vec![];
-
-    for patch in patches.into_iter() {
-
-        match patch.apply(&mut editor, &mut stack) {
-            None => (),
-            Some(PatchResult::Add { node, parent }) => {
-                env.call_method(
-                    &interface,
-                    "ffiOnHandle",
-                    "(JBII)V",
-                    &[
-                        JValue::Long(this as jlong),
-                        JValue::Byte(ChangeType::Add as jbyte),
-                        JValue::Int(node.as_u32() as jint),
-                        JValue::Int(parent.as_u32() as jint),
-                    ],
-                )
-                .unwrap();
-            }
-            Some(PatchResult::Remove { node, parent }) => {
-                env.call_method(
-                    &interface,
-                    "ffiOnHandle",
-                    "(JBII)V",
-                    &[
-                        JValue::Long(this as jlong),
-                        JValue::Byte(ChangeType::Remove as jbyte),
-                        JValue::Int(node.as_u32() as jint),
-                        JValue::Int(parent.as_u32() as jint),
-                    ],
-                )
-                .unwrap();
-            }
-            Some(PatchResult::Change { node }) => {
-                env.call_method(
-                    &interface,
-                    "ffiOnHandle",
-                    "(JBII)V",
-                    &[
-                        JValue::Long(this as jlong),
-                        JValue::Byte(ChangeType::Change as jbyte),
-                        JValue::Int(node.as_u32() as jint),
-                        JValue::Int(0),
-                    ],
-                )
-                .unwrap();
-            }
-            Some(PatchResult::Replace { node, parent }) => {
-                env.call_method(
-                    &interface,
-                    "ffiOnHandle",
-                    "(JBII)V",
-                    &[
-                        JValue::Long(this as jlong),
-                        JValue::Byte(ChangeType::Replace as jbyte),
-                        JValue::Int(node.as_u32() as jint),
-                        JValue::Int(parent.as_u32() as jint),
-                    ],
-                )
-                .unwrap();
-            }
-        }
-    }
+pub(crate) extern "system" fn document_merge_fragment_json_buffer<'local>(
+    mut env: JNIEnv<'local>,
+    _: JClass<'local>,
+    // non-null handle of Document
+    this: jlong,
+    // Direct buffer of UTF-8 json of the fragment, `length` bytes at `offset`
+    other_json: JByteBuffer<'local>,
+    offset: jint,
+    length: jint,
+    // Callback handle interface
+    interface: JObject<'local>,
+) {
+    jni_call(&mut env, |env| {
+        let diff = bytes::with_buffer(env, &other_json, offset, length, merge::decode_fragment)?;
+        merge::merge_and_notify(env, this, &interface, |doc, journal| {
+            merge::merge_fragment(doc, journal, diff)
+        })
+    })
+}
 
-    editor.finish();
+/// Returns the `ChannelEvent` describing the message
+pub(crate) extern "system" fn document_handle_channel_message<'local>(
+    mut env: JNIEnv<'local>,
+    _: JClass<'local>,
+    // non-null handle of Document
+    this: jlong,
+    // A Phoenix channel message, `[join_ref, ref, topic, event, payload]`
+    message: JString<'local>,
+    // Callback handle interface
+    interface: JObject<'local>,
+) -> JObject<'local> {
+    jni_call(&mut env, |env| {
+        let message: String = env.get_string(&message)?.into();
+        let message = channel::Message::decode(message.as_bytes())?;
+        channel::handle(env, this, &interface, message)
+    })
+}
+
+/// Returns the `ChannelEvent` describing the message
+pub(crate) extern "system" fn document_handle_channel_message_bytes<'local>(
+    mut env: JNIEnv<'local>,
+    _: JClass<'local>,
+    // non-null handle of Document
+    this: jlong,
+    // UTF-8 json of a Phoenix channel message, `length` bytes at `offset`
+    message: JByteArray<'local>,
+    offset: jint,
+    length: jint,
+    // Callback handle interface
+    interface: JObject<'local>,
+) -> JObject<'local> {
+    jni_call(&mut env, |env| {
+        let message = bytes::with_array(env, &message, offset, length, channel::Message::decode)?;
+        channel::handle(env, this, &interface, message)
+    })
 }
-/*
-*/
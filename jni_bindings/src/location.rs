@@ -0,0 +1,167 @@
+//! Guessing where in the source a parse failure is.
+//!
+//! The parser only reports what went wrong, not where. After a failed parse we
+//! rescan the source with a lightweight tag matcher to find the first
+//! structural problem (unterminated tag, comment or quoted value, or an
+//! unbalanced end tag). That is where the parser most likely failed, but it is
+//! a guess, and is reported as one.
+
+/// Where in the source a parse failure most likely is
+#[derive(Debug)]
+pub(crate) struct SourceLocation {
+    /// 1-based line
+    pub line: usize,
+    /// 1-based column, counted in characters
+    pub column: usize,
+    /// 0-based byte offset
+    pub offset: usize,
+    /// The offending line, followed by a line with a `^` under the column
+    pub excerpt: String,
+    /// What the scanner found suspicious at this position
+    pub reason: String,
+}
+
+/// Elements that never have an end tag
+const VOID_ELEMENTS: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param", "source",
+    "track", "wbr",
+];
+
+/// Elements whose content is not markup
+const RAW_TEXT_ELEMENTS: &[&str] = &["script", "style"];
+
+/// The number of characters shown on either side of the column in an excerpt
+const EXCERPT_RADIUS: usize = 40;
+
+/// Finds the first structural problem in `source`, if the scanner sees one
+pub(crate) fn locate(source: &str) -> Option<SourceLocation> {
+    let (offset, reason) = scan(source)?;
+    Some(SourceLocation::at(source, offset, reason))
+}
+
+impl SourceLocation {
+    fn at(source: &str, offset: usize, reason: String) -> Self {
+        let line_start = source[..offset].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = source[offset..]
+            .find('\n')
+            .map_or(source.len(), |i| offset + i);
+        let line = source[..offset].matches('\n').count() + 1;
+        let column = source[line_start..offset].chars().count() + 1;
+
+        let text = source[line_start..line_end].trim_end_matches('\r');
+        let skip = (column - 1).saturating_sub(EXCERPT_RADIUS);
+        let shown: String = text.chars().skip(skip).take(2 * EXCERPT_RADIUS).collect();
+        let caret = column - 1 - skip;
+        let excerpt = format!("{shown}\n{}^", " ".repeat(caret));
+
+        Self {
+            line,
+            column,
+            offset,
+            excerpt,
+            reason,
+        }
+    }
+}
+
+fn scan(source: &str) -> Option<(usize, String)> {
+    let bytes = source.as_bytes();
+    // Open elements, with the offset of their start tag
+    let mut open: Vec<(&str, usize)> = vec![];
+    let mut pos = 0;
+
+    while let Some(i) = source[pos..].find('<') {
+        let start = pos + i;
+        let rest = &source[start..];
+
+        if rest.starts_with("<!--") {
+            match rest.find("-->") {
+                Some(end) => pos = start + end + 3,
+                None => return Some((start, "unterminated comment".into())),
+            }
+        } else if rest.starts_with("<!") || rest.starts_with("<?") {
+            match rest.find('>') {
+                Some(end) => pos = start + end + 1,
+                None => return Some((start, "unterminated declaration".into())),
+            }
+        } else if let Some(after) = rest.strip_prefix("</") {
+            let name = tag_name(after);
+            if name.is_empty() {
+                return Some((start, "end tag without a name".into()));
+            }
+            let Some(end) = rest.find('>') else {
+                return Some((start, format!("unterminated end tag `</{name}`")));
+            };
+            match open.pop() {
+                Some((expected, _)) if expected == name => (),
+                Some((expected, _)) => {
+                    return Some((
+                        start,
+                        format!("end tag `</{name}>` does not match open element `<{expected}>`"),
+                    ))
+                }
+                None => {
+                    return Some((
+                        start,
+                        format!("end tag `</{name}>` has no matching start tag"),
+                    ))
+                }
+            }
+            pos = start + end + 1;
+        } else if !rest[1..].starts_with(|c: char| c.is_ascii_alphabetic()) {
+            // Not a tag, so the tokenizer takes it as text, e.g. `a < b`
+            pos = start + 1;
+        } else {
+            let name = tag_name(&rest[1..]);
+            let (end, self_closing) = match start_tag_end(bytes, start + 1 + name.len()) {
+                Ok(end) => end,
+                Err(Some(quote)) => {
+                    return Some((quote, format!("unterminated attribute value in `<{name}>`")))
+                }
+                Err(None) => return Some((start, format!("unterminated start tag `<{name}`"))),
+            };
+            pos = end;
+
+            let lowercase = name.to_ascii_lowercase();
+            if self_closing || VOID_ELEMENTS.contains(&lowercase.as_str()) {
+                continue;
+            }
+            if RAW_TEXT_ELEMENTS.contains(&lowercase.as_str()) {
+                let close = format!("</{name}");
+                match source[pos..].find(&close) {
+                    Some(i) => pos += i,
+                    None => return Some((start, format!("unclosed element `<{name}>`"))),
+                }
+            }
+            open.push((name, start));
+        }
+    }
+
+    open.pop()
+        .map(|(name, start)| (start, format!("unclosed element `<{name}>`")))
+}
+
+fn tag_name(s: &str) -> &str {
+    let end = s
+        .find(|c: char| !(c.is_alphanumeric() || matches!(c, '-' | '_' | ':' | '.')))
+        .unwrap_or(s.len());
+    &s[..end]
+}
+
+/// Finds the end of a start tag whose attributes begin at `pos`.
+///
+/// Returns the offset just past the `>` and whether the tag is self-closing, or
+/// on failure the offset of an unterminated quote, if that is the cause.
+fn start_tag_end(bytes: &[u8], mut pos: usize) -> Result<(usize, bool), Option<usize>> {
+    while pos < bytes.len() {
+        match bytes[pos] {
+            b'>' => return Ok((pos + 1, bytes[pos - 1] == b'/')),
+            quote @ (b'"' | b'\'') => match bytes[pos + 1..].iter().position(|&b| b == quote) {
+                Some(len) => pos += len + 2,
+                None => return Err(Some(pos)),
+            },
+            _ => pos += 1,
+        }
+    }
+    Err(None)
+}
@@ -0,0 +1,747 @@
+//! Merging into a document, and notifying Kotlin of the resulting changes.
+//!
+//! Patches are applied first and their results buffered; the Kotlin handler
+//! is only called once the edit is finished and the document lock released, so
+//! it always observes a consistent, fully patched tree.
+
+use std::{
+    panic::{self, AssertUnwindSafe},
+    sync::PoisonError,
+    thread,
+};
+
+use jni::{
+    objects::{JObject, JValue},
+    signature::{Primitive, ReturnType},
+    sys::{jbyte, jint, jlong, jsize},
+    JNIEnv,
+};
+use serde_json::Value;
+
+use liveview_native_core::{
+    diff,
+    diff::{fragment::RootDiff, PatchResult},
+    dom,
+    dom::{Document, NodeRef},
+};
+
+use crate::{
+    cache,
+    details::ChangeDetails,
+    document::{Journal, NativeDocument},
+    error::Error,
+    fragment::{self, ComponentChanges, Edit},
+    handle::{self, SharedDocument},
+    stream::{StreamEdit, StreamOps},
+    update,
+};
+
+/// Mirrors `Document.Companion.ChangeType`. The first four variants match
+/// `ffi::ChangeType`.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ChangeType {
+    Change = 0,
+    Add = 1,
+    Remove = 2,
+    Replace = 3,
+    /// A keyed node was removed and added back under the same parent
+    Move = 4,
+}
+
+/// A change made to the document by a single patch
+pub(crate) struct Change {
+    pub change_type: ChangeType,
+    pub node: NodeRef,
+    pub parent: Option<NodeRef>,
+    /// For an `Add`, `Replace` or `Move`, the position of `node` among the
+    /// children of `parent` in the merged document
+    pub index: Option<usize>,
+    /// For an `Add`, `Replace` or `Move`, the child of `parent` just before
+    /// `node` in the merged document
+    pub previous_sibling: Option<NodeRef>,
+    /// For a `Change`, what changed about `node`
+    pub details: Option<ChangeDetails>,
+    /// For a `Move`, the position of the node among the children of `parent`
+    /// before the merge
+    pub old_index: Option<usize>,
+}
+
+impl Change {
+    /// The number of ints in a packed record
+    pub const RECORD_LEN: usize = 6;
+
+    fn new(change_type: ChangeType, node: NodeRef, parent: Option<NodeRef>) -> Self {
+        Self {
+            change_type,
+            node,
+            parent,
+            index: None,
+            previous_sibling: None,
+            details: None,
+            old_index: None,
+        }
+    }
+
+    /// Fills in what a `Change` changed, by comparing the node before the
+    /// merge with the merged one
+    fn describe(&mut self, before: &Journal, doc: &Document) {
+        if !matches!(self.change_type, ChangeType::Change) {
+            return;
+        }
+        self.details = before
+            .get(self.node)
+            .and_then(|old| ChangeDetails::between(old, doc.get(self.node)));
+    }
+
+    fn details_to_java<'local>(&self, env: &mut JNIEnv<'local>) -> Result<JObject<'local>, Error> {
+        match &self.details {
+            Some(details) => details.to_java(env),
+            None => Ok(JObject::null()),
+        }
+    }
+
+    /// Fills in the position of an added or replacing node, once every patch
+    /// has been applied. A node that was detached again by a later patch has
+    /// no position.
+    fn locate(&mut self, doc: &Document) {
+        if !matches!(
+            self.change_type,
+            ChangeType::Add | ChangeType::Replace | ChangeType::Move
+        ) {
+            return;
+        }
+        let Some(parent) = self.parent else {
+            return;
+        };
+        let siblings = doc.children(parent);
+        self.index = siblings.iter().position(|&child| child == self.node);
+        self.previous_sibling = self
+            .index
+            .and_then(|index| index.checked_sub(1))
+            .map(|index| siblings[index]);
+    }
+
+    /// The change packed as `[change type, node, parent, index, previous
+    /// sibling, old index]`, with `-1` for anything absent, as decoded by
+    /// `ChangeBatch` on the Kotlin side
+    fn record(&self) -> [jint; Self::RECORD_LEN] {
+        let node_ref = |node: Option<NodeRef>| node.map_or(-1, |node| node.as_u32() as jint);
+        [
+            self.change_type as jint,
+            self.node.as_u32() as jint,
+            node_ref(self.parent),
+            self.index.map_or(-1, |index| index as jint),
+            node_ref(self.previous_sibling),
+            self.old_index.map_or(-1, |index| index as jint),
+        ]
+    }
+}
+
+/// Everything a merge reports to the Kotlin handler
+pub(crate) struct Merged {
+    pub changes: Vec<Change>,
+    /// For a fragment diff, the live components it touched
+    pub components: ComponentChanges,
+}
+
+impl From<Vec<Change>> for Merged {
+    fn from(changes: Vec<Change>) -> Self {
+        Self {
+            changes,
+            components: ComponentChanges::default(),
+        }
+    }
+}
+
+/// Diffs `doc` against `other` and applies the resulting patches, recording
+/// them in `journal`.
+///
+/// Either every patch is applied, or `doc` is left unchanged.
+pub(crate) fn merge(
+    doc: &mut NativeDocument,
+    journal: &mut Journal,
+    other: &Document,
+) -> Result<Merged, Error> {
+    doc.transaction(journal, |doc, journal| apply(doc, journal, other))
+        .map(Merged::from)
+}
+
+fn apply(
+    doc: &mut NativeDocument,
+    journal: &mut Journal,
+    other: &Document,
+) -> Result<Vec<Change>, Error> {
+    let other = update::preserve(doc, other);
+    let patches = diff::diff(doc, &other);
+
+    if patches.is_empty() {
+        return Ok(vec![]);
+    }
+    let key_attribute = doc.key_attribute().map(str::to_owned);
+
+    journal.record_counterparts(doc, &other);
+    let mut changes = doc.update(journal, |doc| {
+        let mut editor = doc.edit();
+        let mut stack = vec![];
+        let mut changes = Vec::with_capacity(patches.len());
+
+        for (patch_index, patch) in patches.into_iter().enumerate() {
+            let result =
+                panic::catch_unwind(AssertUnwindSafe(|| patch.apply(&mut editor, &mut stack)))
+                    .map_err(|payload| Error::PatchApply {
+                        kind: "Panic".into(),
+                        message: crate::panic::report(payload).message,
+                        patch_index,
+                    })?;
+
+            let change = match result {
+                None => continue,
+                Some(PatchResult::Add { node, parent }) => {
+                    Change::new(ChangeType::Add, node, Some(parent))
+                }
+                Some(PatchResult::Remove { node, parent }) => {
+                    Change::new(ChangeType::Remove, node, Some(parent))
+                }
+                Some(PatchResult::Change { node }) => Change::new(ChangeType::Change, node, None),
+                Some(PatchResult::Replace { node, parent }) => {
+                    Change::new(ChangeType::Replace, node, Some(parent))
+                }
+            };
+            changes.push(change);
+        }
+
+        editor.finish();
+        Ok::<_, Error>(changes)
+    })?;
+
+    for change in &mut changes {
+        change.locate(doc);
+        change.describe(journal, doc);
+    }
+    if let Some(key_attribute) = &key_attribute {
+        detect_moves(&mut changes, journal, doc, key_attribute);
+    }
+    Ok(changes)
+}
+
+/// Collapses each `Remove` of a keyed node and the `Add` of a node with the
+/// same key, under the same parent, into a single `Move`, reported in place of
+/// the `Add`.
+///
+/// Nodes are matched by key rather than by `NodeRef`, as the diff rebuilds
+/// reordered children rather than moving them: the `Move` then reports the
+/// node that now holds the key. An element with the same key but another tag
+/// replaced the removed one rather than moving it, so both its `Remove` and
+/// `Add` are kept, as are those of a key added back under another parent.
+fn detect_moves(changes: &mut Vec<Change>, before: &Journal, doc: &Document, key_attribute: &str) {
+    let mut removed = vec![];
+    for (i, change) in changes.iter().enumerate() {
+        if change.change_type != ChangeType::Remove {
+            continue;
+        }
+        let (Some(parent), Some(dom::Node::Element(elem))) =
+            (change.parent, before.get(change.node))
+        else {
+            continue;
+        };
+        if let Some(key) = key_of(elem, key_attribute) {
+            removed.push((i, change.node, parent, &elem.name, key.to_owned()));
+        }
+    }
+    if removed.is_empty() {
+        return;
+    }
+
+    let mut moved = vec![];
+    for change in changes.iter_mut() {
+        if change.change_type != ChangeType::Add {
+            continue;
+        }
+        let dom::Node::Element(elem) = doc.get(change.node) else {
+            continue;
+        };
+        let Some(key) = key_of(elem, key_attribute) else {
+            continue;
+        };
+        let Some(position) = removed
+            .iter()
+            .position(|(_, _, parent, name, removed_key)| {
+                Some(*parent) == change.parent && **name == elem.name && removed_key == key
+            })
+        else {
+            continue;
+        };
+
+        let (i, node, parent, _, _) = removed.swap_remove(position);
+        change.change_type = ChangeType::Move;
+        change.old_index = before
+            .children(parent)
+            .iter()
+            .position(|&child| child == node);
+        moved.push(i);
+    }
+
+    moved.sort_unstable();
+    for i in moved.into_iter().rev() {
+        changes.remove(i);
+    }
+}
+
+/// The value of the key attribute of an element
+fn key_of<'a>(elem: &'a dom::Element, key_attribute: &str) -> Option<&'a str> {
+    elem.attributes()
+        .iter()
+        .find(|attr| attr.name.namespace.is_none() && attr.name.name.as_str() == key_attribute)
+        .and_then(|attr| attr.value.as_str())
+}
+
+/// A fragment diff, decoded both for the fragment template and as JSON for the
+/// `FragmentState`
+pub(crate) struct FragmentDiff {
+    fragment: RootDiff,
+    diff: Value,
+}
+
+/// Merges a fragment diff into the document's fragment template, then merges
+/// the rendered template into the document.
+///
+/// Either the fragment template advances and every patch is applied, or `doc`
+/// and its fragment template are left unchanged.
+pub(crate) fn merge_fragment(
+    doc: &mut NativeDocument,
+    journal: &mut Journal,
+    FragmentDiff { fragment, diff }: FragmentDiff,
+) -> Result<Merged, Error> {
+    doc.transaction(journal, |doc, journal| {
+        apply_fragment(doc, journal, fragment, diff)
+    })
+}
+
+/// Merges a fragment diff that was already parsed as part of a larger message
+pub(crate) fn merge_fragment_value(
+    doc: &mut NativeDocument,
+    journal: &mut Journal,
+    diff: Value,
+) -> Result<Merged, Error> {
+    let fragment = decode_fragment_value(&diff)?;
+    doc.transaction(journal, |doc, journal| {
+        apply_fragment(doc, journal, fragment, diff)
+    })
+}
+
+/// Merges the rendering of a join reply, which replaces the fragment template
+/// rather than being merged into it
+pub(crate) fn merge_rendered(
+    doc: &mut NativeDocument,
+    journal: &mut Journal,
+    rendered: Value,
+) -> Result<Merged, Error> {
+    let fragment = decode_fragment_value(&rendered)?;
+    doc.transaction(journal, |doc, journal| {
+        doc.reset_fragment(journal);
+        apply_fragment(doc, journal, fragment, rendered)
+    })
+}
+
+fn apply_fragment(
+    doc: &mut NativeDocument,
+    journal: &mut Journal,
+    fragment: RootDiff,
+    diff: Value,
+) -> Result<Merged, Error> {
+    if let Err(err) = doc.merge_fragment(journal, fragment) {
+        log::error!("{err:?}");
+        return Err(Error::fragment_merge(&err));
+    }
+
+    // Diffs that only change dynamic text and attribute values are applied in
+    // place, skipping the render, parse and diff below
+    let streams = StreamOps::of(&diff);
+    let edits = match streams.is_empty() {
+        true => doc.plan_fragment(&diff),
+        false => None,
+    };
+    let changes = match edits {
+        Some(edits) => apply_edits(doc, journal, edits),
+        None => render_and_apply(doc, journal, &streams)?,
+    };
+    // Last, as it leaves the rendered LiveView unchanged when it fails: it is
+    // only recorded in the journal if the merge may be rolled back after it
+    // succeeded
+    let components = doc.merge_fragment_state(journal, diff)?;
+    Ok(Merged {
+        changes,
+        components,
+    })
+}
+
+/// Renders the merged fragment template, and merges the rendering into the
+/// document, after applying the stream operations of the diff.
+///
+/// Items added to or removed from a stream are reported first, then the
+/// changes made by the diff.
+fn render_and_apply(
+    doc: &mut NativeDocument,
+    journal: &mut Journal,
+    streams: &StreamOps,
+) -> Result<Vec<Change>, Error> {
+    doc.fragment_mut().invalidate();
+    let new_root = match doc.fragment_template.clone() {
+        Some(fragment) => fragment,
+        None => {
+            return Err(Error::FragmentMerge {
+                kind: "MissingTemplate".into(),
+                message: "the document has no fragment template to merge into".into(),
+            });
+        }
+    };
+
+    let rendered: String = match new_root.try_into() {
+        Ok(rendered) => rendered,
+        Err(err) => {
+            log::error!("{err:?}");
+            return Err(Error::render(&err));
+        }
+    };
+
+    let mut other = match Document::parse(&rendered) {
+        Ok(doc) => doc,
+        Err(err) => {
+            log::error!("{err:?}");
+            return Err(Error::parse(
+                &err,
+                "rendered fragment template is not valid markup",
+                &rendered,
+            ));
+        }
+    };
+
+    let mut changes: Vec<Change> = doc
+        .apply_streams(journal, &mut other, streams)
+        .into_iter()
+        .map(|edit| match edit {
+            StreamEdit::Added { node, parent } => Change::new(ChangeType::Add, node, Some(parent)),
+            StreamEdit::Removed { node, parent } => {
+                Change::new(ChangeType::Remove, node, Some(parent))
+            }
+        })
+        .collect();
+    changes.extend(apply(doc, journal, &other)?);
+    for change in &mut changes {
+        change.locate(doc);
+    }
+    Ok(changes)
+}
+
+/// Applies edits planned by `FragmentState::plan`, reporting each edited node
+/// as changed
+fn apply_edits(doc: &mut NativeDocument, journal: &mut Journal, edits: Vec<Edit>) -> Vec<Change> {
+    doc.apply_edits(journal, &edits);
+
+    // Edits of the same node are adjacent
+    let mut nodes: Vec<NodeRef> = edits.iter().map(Edit::node).collect();
+    nodes.dedup();
+    nodes
+        .into_iter()
+        .map(|node| {
+            let mut change = Change::new(ChangeType::Change, node, None);
+            change.describe(journal, doc);
+            change
+        })
+        .collect()
+}
+
+/// Decodes a fragment diff from JSON, to [`merge_fragment`] it.
+///
+/// The JSON is only parsed once: the `RootDiff` the core merges is decoded
+/// from the parsed value.
+pub(crate) fn decode_fragment(json: &[u8]) -> Result<FragmentDiff, Error> {
+    let diff = serde_json::from_slice(json).map_err(|err| {
+        log::error!("{err:?}");
+        Error::fragment_decode(".".into(), err)
+    })?;
+    let fragment = decode_fragment_value(&diff)?;
+    Ok(FragmentDiff { fragment, diff })
+}
+
+/// Decodes a fragment diff that was already parsed as JSON
+fn decode_fragment_value(diff: &Value) -> Result<RootDiff, Error> {
+    // Checked first, as components are resolved after the tree was patched
+    fragment::check_shared_statics(diff)?;
+    serde_path_to_error::deserialize(diff).map_err(|err| {
+        log::error!("{err:?}");
+        Error::fragment_decode(err.path().to_string(), err.into_inner())
+    })
+}
+
+/// What to do with a merge when the Kotlin handler throws.
+///
+/// Mirrors `Document.Companion.FailurePolicy`.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum FailurePolicy {
+    /// Keep the merged document, and skip the remaining notifications
+    SkipRemaining,
+    /// Restore the document to its state before the merge
+    Rollback,
+}
+
+impl FailurePolicy {
+    /// Asks the handler which policy it wants
+    fn of<'local>(env: &mut JNIEnv<'local>, interface: &JObject<'local>) -> Result<Self, Error> {
+        let cache = cache::get()?;
+        // SAFETY: `ffiFailurePolicy` was resolved on `Handler` as `()B`
+        let policy = unsafe {
+            env.call_method_unchecked(
+                interface,
+                cache.handler_failure_policy,
+                ReturnType::Primitive(Primitive::Byte),
+                &[],
+            )
+        }?
+        .b()?;
+        match policy {
+            0 => Ok(Self::SkipRemaining),
+            1 => Ok(Self::Rollback),
+            other => Err(Error::IllegalState(format!(
+                "unknown handler failure policy {other}"
+            ))),
+        }
+    }
+}
+
+/// Runs `merge` on the document behind `this`, then notifies the handler of
+/// the changes it made.
+///
+/// The document lock is only held while merging. If the handler throws, the
+/// remaining notifications are skipped, the document is rolled back if the
+/// handler's [`FailurePolicy`] asks for it, and the Java exception is left
+/// pending so that it reaches the caller as is. The merge records what it
+/// changes in a single [`Journal`], which undoes it both when the merge
+/// itself fails and on rollback.
+///
+/// The handler must not merge into the document it is notified about: the
+/// remaining notifications, and a rollback, would then describe a tree that no
+/// longer exists. Such a merge fails with an `IllegalStateException`, and so
+/// does a merge from another thread until the handler has returned.
+pub(crate) fn merge_and_notify<'local>(
+    env: &mut JNIEnv<'local>,
+    // handle of the merged document, passed back to the callback
+    this: jlong,
+    // Callback handle interface
+    interface: &JObject<'local>,
+    merge: impl FnOnce(&mut NativeDocument, &mut Journal) -> Result<Merged, Error>,
+) -> Result<(), Error> {
+    let policy = FailurePolicy::of(env, interface)?;
+    let doc = handle::document(this)?;
+    let begun = begin(&mut *handle::lock(&doc)?, policy, merge)?;
+    finish(env, this, &doc, interface, begun)
+}
+
+/// Merges `other` into the document behind `this`, then notifies the handler
+/// like [`merge_and_notify`] does.
+///
+/// Both documents are locked while merging, see [`handle::lock_pair`].
+pub(crate) fn merge_document_and_notify<'local>(
+    env: &mut JNIEnv<'local>,
+    // handle of the merged document, passed back to the callback
+    this: jlong,
+    other: &SharedDocument,
+    // Callback handle interface
+    interface: &JObject<'local>,
+) -> Result<(), Error> {
+    let policy = FailurePolicy::of(env, interface)?;
+    let doc = handle::document(this)?;
+    let begun = {
+        let (mut doc, other) = handle::lock_pair(&doc, other)?;
+        begin(&mut doc, policy, |doc, journal| merge(doc, journal, &other))?
+    };
+    finish(env, this, &doc, interface, begun)
+}
+
+/// Runs `merge` on a locked document, and marks it as notifying the handler
+/// of the changes, along with the journal to roll them back with, if any
+fn begin(
+    doc: &mut NativeDocument,
+    policy: FailurePolicy,
+    merge: impl FnOnce(&mut NativeDocument, &mut Journal) -> Result<Merged, Error>,
+) -> Result<(Merged, Option<Journal>), Error> {
+    match doc.notifying() {
+        Some(thread) if thread == thread::current().id() => {
+            return Err(Error::IllegalState(
+                "merge called from the handler of another merge into the same document; \
+                 merge once the handler has returned"
+                    .into(),
+            ))
+        }
+        Some(_) => {
+            return Err(Error::IllegalState(
+                "merge called while another thread notifies the handler of a merge into \
+                 the same document; merges into a document must not overlap"
+                    .into(),
+            ))
+        }
+        None => (),
+    }
+    let rollback = policy == FailurePolicy::Rollback;
+    let mut journal = Journal::new(rollback);
+    let merged = merge(doc, &mut journal)?;
+    doc.set_notifying(Some(thread::current().id()));
+    Ok((merged, rollback.then_some(journal)))
+}
+
+/// Notifies the handler of a merge [`begin`] made, with the document unlocked,
+/// and rolls it back if the handler fails
+fn finish<'local>(
+    env: &mut JNIEnv<'local>,
+    this: jlong,
+    doc: &SharedDocument,
+    interface: &JObject<'local>,
+    (merged, journal): (Merged, Option<Journal>),
+) -> Result<(), Error> {
+    // Clears the flag however notifying ends, after any rollback
+    let _notifying = Notifying(doc);
+    if let Err(err) = notify(env, this, &merged, interface) {
+        if let Some(journal) = journal {
+            handle::lock(doc)?.undo(journal);
+        }
+        return Err(err);
+    }
+    Ok(())
+}
+
+/// Marks a document as no longer notifying when dropped
+struct Notifying<'a>(&'a SharedDocument);
+
+impl Drop for Notifying<'_> {
+    fn drop(&mut self) {
+        // Blocks rather than fails if another thread holds the document, and
+        // clears the flag even once a panic poisoned the lock
+        let mut doc = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        doc.set_notifying(None);
+    }
+}
+
+/// Delivers a merge to the Kotlin handler: its changes first, then the live
+/// components it touched, if any
+fn notify<'local>(
+    env: &mut JNIEnv<'local>,
+    this: jlong,
+    merged: &Merged,
+    interface: &JObject<'local>,
+) -> Result<(), Error> {
+    notify_changes(env, this, &merged.changes, interface)?;
+    if !merged.components.is_empty() {
+        notify_components(env, this, &merged.components, interface)?;
+    }
+    Ok(())
+}
+
+/// Delivers `changes` to the Kotlin handler: all at once to a `BatchHandler`,
+/// otherwise one call per change, stopping at the first one that throws
+fn notify_changes<'local>(
+    env: &mut JNIEnv<'local>,
+    this: jlong,
+    changes: &[Change],
+    interface: &JObject<'local>,
+) -> Result<(), Error> {
+    let cache = cache::get()?;
+    if env.is_instance_of(interface, cache::class(&cache.batch_handler))? {
+        return notify_batch(env, this, changes, interface);
+    }
+
+    for change in changes {
+        let [change_type, node, parent, index, previous_sibling, old_index] = change.record();
+        let details = change.details_to_java(env)?;
+        // SAFETY: the arguments match `cache::ON_HANDLE_SIGNATURE`
+        unsafe {
+            env.call_method_unchecked(
+                interface,
+                cache.handler_on_handle,
+                ReturnType::Primitive(Primitive::Void),
+                &[
+                    JValue::Long(this).as_jni(),
+                    JValue::Byte(change_type as jbyte).as_jni(),
+                    JValue::Int(node).as_jni(),
+                    JValue::Int(parent).as_jni(),
+                    JValue::Int(index).as_jni(),
+                    JValue::Int(previous_sibling).as_jni(),
+                    JValue::Object(&details).as_jni(),
+                    JValue::Int(old_index).as_jni(),
+                ],
+            )
+        }?;
+        // Free the local reference now, rather than when the binding returns
+        env.delete_local_ref(details)?;
+    }
+    Ok(())
+}
+
+/// Delivers every change in a single call, packed as consecutive records of
+/// `Change::RECORD_LEN` ints
+fn notify_batch<'local>(
+    env: &mut JNIEnv<'local>,
+    this: jlong,
+    changes: &[Change],
+    interface: &JObject<'local>,
+) -> Result<(), Error> {
+    let records: Vec<jint> = changes.iter().flat_map(Change::record).collect();
+    let array = env.new_int_array(records.len() as jsize)?;
+    env.set_int_array_region(&array, 0, &records)?;
+
+    let cache = cache::get()?;
+    let details = env.new_object_array(
+        changes.len() as jsize,
+        cache::class(&cache.change_details),
+        JObject::null(),
+    )?;
+    for (i, change) in changes.iter().enumerate() {
+        if change.details.is_some() {
+            let element = change.details_to_java(env)?;
+            env.set_object_array_element(&details, i as jsize, &element)?;
+            env.delete_local_ref(element)?;
+        }
+    }
+
+    // SAFETY: the arguments match `cache::ON_BATCH_SIGNATURE`
+    unsafe {
+        env.call_method_unchecked(
+            interface,
+            cache.batch_handler_on_batch,
+            ReturnType::Primitive(Primitive::Void),
+            &[
+                JValue::Long(this).as_jni(),
+                JValue::Object(&array).as_jni(),
+                JValue::Object(&details).as_jni(),
+            ],
+        )
+    }?;
+    Ok(())
+}
+
+fn notify_components<'local>(
+    env: &mut JNIEnv<'local>,
+    this: jlong,
+    components: &ComponentChanges,
+    interface: &JObject<'local>,
+) -> Result<(), Error> {
+    let rendered = env.new_int_array(components.rendered.len() as jsize)?;
+    env.set_int_array_region(&rendered, 0, &components.rendered)?;
+    let destroyed = env.new_int_array(components.destroyed.len() as jsize)?;
+    env.set_int_array_region(&destroyed, 0, &components.destroyed)?;
+
+    let cache = cache::get()?;
+    // SAFETY: the arguments match `cache::ON_COMPONENTS_SIGNATURE`
+    unsafe {
+        env.call_method_unchecked(
+            interface,
+            cache.handler_on_components,
+            ReturnType::Primitive(Primitive::Void),
+            &[
+                JValue::Long(this).as_jni(),
+                JValue::Object(&rendered).as_jni(),
+                JValue::Object(&destroyed).as_jni(),
+            ],
+        )
+    }?;
+    Ok(())
+}
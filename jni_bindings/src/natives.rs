@@ -0,0 +1,242 @@
+//! The table of native methods, registered with `RegisterNatives` in
+//! `JNI_OnLoad`.
+//!
+//! Bindings are not exported under mangled `Java_…` names, so moving or
+//! renaming a Kotlin class or method only needs a change here.
+
+use std::ffi::c_void;
+#[cfg(feature = "native-checks")]
+use std::ptr;
+
+use jni::{objects::JClass, JNIEnv, NativeMethod};
+
+use crate::error::Error;
+
+const DOCUMENT_COMPANION_CLASS: &str = "org/phoenixframework/liveview/lib/Document$Companion";
+const ELEMENT_CLASS: &str = "org/phoenixframework/liveview/lib/Node$Element";
+const SYMBOLS_CLASS: &str = "org/phoenixframework/liveview/lib/Symbols";
+const JS_COMMAND_COMPANION_CLASS: &str = "org/phoenixframework/liveview/lib/JsCommand$Companion";
+#[cfg(feature = "native-checks")]
+const NATIVE_CHECKS_CLASS: &str = "org/phoenixframework/liveview/lib/NativeChecks";
+
+const HANDLER: &str = "Lorg/phoenixframework/liveview/lib/Document$Companion$Handler;";
+const CHANNEL_EVENT: &str = "Lorg/phoenixframework/liveview/lib/ChannelEvent;";
+const JOIN_PARAMS: &str = "Lorg/phoenixframework/liveview/lib/JoinParams;";
+
+/// A native method of a Kotlin class
+struct Native<'a> {
+    name: &'a str,
+    signature: String,
+    fn_ptr: *mut c_void,
+}
+
+macro_rules! natives {
+    ($($name:literal $signature:expr => $function:path),* $(,)?) => {
+        vec![$(Native {
+            name: $name,
+            signature: $signature.to_string(),
+            fn_ptr: $function as *mut c_void,
+        }),*]
+    };
+}
+
+/// Every class with native methods, and the functions implementing them
+fn table() -> Vec<(&'static str, Vec<Native<'static>>)> {
+    use crate::*;
+
+    vec![
+        (
+            cache::DOCUMENT_CLASS,
+            natives![
+                "empty" "()J" => document_empty,
+                "drop" "(J)V" => document_drop,
+                "do_to_string" "(J)Ljava/lang/String;" => document_do_to_string,
+                "node_to_string" "(JI)Ljava/lang/String;" => document_node_to_string,
+                "root" "(J)I" => document_root,
+                "get_node" "(JI)J" => document_get_node,
+                "drop_node" "(J)V" => document_drop_node,
+                "get_node_leaf_string" "(JI)Ljava/lang/String;" => document_get_node_leaf_string,
+                "get_node_type" "(J)B" => document_get_node_type,
+                "get_node_element" "(J)J" => document_get_node_element,
+                "get_children" "(JI)[I" => document_get_children,
+                "get_parent" "(JI)I" => document_get_parent,
+                "snapshot" "(JI)[B" => document_snapshot,
+                "set_key_attribute" "(JLjava/lang/String;)V" => document_set_key_attribute,
+                "component_of" "(JI)I" => document_component_of,
+                "prune_components" "(J[I)V" => document_prune_components,
+                "event_payloads" "(JILjava/lang/String;)[Ljava/lang/String;"
+                    => document_event_payloads,
+                "join_params" format!("(J){JOIN_PARAMS}") => document_join_params,
+                "merge" format!("(JJ{HANDLER})V") => document_merge,
+                "merge_fragment_json" format!("(JLjava/lang/String;{HANDLER})V")
+                    => document_merge_fragment_json,
+                "merge_fragment_json_bytes" format!("(J[BII{HANDLER})V")
+                    => document_merge_fragment_json_bytes,
+                "merge_fragment_json_buffer" format!("(JLjava/nio/ByteBuffer;II{HANDLER})V")
+                    => document_merge_fragment_json_buffer,
+                "handle_channel_message"
+                    format!("(JLjava/lang/String;{HANDLER}){CHANNEL_EVENT}")
+                    => document_handle_channel_message,
+                "handle_channel_message_bytes" format!("(J[BII{HANDLER}){CHANNEL_EVENT}")
+                    => document_handle_channel_message_bytes,
+            ],
+        ),
+        (
+            DOCUMENT_COMPANION_CLASS,
+            natives![
+                "do_parse" "(Ljava/lang/String;)J" => document_companion_do_parse,
+                "do_parse_bytes" "([BII)J" => document_companion_do_parse_bytes,
+                "do_parse_buffer" "(Ljava/nio/ByteBuffer;II)J" => document_companion_do_parse_buffer,
+                "initialize_log" "()V" => document_companion_initialize_log,
+            ],
+        ),
+        (
+            ELEMENT_CLASS,
+            natives![
+                "drop" "(J)V" => element_drop,
+                "get_namespace" "(J)I" => element_get_namespace,
+                "get_namespace_string" "(J)Ljava/lang/String;" => element_get_namespace_string,
+                "get_tag" "(J)I" => element_get_tag,
+                "get_tag_string" "(J)Ljava/lang/String;" => element_get_tag_string,
+                "get_attributes" "(J)[Lorg/phoenixframework/liveview/lib/Attribute;"
+                    => element_get_attributes,
+            ],
+        ),
+        (
+            cache::ATTRIBUTE_CLASS,
+            natives![
+                "drop" "(J)V" => attribute_drop,
+                "get_name" "(J)I" => attribute_get_name,
+                "get_name_string" "(J)Ljava/lang/String;" => attribute_get_name_string,
+                "get_namespace" "(J)I" => attribute_get_namespace,
+                "get_namespace_string" "(J)Ljava/lang/String;" => attribute_get_namespace_string,
+                "get_value" "(J)Ljava/lang/String;" => attribute_get_value,
+            ],
+        ),
+        (
+            SYMBOLS_CLASS,
+            natives![
+                "resolve" "(I)[Ljava/lang/String;" => symbols_resolve,
+            ],
+        ),
+        (
+            JS_COMMAND_COMPANION_CLASS,
+            natives![
+                "do_decode" "(Ljava/lang/String;)[B" => js_command_companion_do_decode,
+            ],
+        ),
+    ]
+}
+
+/// The natives of `NativeChecks`, which only the Kotlin tests declare
+#[cfg(feature = "native-checks")]
+fn checks_table() -> Vec<(&'static str, Vec<Native<'static>>)> {
+    use crate::checks::*;
+
+    vec![(
+        NATIVE_CHECKS_CLASS,
+        natives![
+            "panic" "(Ljava/lang/String;)V" => native_checks_panic,
+            "merges_in_place" "(JLjava/lang/String;)Z" => native_checks_merges_in_place,
+            "resolve" "([Ljava/lang/String;)V" => native_checks_resolve,
+            "checkNatives" "([Ljava/lang/String;)V" => native_checks_check_natives,
+        ],
+    )]
+}
+
+/// Registers every native method, or fails with the list of those the Kotlin
+/// classes do not declare.
+///
+/// With the `native-checks` feature, the natives of `NativeChecks` are also
+/// registered, if the class is there at all: only the tests declare it.
+pub(crate) fn register(env: &mut JNIEnv) -> Result<(), Error> {
+    for (class, natives) in find(env, table())? {
+        register_class(env, &class, &natives)?;
+    }
+    #[cfg(feature = "native-checks")]
+    if env.find_class(NATIVE_CHECKS_CLASS).is_ok() {
+        for (class, natives) in find(env, checks_table())? {
+            register_class(env, &class, &natives)?;
+        }
+    } else {
+        env.exception_clear()?;
+    }
+    Ok(())
+}
+
+/// Checks each `[class, method, signature]` like [`register`] does before
+/// registering anything, so that tests can check how missing or misspelled
+/// natives fail. Nothing is registered.
+#[cfg(feature = "native-checks")]
+pub(crate) fn check(env: &mut JNIEnv, natives: &[[String; 3]]) -> Result<(), Error> {
+    let table = natives
+        .iter()
+        .map(|[class_name, name, signature]| {
+            let native = Native {
+                name,
+                signature: signature.clone(),
+                fn_ptr: ptr::null_mut(),
+            };
+            (class_name.as_str(), vec![native])
+        })
+        .collect();
+    find(env, table).map(drop)
+}
+
+/// The class declaring each group of natives in `table`, or an error listing
+/// every native the Kotlin classes do not declare
+fn find<'local, 'a>(
+    env: &mut JNIEnv<'local>,
+    table: Vec<(&str, Vec<Native<'a>>)>,
+) -> Result<Vec<(JClass<'local>, Vec<Native<'a>>)>, Error> {
+    let mut missing = vec![];
+    let mut classes = vec![];
+
+    // Check everything first, since `RegisterNatives` stops at the first
+    // method it can not find
+    for (class_name, natives) in table {
+        let class = match env.find_class(class_name) {
+            Ok(class) => class,
+            Err(_) => {
+                env.exception_clear()?;
+                missing.push(format!("class {class_name}"));
+                continue;
+            }
+        };
+        for native in &natives {
+            if env
+                .get_method_id(&class, native.name, &native.signature)
+                .is_err()
+            {
+                env.exception_clear()?;
+                missing.push(format!(
+                    "native method {class_name}.{}{}",
+                    native.name, native.signature
+                ));
+            }
+        }
+        classes.push((class, natives));
+    }
+    if !missing.is_empty() {
+        return Err(Error::Load(format!(
+            "liveview_native_core could not register {} (renamed, or stripped by R8?)",
+            missing.join(", ")
+        )));
+    }
+    Ok(classes)
+}
+
+fn register_class(env: &mut JNIEnv, class: &JClass, natives: &[Native]) -> Result<(), Error> {
+    let methods: Vec<NativeMethod> = natives
+        .iter()
+        .map(|native| NativeMethod {
+            name: native.name.into(),
+            sig: native.signature.as_str().into(),
+            fn_ptr: native.fn_ptr,
+        })
+        .collect();
+    // Every function in the table must be an `extern "system"` fn whose
+    // parameters and return type match its signature
+    env.register_native_methods(class, &methods)?;
+    Ok(())
+}
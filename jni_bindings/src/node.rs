@@ -0,0 +1,73 @@
+//! Owned copies of document nodes handed out to Kotlin.
+//!
+//! Unlike the `ffi` types these do not borrow from the `Document`, so they stay
+//! valid after the document is merged into or dropped.
+
+use jni::sys::jbyte;
+use liveview_native_core::{dom, ffi::NodeType};
+
+use crate::symbols::{self, Name};
+
+pub(crate) enum Node {
+    Root,
+    Element(Element),
+    Leaf,
+}
+
+impl Node {
+    pub(crate) fn ty(&self) -> jbyte {
+        let ty = match self {
+            Self::Root => NodeType::Root,
+            Self::Element(_) => NodeType::Element,
+            Self::Leaf => NodeType::Leaf,
+        };
+        ty as jbyte
+    }
+}
+
+impl From<&dom::Node> for Node {
+    fn from(node: &dom::Node) -> Self {
+        match node {
+            dom::Node::Root => Self::Root,
+            dom::Node::Leaf(_) => Self::Leaf,
+            dom::Node::Element(ref elem) => Self::Element(elem.into()),
+        }
+    }
+}
+
+/// An element, with its names interned, see [`symbols`]
+#[derive(Clone)]
+pub(crate) struct Element {
+    /// The empty name if the element has no namespace
+    pub namespace: Name,
+    pub tag: Name,
+    pub attributes: Vec<Attribute>,
+}
+
+impl From<&dom::Element> for Element {
+    fn from(elem: &dom::Element) -> Self {
+        Self {
+            namespace: symbols::intern(elem.name.namespace.map_or("", |ns| ns.as_str())),
+            tag: symbols::intern(elem.name.name.as_str()),
+            attributes: elem.attributes().iter().map(Attribute::from).collect(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct Attribute {
+    /// The empty name if the attribute has no namespace
+    pub namespace: Name,
+    pub name: Name,
+    pub value: String,
+}
+
+impl From<&dom::Attribute> for Attribute {
+    fn from(attr: &dom::Attribute) -> Self {
+        Self {
+            namespace: symbols::intern(attr.name.namespace.map_or("", |ns| ns.as_str())),
+            name: symbols::intern(attr.name.name.as_str()),
+            value: attr.value.as_str().unwrap_or_default().to_owned(),
+        }
+    }
+}
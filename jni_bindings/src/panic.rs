@@ -0,0 +1,90 @@
+//! Panic capture for the JNI boundary.
+//!
+//! A panic must never unwind across an `extern "system"` function, so every
+//! binding runs inside `catch_unwind` (see [`crate::error::jni_call`]). The
+//! payload alone carries no location or backtrace, so a panic hook records both
+//! on the panicking thread for the guard to pick up.
+
+use std::{any::Any, backtrace::Backtrace, cell::RefCell, panic, sync::Once};
+
+static HOOK: Once = Once::new();
+static LOGGING: Once = Once::new();
+
+thread_local! {
+    static LAST_PANIC: RefCell<Option<Captured>> = const { RefCell::new(None) };
+}
+
+struct Captured {
+    location: Option<String>,
+    backtrace: String,
+}
+
+/// Details of a caught panic, ready to be thrown as a `NativePanicException`
+#[derive(Debug)]
+pub(crate) struct PanicReport {
+    pub message: String,
+    pub backtrace: String,
+}
+
+/// Chains our hook in front of the current one, which keeps handling the
+/// panic as before
+fn chain_hook() {
+    let previous = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        let captured = Captured {
+            location: info.location().map(|location| location.to_string()),
+            backtrace: Backtrace::force_capture().to_string(),
+        };
+        LAST_PANIC.with(|last| *last.borrow_mut() = Some(captured));
+        previous(info);
+    }));
+}
+
+/// Installs the hook, once per process
+pub(crate) fn ensure_hook() {
+    HOOK.call_once(chain_hook);
+}
+
+/// Runs `init`, which replaces the panic hook with one that logs panics, e.g.
+/// `log_panics::init`, then chains our hook back in front of it. Only the
+/// first call does anything, so hooks do not pile up.
+pub(crate) fn init_logging(init: impl FnOnce()) {
+    // Chaining is not atomic, so the two must not run at the same time
+    ensure_hook();
+    LOGGING.call_once(|| {
+        init();
+        chain_hook();
+    });
+}
+
+/// Builds the report for a panic caught on this thread
+pub(crate) fn report(payload: Box<dyn Any + Send>) -> PanicReport {
+    let message = if let Some(message) = payload.downcast_ref::<&str>() {
+        (*message).to_owned()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "Box<dyn Any>".to_owned()
+    };
+
+    match LAST_PANIC.with(|last| last.borrow_mut().take()) {
+        Some(Captured {
+            location: Some(location),
+            backtrace,
+        }) => PanicReport {
+            message: format!("Rust panic at {location}: {message}"),
+            backtrace,
+        },
+        Some(Captured {
+            location: None,
+            backtrace,
+        }) => PanicReport {
+            message: format!("Rust panic: {message}"),
+            backtrace,
+        },
+        None => PanicReport {
+            message: format!("Rust panic: {message}"),
+            backtrace: String::new(),
+        },
+    }
+}
@@ -0,0 +1,112 @@
+//! A whole subtree serialised into one buffer, so that Kotlin can walk it
+//! without a native call per node.
+//!
+//! The layout is read by `SnapshotNode.decode`, and every integer is big
+//! endian (the default byte order of `java.nio.ByteBuffer`):
+//!
+//! ```text
+//! snapshot := u32 string_count, string*, u32 node_count, node*
+//! string   := u32 byte_len, UTF-8 bytes
+//! node     := i32 ref, u8 type, body, u32 child_count
+//! body     := (root)    nothing
+//!           | (element) str? namespace, str tag, u32 attr_count, attr*
+//!           | (leaf)    str text
+//! attr     := str? namespace, str name, str? value
+//! str      := i32 index into the strings, or -1 for `str?` if absent
+//! ```
+//!
+//! Nodes are written in pre-order, each followed by its children, so the
+//! decoder rebuilds the tree with a single pass. Strings are deduplicated, so
+//! a tag or attribute name is sent once however many elements use it.
+
+use std::collections::HashMap;
+
+use liveview_native_core::{
+    dom,
+    dom::{Document, NodeRef},
+    ffi::NodeType,
+};
+
+/// Serialises `node` and everything below it
+pub(crate) fn encode(doc: &Document, node: NodeRef) -> Vec<u8> {
+    let mut encoder = Encoder::default();
+    let mut stack = vec![node];
+    while let Some(node) = stack.pop() {
+        encoder.node(doc, node);
+        // Pushed in reverse, so that they are popped in document order
+        stack.extend(doc.children(node).iter().rev().copied());
+    }
+
+    let mut buf = Vec::with_capacity(encoder.strings_len + encoder.nodes.len() + 8);
+    put_u32(&mut buf, encoder.strings.len());
+    for string in &encoder.strings {
+        put_u32(&mut buf, string.len());
+        buf.extend_from_slice(string.as_bytes());
+    }
+    put_u32(&mut buf, encoder.node_count);
+    buf.extend_from_slice(&encoder.nodes);
+    buf
+}
+
+#[derive(Default)]
+struct Encoder<'a> {
+    strings: Vec<&'a str>,
+    indices: HashMap<&'a str, i32>,
+    /// The encoded size of `strings`
+    strings_len: usize,
+    node_count: usize,
+    nodes: Vec<u8>,
+}
+
+impl<'a> Encoder<'a> {
+    fn node(&mut self, doc: &'a Document, node: NodeRef) {
+        self.node_count += 1;
+        self.nodes
+            .extend_from_slice(&(node.as_u32() as i32).to_be_bytes());
+        match doc.get(node) {
+            dom::Node::Root => self.nodes.push(NodeType::Root as u8),
+            dom::Node::Element(elem) => {
+                self.nodes.push(NodeType::Element as u8);
+                self.optional_string(elem.name.namespace.map(|ns| ns.as_str()));
+                self.string(elem.name.name.as_str());
+                let attributes = elem.attributes();
+                put_u32(&mut self.nodes, attributes.len());
+                for attr in attributes {
+                    self.optional_string(attr.name.namespace.map(|ns| ns.as_str()));
+                    self.string(attr.name.name.as_str());
+                    self.optional_string(attr.value.as_str());
+                }
+            }
+            dom::Node::Leaf(text) => {
+                self.nodes.push(NodeType::Leaf as u8);
+                self.string(text.as_str());
+            }
+        }
+        put_u32(&mut self.nodes, doc.children(node).len());
+    }
+
+    fn string(&mut self, string: &'a str) {
+        let index = match self.indices.get(string) {
+            Some(&index) => index,
+            None => {
+                let index = self.strings.len() as i32;
+                self.strings.push(string);
+                self.indices.insert(string, index);
+                self.strings_len += 4 + string.len();
+                index
+            }
+        };
+        self.nodes.extend_from_slice(&index.to_be_bytes());
+    }
+
+    fn optional_string(&mut self, string: Option<&'a str>) {
+        match string {
+            Some(string) => self.string(string),
+            None => self.nodes.extend_from_slice(&(-1i32).to_be_bytes()),
+        }
+    }
+}
+
+fn put_u32(buf: &mut Vec<u8>, value: usize) {
+    buf.extend_from_slice(&(value as u32).to_be_bytes());
+}
@@ -0,0 +1,303 @@
+//! LiveView streams, rendered into containers marked `phx-update="stream"`.
+//!
+//! The server sends each streamed item once, and the client keeps it until it
+//! is told to delete it. A rendering of the fragment template only holds the
+//! items inserted by the latest stream diff, so before the document is diffed
+//! against a rendering, the stream operations of the diff are applied to the
+//! document directly, and each container in the rendering is given the
+//! resulting children. The diff then only sees the changes made to items that
+//! were inserted again.
+
+use std::collections::{HashMap, HashSet};
+
+use liveview_native_core::{
+    dom,
+    dom::{Document, NodeRef},
+};
+use serde_json::Value;
+
+use crate::update::{copy, id_of, Mode};
+
+const STREAM: &str = "stream";
+
+/// The stream operations of a fragment diff, as sent by `stream/3`,
+/// `stream_insert/3`, `stream_delete/3` and `reset: true`
+#[derive(Default)]
+pub(crate) struct StreamOps {
+    /// By DOM id of the item
+    inserts: HashMap<String, Insert>,
+    /// DOM ids of the items to delete
+    deletes: HashSet<String>,
+    /// Refs of the streams to empty before inserting
+    resets: HashSet<String>,
+}
+
+struct Insert {
+    stream: String,
+    /// `-1` to append, otherwise the index to insert at
+    at: i64,
+    /// Trims the container to this many items after inserting: the first ones
+    /// if positive, the last ones if negative
+    limit: Option<i64>,
+}
+
+/// A child added to or removed from a stream container
+pub(crate) enum StreamEdit {
+    Added { node: NodeRef, parent: NodeRef },
+    Removed { node: NodeRef, parent: NodeRef },
+}
+
+impl StreamOps {
+    /// Collects the operations of every stream in `diff`, including those in
+    /// components
+    pub(crate) fn of(diff: &Value) -> Self {
+        let mut ops = Self::default();
+        let mut values = vec![diff];
+        while let Some(value) = values.pop() {
+            match value {
+                Value::Object(object) => {
+                    if let Some(Value::Array(stream)) = object.get(STREAM) {
+                        ops.add(stream);
+                    }
+                    values.extend(object.values());
+                }
+                Value::Array(array) => values.extend(array),
+                _ => (),
+            }
+        }
+        ops
+    }
+
+    /// Adds a `[ref, inserts, delete_ids, reset]` entry
+    fn add(&mut self, stream: &[Value]) {
+        let Some(stream_ref) = stream.first().and_then(key) else {
+            return;
+        };
+        match stream.get(1) {
+            // `[[dom_id, at, limit], ..]`
+            Some(Value::Array(inserts)) => {
+                for insert in inserts.iter().filter_map(Value::as_array) {
+                    if let Some(id) = insert.first().and_then(key) {
+                        self.insert(&stream_ref, id, insert.get(1), insert.get(2));
+                    }
+                }
+            }
+            // `{dom_id: at}` or `{dom_id: [at, limit]}`, before LiveView 0.20
+            Some(Value::Object(inserts)) => {
+                for (id, insert) in inserts {
+                    match insert {
+                        Value::Array(insert) => {
+                            self.insert(&stream_ref, id.clone(), insert.first(), insert.get(1))
+                        }
+                        at => self.insert(&stream_ref, id.clone(), Some(at), None),
+                    }
+                }
+            }
+            _ => (),
+        }
+        if let Some(Value::Array(deletes)) = stream.get(2) {
+            self.deletes.extend(deletes.iter().filter_map(key));
+        }
+        if stream.get(3).and_then(Value::as_bool) == Some(true) {
+            self.resets.insert(stream_ref);
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.inserts.is_empty() && self.deletes.is_empty() && self.resets.is_empty()
+    }
+
+    fn insert(&mut self, stream: &str, id: String, at: Option<&Value>, limit: Option<&Value>) {
+        let insert = Insert {
+            stream: stream.to_owned(),
+            at: at.and_then(Value::as_i64).unwrap_or(-1),
+            limit: limit.and_then(Value::as_i64),
+        };
+        self.inserts.insert(id, insert);
+    }
+}
+
+/// Applies `ops` to the stream containers of `doc`, then gives every stream
+/// container of `rendered` the children of its counterpart in `doc`.
+///
+/// `containers` maps the ref of each stream to the DOM id of its container, so
+/// that a stream can be reset without inserting anything.
+pub(crate) fn apply(
+    doc: &mut Document,
+    rendered: &mut Document,
+    ops: &StreamOps,
+    containers: &mut HashMap<String, String>,
+) -> Vec<StreamEdit> {
+    let live: HashMap<String, NodeRef> = find_containers(doc)
+        .into_iter()
+        .map(|(node, id)| (id, node))
+        .collect();
+    let mut edits = vec![];
+
+    for (container, container_id) in find_containers(rendered) {
+        let items: Vec<(String, NodeRef)> = rendered
+            .children(container)
+            .iter()
+            .filter_map(|&child| Some((id_of(rendered.get(child))?.to_owned(), child)))
+            .filter(|(id, _)| ops.inserts.contains_key(id))
+            .collect();
+        for (id, _) in &items {
+            containers.insert(ops.inserts[id].stream.clone(), container_id.clone());
+        }
+        // A new container is added by the diff with whatever was rendered
+        let Some(&target) = live.get(&container_id) else {
+            continue;
+        };
+
+        let mut stream = Stream {
+            doc: &mut *doc,
+            container: target,
+            edits: vec![],
+        };
+        // A reset keeps only the items inserted again
+        let reset = ops
+            .resets
+            .iter()
+            .any(|stream_ref| containers.get(stream_ref) == Some(&container_id));
+        stream.remove_where(|id| {
+            ops.deletes.contains(id) || (reset && !ops.inserts.contains_key(id))
+        });
+
+        // Items inserted again are updated in place, by the diff
+        let mut updated = HashMap::new();
+        for (id, item) in items {
+            if stream.find(&id).is_some() {
+                updated.insert(id, item);
+                continue;
+            }
+            let insert = &ops.inserts[&id];
+            let node = copy(rendered, item, stream.doc);
+            stream.insert(node, insert.at);
+            if let Some(limit) = insert.limit {
+                stream.limit(limit);
+            }
+        }
+        edits.extend(stream.edits);
+
+        let children: Vec<NodeRef> = doc
+            .children(target)
+            .iter()
+            .map(
+                |&child| match id_of(doc.get(child)).and_then(|id| updated.get(id)) {
+                    Some(&item) => item,
+                    None => copy(doc, child, rendered),
+                },
+            )
+            .collect();
+        for child in rendered.children(container).to_vec() {
+            rendered.detach(child);
+        }
+        for child in children {
+            rendered.append_child(container, child);
+        }
+    }
+    edits
+}
+
+/// Whether the children of `elem` are the items of a stream
+fn is_container(elem: &dom::Element) -> bool {
+    Mode::of(elem) == Mode::Stream
+}
+
+/// The edits made to a single stream container
+struct Stream<'a> {
+    doc: &'a mut Document,
+    container: NodeRef,
+    edits: Vec<StreamEdit>,
+}
+
+impl Stream<'_> {
+    fn find(&self, id: &str) -> Option<NodeRef> {
+        self.doc
+            .children(self.container)
+            .iter()
+            .copied()
+            .find(|&child| id_of(self.doc.get(child)) == Some(id))
+    }
+
+    fn insert(&mut self, node: NodeRef, at: i64) {
+        let children = self.doc.children(self.container);
+        match usize::try_from(at).ok().and_then(|at| children.get(at)) {
+            Some(&before) => self.doc.insert_before(node, before),
+            None => self.doc.append_child(self.container, node),
+        }
+        self.edits.push(StreamEdit::Added {
+            node,
+            parent: self.container,
+        });
+    }
+
+    fn limit(&mut self, limit: i64) {
+        let children = self.doc.children(self.container).to_vec();
+        let keep = limit.unsigned_abs() as usize;
+        if children.len() <= keep {
+            return;
+        }
+        let excess = if limit < 0 {
+            &children[..children.len() - keep]
+        } else {
+            &children[keep..]
+        };
+        for &child in excess {
+            self.remove(child);
+        }
+    }
+
+    fn remove_where(&mut self, mut f: impl FnMut(&str) -> bool) {
+        let children = self.doc.children(self.container).to_vec();
+        for child in children {
+            if id_of(self.doc.get(child)).is_some_and(&mut f) {
+                self.remove(child);
+            }
+        }
+    }
+
+    fn remove(&mut self, node: NodeRef) {
+        self.doc.detach(node);
+        // An item both inserted and trimmed by this diff was never there
+        let added = self.edits.iter().position(
+            |edit| matches!(edit, StreamEdit::Added { node: added, .. } if *added == node),
+        );
+        match added {
+            Some(i) => {
+                self.edits.remove(i);
+            }
+            None => self.edits.push(StreamEdit::Removed {
+                node,
+                parent: self.container,
+            }),
+        }
+    }
+}
+
+/// Every stream container of `doc`, with its DOM id
+fn find_containers(doc: &Document) -> Vec<(NodeRef, String)> {
+    let mut containers = vec![];
+    let mut stack = vec![doc.root()];
+    while let Some(node) = stack.pop() {
+        if let dom::Node::Element(elem) = doc.get(node) {
+            if is_container(elem) {
+                if let Some(id) = id_of(doc.get(node)) {
+                    containers.push((node, id.to_owned()));
+                }
+                continue;
+            }
+        }
+        stack.extend(doc.children(node));
+    }
+    containers
+}
+
+/// A DOM id or stream ref, sent as a string or a number
+fn key(value: &Value) -> Option<String> {
+    match value {
+        Value::String(key) => Some(key.clone()),
+        Value::Number(key) => Some(key.to_string()),
+        _ => None,
+    }
+}
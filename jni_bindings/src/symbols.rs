@@ -0,0 +1,91 @@
+//! Interned tag and attribute names.
+//!
+//! A template only uses a few hundred distinct names, so rather than a new
+//! Java `String` per element and attribute, the bindings hand out the stable
+//! integer ID of each name. Kotlin resolves an ID with `Symbols.name`, which
+//! fetches the names it has not seen yet with `resolve` and caches them for
+//! the lifetime of the process.
+//!
+//! Names are never evicted, as Kotlin caches them, so the table is bounded:
+//! once it holds [`CAPACITY`] names, new ones, like generated `data-*` or
+//! `phx-value-*` attributes, are handed out as strings instead.
+
+use std::{
+    collections::BTreeMap,
+    sync::{Mutex, MutexGuard},
+};
+
+use jni::sys::jint;
+
+/// The ID of an interned name, an index into the table
+pub(crate) type Symbol = jint;
+
+/// The most names the table holds
+const CAPACITY: usize = 4096;
+
+/// The symbol of a name that was not interned, see [`Name`]
+pub(crate) const NOT_INTERNED: Symbol = -1;
+
+static SYMBOLS: Mutex<Symbols> = Mutex::new(Symbols {
+    names: Vec::new(),
+    ids: BTreeMap::new(),
+});
+
+struct Symbols {
+    names: Vec<Box<str>>,
+    ids: BTreeMap<Box<str>, Symbol>,
+}
+
+fn table() -> MutexGuard<'static, Symbols> {
+    // Names are only ever appended, so a poisoned lock is still usable
+    SYMBOLS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// A tag or attribute name, interned unless the table was full
+#[derive(Clone)]
+pub(crate) enum Name {
+    Symbol(Symbol),
+    String(Box<str>),
+}
+
+impl Name {
+    /// The symbol of the name, or [`NOT_INTERNED`]
+    pub(crate) fn symbol(&self) -> Symbol {
+        match self {
+            Self::Symbol(symbol) => *symbol,
+            Self::String(_) => NOT_INTERNED,
+        }
+    }
+
+    /// The name itself, if it was not interned
+    pub(crate) fn string(&self) -> Option<&str> {
+        match self {
+            Self::Symbol(_) => None,
+            Self::String(name) => Some(name),
+        }
+    }
+}
+
+/// `name`, interning it if it is new and the table is not full
+pub(crate) fn intern(name: &str) -> Name {
+    let mut symbols = table();
+    if let Some(&symbol) = symbols.ids.get(name) {
+        return Name::Symbol(symbol);
+    }
+    if symbols.names.len() == CAPACITY {
+        return Name::String(name.into());
+    }
+    let symbol = symbols.names.len() as Symbol;
+    symbols.names.push(name.into());
+    symbols.ids.insert(name.into(), symbol);
+    Name::Symbol(symbol)
+}
+
+/// Every name interned since `from`, in order of their IDs
+pub(crate) fn since(from: Symbol) -> Vec<Box<str>> {
+    let symbols = table();
+    let from = (from.max(0) as usize).min(symbols.names.len());
+    symbols.names[from..].to_vec()
+}
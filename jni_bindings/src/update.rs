@@ -0,0 +1,206 @@
+//! `phx-update`, which tells the LiveView client what to keep of the children
+//! of an element when a new rendering is merged.
+//!
+//! Like the JS client, a container is matched with its counterpart in the
+//! document by its DOM id. Before the document is diffed against a rendering,
+//! each container of the rendering is given what the document already holds:
+//!
+//! - `ignore` keeps the children and attributes of the element, except for
+//!   `data-*` attributes, which come from the rendering
+//! - `append` and `prepend` keep the children, and add the rendered children
+//!   after or before them. A rendered child with the id of a kept one replaces
+//!   it in place.
+//! - `stream` is handled by [`stream`](crate::stream), since its items depend
+//!   on the diff rather than on the rendering
+
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+};
+
+use liveview_native_core::{
+    dom,
+    dom::{Document, NodeRef},
+};
+
+const PHX_UPDATE: &str = "phx-update";
+const DATA_PREFIX: &str = "data-";
+
+/// The values of `phx-update`
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Mode {
+    Replace,
+    Ignore,
+    Append,
+    Prepend,
+    Stream,
+}
+
+impl Mode {
+    pub(crate) fn of(elem: &dom::Element) -> Self {
+        let value = elem
+            .attributes()
+            .iter()
+            .find(|attr| attr.name.namespace.is_none() && attr.name.name.as_str() == PHX_UPDATE)
+            .and_then(|attr| attr.value.as_str());
+        match value {
+            Some("ignore") => Self::Ignore,
+            Some("append") => Self::Append,
+            Some("prepend") => Self::Prepend,
+            Some("stream") => Self::Stream,
+            _ => Self::Replace,
+        }
+    }
+}
+
+/// `rendered`, with every `ignore`, `append` and `prepend` container given
+/// what its counterpart in `doc` keeps. Borrowed if there is nothing to keep.
+pub(crate) fn preserve<'a>(doc: &Document, rendered: &'a Document) -> Cow<'a, Document> {
+    let live = containers(doc);
+    if live.is_empty() {
+        return Cow::Borrowed(rendered);
+    }
+    let mut preserved = Cow::Borrowed(rendered);
+    let mut stack = vec![rendered.root()];
+    while let Some(node) = stack.pop() {
+        let container = match preserved.get(node) {
+            dom::Node::Element(elem) => id_of(preserved.get(node))
+                .and_then(|id| live.get(id))
+                .map(|&target| (Mode::of(elem), target)),
+            _ => None,
+        };
+        match container {
+            Some((Mode::Ignore, target)) => {
+                ignore(doc, target, preserved.to_mut(), node);
+                // Everything below was copied from `doc` as is
+                continue;
+            }
+            Some((mode @ (Mode::Append | Mode::Prepend), target)) => {
+                extend(doc, target, preserved.to_mut(), node, mode);
+            }
+            _ => (),
+        }
+        stack.extend(preserved.children(node));
+    }
+    preserved
+}
+
+/// Every `ignore`, `append` and `prepend` container of `doc`, by DOM id
+fn containers(doc: &Document) -> HashMap<&str, NodeRef> {
+    let mut containers = HashMap::new();
+    let mut stack = vec![doc.root()];
+    while let Some(node) = stack.pop() {
+        if let dom::Node::Element(elem) = doc.get(node) {
+            let kept = matches!(Mode::of(elem), Mode::Ignore | Mode::Append | Mode::Prepend);
+            if let Some(id) = id_of(doc.get(node)).filter(|_| kept) {
+                containers.insert(id, node);
+            }
+        }
+        stack.extend(doc.children(node));
+    }
+    containers
+}
+
+/// Gives `node` the children and attributes of `target`, except for `data-*`
+/// attributes
+fn ignore(doc: &Document, target: NodeRef, rendered: &mut Document, node: NodeRef) {
+    if let (dom::Node::Element(kept), dom::Node::Element(elem)) =
+        (doc.get(target), rendered.get_mut(node))
+    {
+        let data = elem
+            .attributes
+            .drain(..)
+            .filter(is_data)
+            .collect::<Vec<_>>();
+        elem.attributes = kept
+            .attributes()
+            .iter()
+            .filter(|attr| !is_data(attr))
+            .cloned()
+            .chain(data)
+            .collect();
+    }
+    let children: Vec<NodeRef> = doc
+        .children(target)
+        .iter()
+        .map(|&child| copy(doc, child, rendered))
+        .collect();
+    replace_children(rendered, node, children);
+}
+
+/// Adds the children of `target` to those of `node`, after them for `append`
+/// and before them for `prepend`
+fn extend(doc: &Document, target: NodeRef, rendered: &mut Document, node: NodeRef, mode: Mode) {
+    let kept_ids: HashSet<&str> = doc
+        .children(target)
+        .iter()
+        .filter_map(|&child| id_of(doc.get(child)))
+        .collect();
+    let mut updates = HashMap::new();
+    // New children keep the order they were rendered in
+    let mut new = vec![];
+    for &child in rendered.children(node) {
+        match id_of(rendered.get(child)).filter(|id| kept_ids.contains(id)) {
+            Some(id) => {
+                updates.insert(id.to_owned(), child);
+            }
+            None => new.push(child),
+        }
+    }
+
+    let mut kept = Vec::with_capacity(doc.children(target).len());
+    for &child in doc.children(target) {
+        let updated = id_of(doc.get(child)).and_then(|id| updates.get(id));
+        kept.push(
+            updated
+                .copied()
+                .unwrap_or_else(|| copy(doc, child, rendered)),
+        );
+    }
+
+    let children = match mode {
+        Mode::Prepend => {
+            new.extend(kept);
+            new
+        }
+        _ => {
+            kept.extend(new);
+            kept
+        }
+    };
+    replace_children(rendered, node, children);
+}
+
+fn replace_children(doc: &mut Document, node: NodeRef, children: Vec<NodeRef>) {
+    for child in doc.children(node).to_vec() {
+        doc.detach(child);
+    }
+    for child in children {
+        doc.append_child(node, child);
+    }
+}
+
+fn is_data(attr: &dom::Attribute) -> bool {
+    attr.name.namespace.is_none() && attr.name.name.as_str().starts_with(DATA_PREFIX)
+}
+
+/// Copies `node` and everything below it from `from` into `to`, detached
+pub(crate) fn copy(from: &Document, node: NodeRef, to: &mut Document) -> NodeRef {
+    let copied = to.push_node(from.get(node).clone());
+    for &child in from.children(node) {
+        let child = copy(from, child, to);
+        to.append_child(copied, child);
+    }
+    copied
+}
+
+/// The DOM id of an element
+pub(crate) fn id_of(node: &dom::Node) -> Option<&str> {
+    let dom::Node::Element(elem) = node else {
+        return None;
+    };
+    elem.attributes()
+        .iter()
+        .find(|attr| attr.name.namespace.is_none() && attr.name.name.as_str() == "id")
+        .and_then(|attr| attr.value.as_str())
+}